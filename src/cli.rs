@@ -1,5 +1,5 @@
 use clap::{ArgAction, Args, Parser, Subcommand};
-use pixeldike::pixmap::Color;
+use pixeldike::pixmap::{Color, CoordinatePolicy, Tile};
 use std::path::PathBuf;
 use std::str::FromStr;
 use url::Url;
@@ -27,7 +27,7 @@ pub(crate) struct CliOpts {
 #[derive(Subcommand, Debug, Clone)]
 pub(crate) enum Command {
     /// Start a pixelflut server
-    Server(ServerOpts),
+    Server(Box<ServerOpts>),
     /// Run a pixelflut client to project a colored rectangle onto a servers pixmap
     PutRectangle(PutRectangleData),
     /// Upload an image to a pixelflut server
@@ -41,9 +41,22 @@ pub(crate) struct ServerOpts {
     /// Url on which to bind a server
     ///
     /// Valid protocols are "tcp://", "udp://" and "ws://".
+    /// Requests are parsed leniently unless "?mode=strict" is appended to the url.
+    /// The canvas which is served is selected by the url path (e.g. "tcp://0.0.0.0:1234/kids") or by appending
+    /// "?canvas=<name>", which also works for unix sockets. Without either, the "main" canvas is served.
+    /// Appending "?operator" reserves a listener for operators, who may use the WHO command to see what other clients
+    /// did. This is not supported for UDP.
     #[arg(long = "listen")]
     pub listen: Vec<Url>,
 
+    /// An additional canvas which is hosted next to the "main" one
+    ///
+    /// Given as "<name>[?<option>=<value>&...]" where the options are width, height, load-snapshot, map-file,
+    /// snapshot, rtmp-stream, rtsp-stream, fb-device, open-window, protect and protect-mask. They have the same meaning as the
+    /// flags which configure the main canvas but only width and height are inherited from them.
+    #[arg(long = "canvas")]
+    pub canvases: Vec<CanvasSpec>,
+
     /// width of the pixmap
     #[arg(short = 'x', long = "width", default_value = "800")]
     pub width: usize,
@@ -52,6 +65,56 @@ pub(crate) struct ServerOpts {
     #[arg(short = 'y', long = "height", default_value = "600")]
     pub height: usize,
 
+    /// How coordinates outside of the pixmap are treated
+    ///
+    /// Possible values: ["reject", "clip", "wrap"]
+    #[arg(long = "coordinate-policy", default_value = "reject")]
+    pub coordinate_policy: CoordinatePolicy,
+
+    /// Size of the square tiles in which changes to the pixmap are tracked
+    ///
+    /// Sinks only copy tiles which changed and skip frames in which nothing changed.
+    /// Set to 0 to disable tracking.
+    #[arg(long = "dirty-tile-size", default_value = "64")]
+    pub dirty_tile_size: usize,
+
+    /// Remember who last wrote each pixel and when
+    ///
+    /// This enables the WHO command on operator listeners which reports the address of the client that last wrote a
+    /// pixel. Writes which arrive via UDP are not attributed because their sender address can be spoofed.
+    #[arg(long = "attribution")]
+    pub attribution: bool,
+
+    /// Remember the previous colors of this many written pixels so that writes can be undone via the control socket
+    ///
+    /// Every remembered pixel takes up 32 bytes of memory.
+    /// Undoing the writes of single clients requires --attribution as well.
+    /// Set to 0 to disable the history.
+    #[arg(long = "history", default_value = "0")]
+    pub history_len: usize,
+
+    /// A rectangle given as "<x>,<y>,<width>,<height>" which clients are not allowed to draw on
+    ///
+    /// Writes which touch it are rejected with a PROTECTED error. May be given multiple times.
+    #[arg(long = "protect", value_parser = parse_rect)]
+    pub protect: Vec<Tile>,
+
+    /// An image whose pixels mark where clients are not allowed to draw
+    ///
+    /// The image is aligned with the top left corner of the canvas. All pixels which are neither black nor
+    /// transparent are protected.
+    #[arg(long = "protect-mask")]
+    pub protect_mask: Option<PathBuf>,
+
+    /// A unix socket through which the operator can manage the server while it is running
+    ///
+    /// It accepts commands like REWIND, UNDO and RESIZE, one per line. Send HELP for a list of all commands.
+    #[arg(long = "control-socket")]
+    pub control_socket: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub decay_opts: DecayOpts,
+
     #[command(flatten)]
     pub stream_opts: StreamOpts,
 
@@ -61,11 +124,188 @@ pub(crate) struct ServerOpts {
     #[command(flatten)]
     pub fb_opts: FramebufferOpts,
 
+    #[command(flatten)]
+    pub overlay_opts: OverlayOpts,
+
     #[cfg(feature = "windowing")]
     #[arg(long = "open-window")]
     pub open_window: bool,
 }
 
+impl ServerOpts {
+    /// The name of the canvas which is configured by the top-level flags
+    pub const MAIN_CANVAS: &'static str = "main";
+
+    /// Collect the configuration of all canvases which should be hosted
+    ///
+    /// Fails if more than one canvas has the same name, if a canvas is mapped onto the file into which its
+    /// snapshots are written or if a mapped canvas should fade.
+    pub fn canvas_opts(&self) -> Result<Vec<CanvasOpts>, String> {
+        let main = CanvasOpts {
+            name: Self::MAIN_CANVAS.to_string(),
+            width: self.width,
+            height: self.height,
+            load_snapshot: self.file_opts.load_snapshot.clone(),
+            map_file: self.file_opts.map_file.clone(),
+            snapshot_file: self.file_opts.snapshot_file.clone(),
+            rtmp_dst_addr: self.stream_opts.rtmp_dst_addr.clone(),
+            rtsp_dst_addr: self.stream_opts.rtsp_dst_addr.clone(),
+            fb_device: self.fb_opts.fb_device.clone(),
+            #[cfg(feature = "windowing")]
+            open_window: self.open_window,
+            protect: self.protect.clone(),
+            protect_mask: self.protect_mask.clone(),
+        };
+        let additional = self.canvases.iter().map(|spec| CanvasOpts {
+            name: spec.name.clone(),
+            width: spec.width.unwrap_or(self.width),
+            height: spec.height.unwrap_or(self.height),
+            load_snapshot: spec.load_snapshot.clone(),
+            map_file: spec.map_file.clone(),
+            snapshot_file: spec.snapshot_file.clone(),
+            rtmp_dst_addr: spec.rtmp_dst_addr.clone(),
+            rtsp_dst_addr: spec.rtsp_dst_addr.clone(),
+            fb_device: spec.fb_device.clone(),
+            #[cfg(feature = "windowing")]
+            open_window: spec.open_window,
+            protect: spec.protect.clone(),
+            protect_mask: spec.protect_mask.clone(),
+        });
+        let canvases: Vec<CanvasOpts> = std::iter::once(main).chain(additional).collect();
+        for (i, canvas) in canvases.iter().enumerate() {
+            if canvases[..i].iter().any(|other| other.name == canvas.name) {
+                return Err(format!("canvas {} is configured more than once", canvas.name));
+            }
+            if canvas.map_file.is_some() && canvas.map_file == canvas.snapshot_file {
+                return Err(format!(
+                    "canvas {} cannot write snapshots into the file it is mapped onto",
+                    canvas.name
+                ));
+            }
+            if canvas.map_file.is_some() && self.decay_opts.half_life_secs.is_some() {
+                return Err(format!(
+                    "canvas {} cannot fade because it is mapped onto a file",
+                    canvas.name
+                ));
+            }
+        }
+        Ok(canvases)
+    }
+}
+
+/// Everything that is configured individually for each canvas
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct CanvasOpts {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub load_snapshot: Option<PathBuf>,
+    pub map_file: Option<PathBuf>,
+    pub snapshot_file: Option<PathBuf>,
+    pub rtmp_dst_addr: Option<String>,
+    pub rtsp_dst_addr: Option<String>,
+    pub fb_device: Option<PathBuf>,
+    #[cfg(feature = "windowing")]
+    pub open_window: bool,
+    pub protect: Vec<Tile>,
+    pub protect_mask: Option<PathBuf>,
+}
+
+/// An additional canvas as it is given on the command line
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct CanvasSpec {
+    pub name: String,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub load_snapshot: Option<PathBuf>,
+    pub map_file: Option<PathBuf>,
+    pub snapshot_file: Option<PathBuf>,
+    pub rtmp_dst_addr: Option<String>,
+    pub rtsp_dst_addr: Option<String>,
+    pub fb_device: Option<PathBuf>,
+    pub open_window: bool,
+    pub protect: Vec<Tile>,
+    pub protect_mask: Option<PathBuf>,
+}
+
+impl FromStr for CanvasSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, options) = s.split_once('?').unwrap_or((s, ""));
+        if name.is_empty() || name.contains('/') {
+            return Err(format!("invalid canvas name '{name}'"));
+        }
+
+        let mut spec = CanvasSpec {
+            name: name.to_string(),
+            ..CanvasSpec::default()
+        };
+        let parse_size = |value: &str| {
+            value
+                .parse()
+                .map_err(|e| format!("invalid canvas size '{value}': {e}"))
+        };
+        for (key, value) in url::form_urlencoded::parse(options.as_bytes()) {
+            match key.as_ref() {
+                "width" => spec.width = Some(parse_size(&value)?),
+                "height" => spec.height = Some(parse_size(&value)?),
+                "load-snapshot" => spec.load_snapshot = Some(PathBuf::from(value.as_ref())),
+                "map-file" => spec.map_file = Some(PathBuf::from(value.as_ref())),
+                "snapshot" => spec.snapshot_file = Some(PathBuf::from(value.as_ref())),
+                "rtmp-stream" => spec.rtmp_dst_addr = Some(value.into_owned()),
+                "rtsp-stream" => spec.rtsp_dst_addr = Some(value.into_owned()),
+                "fb-device" => spec.fb_device = Some(PathBuf::from(value.as_ref())),
+                #[cfg(not(feature = "windowing"))]
+                "open-window" => return Err("open-window requires the windowing feature".to_string()),
+                #[cfg(feature = "windowing")]
+                "open-window" => {
+                    spec.open_window = value.is_empty()
+                        || value
+                            .parse()
+                            .map_err(|e| format!("invalid value for open-window '{value}': {e}"))?
+                }
+                "protect" => spec.protect.push(parse_rect(&value)?),
+                "protect-mask" => spec.protect_mask = Some(PathBuf::from(value.as_ref())),
+                _ => return Err(format!("unknown canvas option '{key}'")),
+            }
+        }
+        if spec.map_file.is_some() && spec.load_snapshot.is_some() {
+            return Err("map-file cannot be combined with load-snapshot".to_string());
+        }
+        Ok(spec)
+    }
+}
+
+/// Parse a rectangle that is given as "<x>,<y>,<width>,<height>"
+fn parse_rect(s: &str) -> Result<Tile, String> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|e| format!("invalid rectangle '{s}': {e}"))?;
+    match values[..] {
+        [x, y, width, height] => Ok(Tile { x, y, width, height }),
+        _ => Err(format!(
+            "invalid rectangle '{s}', expected <x>,<y>,<width>,<height>"
+        )),
+    }
+}
+
+/// Parse a position that is given as "<x>,<y>"
+fn parse_position(s: &str) -> Result<(usize, usize), String> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or(format!("invalid position '{s}', expected <x>,<y>"))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid position '{s}': {e}"))
+    };
+    Ok((parse(x)?, parse(y)?))
+}
+
 /// Specific options for sinking the pixmap data into something else (e.g. streaming it somewhere)
 #[derive(Args, Debug, Clone)]
 pub(crate) struct StreamOpts {
@@ -96,6 +336,14 @@ pub(crate) struct FileOpts {
     #[arg(long = "load-snapshot")]
     pub load_snapshot: Option<PathBuf>,
 
+    /// A file onto which the canvas is memory-mapped so that it always holds the current canvas
+    ///
+    /// The file uses the snapshot format and can be read by other processes while the server is running.
+    /// It is created if it does not exist, otherwise it must hold a canvas of the dimensions given via --width and
+    /// --height whose content is kept. Such a canvas can neither be resized nor fade via --decay-half-life.
+    #[arg(long = "map-file", conflicts_with = "load_snapshot")]
+    pub map_file: Option<PathBuf>,
+
     /// A path into which snapshots are stored
     #[arg(long = "snapshot")]
     pub snapshot_file: Option<PathBuf>,
@@ -105,6 +353,26 @@ pub(crate) struct FileOpts {
     pub snapshot_interval_secs: usize,
 }
 
+/// Specific options for letting the canvas fade away over time
+#[derive(Args, Debug, Clone)]
+pub(crate) struct DecayOpts {
+    /// Let pixels fade toward the background color, halving their distance to it every given number of seconds
+    ///
+    /// Together with --attribution, pixels which were written since the last fading step only fade by their age.
+    /// Canvases which are mapped onto a file cannot fade because writes to their pixels could not be told apart
+    /// from the fading reliably.
+    #[arg(long = "decay-half-life")]
+    pub half_life_secs: Option<f64>,
+
+    /// The color toward which pixels fade
+    #[arg(long = "decay-background", default_value = "000000")]
+    pub background: Color,
+
+    /// The interval in milliseconds with which pixels are faded
+    #[arg(long = "decay-interval", default_value = "200")]
+    pub interval_ms: u64,
+}
+
 /// Specific options for rendering onto a framebuffer
 #[derive(Args, Debug, Clone)]
 pub(crate) struct FramebufferOpts {
@@ -117,6 +385,43 @@ pub(crate) struct FramebufferOpts {
     pub fb_framerate: usize,
 }
 
+/// Specific options for drawing an overlay over every canvas in streams, windows and on framebuffers
+///
+/// Clients can neither see nor overwrite the overlay.
+#[derive(Args, Debug, Clone)]
+pub(crate) struct OverlayOpts {
+    /// An image which is drawn over the canvas
+    #[arg(long = "overlay-image")]
+    pub image: Option<PathBuf>,
+
+    /// The position of the top left corner of the overlay image given as "<x>,<y>"
+    #[arg(long = "overlay-image-position", default_value = "0,0", value_parser = parse_position)]
+    pub image_position: (usize, usize),
+
+    /// A line of text which is drawn over the canvas and the overlay image
+    ///
+    /// The placeholders {address}, {clients} and {pixels_per_sec} are replaced with the urls on which the canvas is
+    /// served, the number of connected clients and the number of pixels which clients write per second.
+    #[arg(long = "overlay-text")]
+    pub text: Option<String>,
+
+    /// The position of the top left corner of the overlay text given as "<x>,<y>"
+    #[arg(long = "overlay-text-position", default_value = "0,0", value_parser = parse_position)]
+    pub text_position: (usize, usize),
+
+    /// The height of the overlay text in pixels
+    #[arg(long = "overlay-text-size", default_value = "16")]
+    pub text_size: f32,
+
+    /// The color of the overlay text
+    #[arg(long = "overlay-text-color", default_value = "FFFFFF")]
+    pub text_color: Color,
+
+    /// The color of the box behind the overlay text
+    #[arg(long = "overlay-text-background", default_value = "00000080")]
+    pub text_background: Color,
+}
+
 /// Arguments common to all client commands
 #[derive(Args, Debug, Clone)]
 pub(crate) struct CommonClientOps {
@@ -151,7 +456,7 @@ pub(crate) struct PutRectangleData {
 
     /// The color which the rectangle should have.
     ///
-    /// Available values are 'random', 'random-per-iteration' or a specific hex encoded color.
+    /// Available values are 'random', 'random-per-iteration', a specific hex encoded color or a CSS color name.
     #[arg(long = "color", default_value = "random")]
     pub color: TargetColor,
 }
@@ -176,6 +481,8 @@ pub(crate) struct PutTextOpts {
     pub text: String,
 
     /// The color in which the text is rendered
+    ///
+    /// Available values are 'random', 'random-per-iteration', a specific hex encoded color or a CSS color name.
     #[arg(long = "color")]
     pub color: TargetColor,
 }
@@ -209,7 +516,7 @@ pub(crate) enum TargetColor {
 }
 
 impl FromStr for TargetColor {
-    type Err = <u32 as FromStr>::Err;
+    type Err = <Color as FromStr>::Err;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.eq_ignore_ascii_case("random") {
@@ -217,8 +524,102 @@ impl FromStr for TargetColor {
         } else if s.eq_ignore_ascii_case("random-per-iteration") {
             Ok(TargetColor::RandomPerIteration)
         } else {
-            let color = u32::from_str_radix(s, 16)?;
-            Ok(TargetColor::Specific(color.into()))
+            let color = Color::from_str(s)?;
+            Ok(TargetColor::Specific(color))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn server_opts(args: &[&str]) -> ServerOpts {
+        let opts = CliOpts::try_parse_from(["pixeldike", "server"].iter().chain(args)).unwrap();
+        match opts.command {
+            Command::Server(opts) => *opts,
+            command => panic!("parsed unexpected command {command:?}"),
         }
     }
+
+    #[test]
+    fn test_parse_canvas_spec() {
+        assert_eq!(
+            "kids".parse(),
+            Ok(CanvasSpec {
+                name: "kids".to_string(),
+                ..CanvasSpec::default()
+            })
+        );
+
+        let spec: CanvasSpec =
+            "kids?width=64&height=32&snapshot=%2Ftmp%2Fkids.pixmap&protect=0,0,8,8&protect=8,8,1,1"
+                .parse()
+                .unwrap();
+        assert_eq!(spec.name, "kids");
+        assert_eq!((spec.width, spec.height), (Some(64), Some(32)));
+        assert_eq!(spec.snapshot_file, Some(PathBuf::from("/tmp/kids.pixmap")));
+        assert_eq!(
+            spec.protect,
+            vec![
+                Tile {
+                    x: 0,
+                    y: 0,
+                    width: 8,
+                    height: 8
+                },
+                Tile {
+                    x: 8,
+                    y: 8,
+                    width: 1,
+                    height: 1
+                }
+            ]
+        );
+
+        assert!("".parse::<CanvasSpec>().is_err());
+        assert!("?width=5".parse::<CanvasSpec>().is_err());
+        assert!("a/b".parse::<CanvasSpec>().is_err());
+        assert!("kids?width=-1".parse::<CanvasSpec>().is_err());
+        assert!("kids?protect=1,2,3".parse::<CanvasSpec>().is_err());
+        assert!("kids?color=red".parse::<CanvasSpec>().is_err());
+        assert!("kids?map-file=a.pixmap&load-snapshot=b.pixmap"
+            .parse::<CanvasSpec>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_canvas_opts() {
+        let opts = server_opts(&["--width", "20", "--canvas", "kids?height=10"]);
+        let canvases = opts.canvas_opts().unwrap();
+        assert_eq!(canvases.len(), 2);
+        assert_eq!(canvases[0].name, ServerOpts::MAIN_CANVAS);
+        assert_eq!(
+            (&canvases[1].name[..], canvases[1].width, canvases[1].height),
+            ("kids", 20, 10)
+        );
+
+        // names must be unique, including the one of the main canvas
+        let opts = server_opts(&["--canvas", "kids", "--canvas", "kids?width=5"]);
+        assert!(opts.canvas_opts().is_err());
+        let opts = server_opts(&["--canvas", "main"]);
+        assert!(opts.canvas_opts().is_err());
+
+        // snapshots must not overwrite the file a canvas is mapped onto
+        let opts = server_opts(&["--map-file", "/tmp/main.pixmap", "--snapshot", "/tmp/main.pixmap"]);
+        assert!(opts.canvas_opts().is_err());
+        let opts = server_opts(&["--canvas", "kids?map-file=kids.pixmap&snapshot=kids.pixmap"]);
+        assert!(opts.canvas_opts().is_err());
+        let opts = server_opts(&[
+            "--map-file",
+            "/tmp/main.pixmap",
+            "--snapshot",
+            "/tmp/main-snapshot.pixmap",
+        ]);
+        assert!(opts.canvas_opts().is_ok());
+
+        // mapped canvases cannot fade
+        let opts = server_opts(&["--decay-half-life", "10", "--canvas", "kids?map-file=kids.pixmap"]);
+        assert!(opts.canvas_opts().is_err());
+    }
 }
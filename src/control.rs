@@ -0,0 +1,251 @@
+//! A unix socket through which the operator of a server manages its canvases while it is running
+//!
+//! The socket accepts one command per line and answers each of them with a response which starts with either
+//! `OK` or `ERR`.
+//! It is meant to be used interactively, e.g. with `socat - UNIX-CONNECT:<path>`, and access to it is only
+//! restricted by the permissions of the socket file.
+
+use pixeldike::pixmap::SharedPixmap;
+use pixeldike::DaemonResult;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::{AbortHandle, JoinSet};
+
+const HELP: &str = "OK commands are:\n\
+REWIND <seconds> [<canvas>]\t\t- Undo all writes of the last <seconds>\n\
+UNDO <identity> <seconds> [<canvas>]\t- Undo the writes which the client <identity> made in the last <seconds>\n\
+PROTECT <x> <y> <width> <height> [<canvas>]\t- Reject client writes to a rectangle\n\
+UNPROTECT [<canvas>]\t\t\t- Allow clients to write everywhere again\n\
+RESIZE <width> <height> [<canvas>]\t- Change the size of the canvas, keeping the pixels inside both sizes\n\
+HELP\t\t\t\t\t- This help message\n\
+Commands apply to the main canvas unless another one is given.\n";
+
+/// Start listening for operator commands on a unix socket at the given path
+pub(crate) fn start(
+    path: PathBuf,
+    canvases: HashMap<String, SharedPixmap>,
+    join_set: &mut JoinSet<DaemonResult>,
+) -> anyhow::Result<AbortHandle> {
+    remove_stale_socket(&path)?;
+    let listener = UnixListener::bind(&path)?;
+    tracing::info!("Started control socket on {}", path.display());
+
+    let canvases = Arc::new(canvases);
+    let handle = join_set.build_task().name("control_socket").spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let canvases = canvases.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &canvases).await {
+                    tracing::warn!("Got error while handling control socket connection: {e}");
+                }
+            });
+        }
+    })?;
+    Ok(handle)
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    canvases: &HashMap<String, SharedPixmap>,
+) -> anyhow::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match execute(&line, canvases) {
+            Ok(response) => response,
+            Err(e) => format!("ERR {e}\n"),
+        };
+        writer.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Remove a socket file which a previous run of the server left behind
+///
+/// Sockets on which somebody still accepts connections and files which are not sockets are left alone, so binding
+/// to them fails afterwards.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_err() {
+                tracing::debug!("Removing stale control socket {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+            Ok(())
+        }
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Execute one command line and return the response to it
+fn execute(line: &str, canvases: &HashMap<String, SharedPixmap>) -> Result<String, String> {
+    let canvas = |name: Option<&str>| {
+        let name = name.unwrap_or(crate::cli::ServerOpts::MAIN_CANVAS);
+        canvases.get(name).ok_or(format!("unknown canvas {name}"))
+    };
+    let since = |seconds: &str| {
+        let invalid = || format!("invalid number of seconds {seconds}");
+        let seconds = seconds.parse().map_err(|_| invalid())?;
+        SystemTime::now()
+            .checked_sub(Duration::from_secs(seconds))
+            .ok_or_else(invalid)
+    };
+    let number = |value: &str| value.parse().map_err(|_| format!("invalid number {value}"));
+
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    match tokens[..] {
+        [] => Err("empty command".to_string()),
+        [cmd] if cmd.eq_ignore_ascii_case("HELP") => Ok(HELP.to_string()),
+        [cmd, seconds] | [cmd, seconds, _] if cmd.eq_ignore_ascii_case("REWIND") => {
+            let pixmap = canvas(tokens.get(2).copied())?;
+            let undone = pixmap.rewind(since(seconds)?);
+            tracing::info!("Operator rewound {seconds} seconds, undoing {undone} changes");
+            Ok(format!("OK undid {undone} changes\n"))
+        }
+        [cmd, identity, seconds] | [cmd, identity, seconds, _] if cmd.eq_ignore_ascii_case("UNDO") => {
+            let pixmap = canvas(tokens.get(3).copied())?;
+            let writer = pixmap
+                .find_writer(identity)
+                .ok_or(format!("{identity} did not write anything"))?;
+            let undone = pixmap.undo_writes(writer, since(seconds)?);
+            tracing::info!(
+                "Operator undid the last {seconds} seconds of {identity}, undoing {undone} changes"
+            );
+            Ok(format!("OK undid {undone} changes\n"))
+        }
+        [cmd, x, y, width, height] | [cmd, x, y, width, height, _] if cmd.eq_ignore_ascii_case("PROTECT") => {
+            let pixmap = canvas(tokens.get(5).copied())?;
+            pixmap.protect_rect(number(x)?, number(y)?, number(width)?, number(height)?);
+            tracing::info!("Operator protected {width}x{height} pixels at {x},{y}");
+            Ok("OK\n".to_string())
+        }
+        [cmd] | [cmd, _] if cmd.eq_ignore_ascii_case("UNPROTECT") => {
+            canvas(tokens.get(1).copied())?.clear_protection();
+            tracing::info!("Operator removed all protected regions");
+            Ok("OK\n".to_string())
+        }
+        [cmd, width, height] | [cmd, width, height, _] if cmd.eq_ignore_ascii_case("RESIZE") => {
+            let pixmap = canvas(tokens.get(3).copied())?;
+            pixmap
+                .resize(number(width)?, number(height)?)
+                .map_err(|e| e.to_string())?;
+            tracing::info!("Operator resized the canvas to {width}x{height}");
+            Ok("OK\n".to_string())
+        }
+        _ => Err("unknown command, send HELP for a list of commands".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pixeldike::pixmap::{Color, Pixmap};
+
+    fn canvases() -> HashMap<String, SharedPixmap> {
+        let pixmap = Pixmap::new(20, 20).unwrap().with_attribution().with_history(100);
+        HashMap::from([
+            (crate::cli::ServerOpts::MAIN_CANVAS.to_string(), Arc::new(pixmap)),
+            ("kids".to_string(), Arc::new(Pixmap::new(20, 20).unwrap())),
+        ])
+    }
+
+    #[test]
+    fn test_execute() {
+        let canvases = canvases();
+        let main = &canvases[crate::cli::ServerOpts::MAIN_CANVAS];
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        let alice = main.register_writer("alice").unwrap();
+        main.fill_rect_as(0, 0, 2, 2, color, alice).unwrap();
+        main.set_pixel(5, 5, color).unwrap();
+
+        assert_eq!(execute("HELP", &canvases), Ok(HELP.to_string()));
+        assert_eq!(execute("help", &canvases), Ok(HELP.to_string()));
+        assert_eq!(
+            execute("UNDO alice 60", &canvases),
+            Ok("OK undid 4 changes\n".to_string())
+        );
+        assert_eq!(main.get_pixel(0, 0).unwrap(), Color::default());
+        assert_eq!(
+            execute("rewind 60 main", &canvases),
+            Ok("OK undid 1 changes\n".to_string())
+        );
+        assert_eq!(main.get_pixel(5, 5).unwrap(), Color::default());
+
+        assert_eq!(execute("PROTECT 1 2 3 4 kids", &canvases), Ok("OK\n".to_string()));
+        assert!(canvases["kids"].is_protected(3, 5, 1, 1));
+        assert!(!main.is_protected(3, 5, 1, 1));
+        assert_eq!(execute("UNPROTECT kids", &canvases), Ok("OK\n".to_string()));
+        assert!(!canvases["kids"].is_protected(3, 5, 1, 1));
+
+        canvases["kids"].set_pixel(1, 1, color).unwrap();
+        assert_eq!(execute("RESIZE 30 10 kids", &canvases), Ok("OK\n".to_string()));
+        assert_eq!(canvases["kids"].get_size(), (30, 10));
+        assert_eq!(canvases["kids"].get_pixel(1, 1).unwrap(), color);
+        assert_eq!(main.get_size(), (20, 20));
+    }
+
+    #[test]
+    fn test_execute_errors() {
+        let canvases = canvases();
+        let error = |line: &str| execute(line, &canvases).unwrap_err();
+
+        assert_eq!(error(""), "empty command");
+        assert_eq!(error("FOO"), "unknown command, send HELP for a list of commands");
+        assert_eq!(
+            error("HELP me"),
+            "unknown command, send HELP for a list of commands"
+        );
+        assert_eq!(
+            error("REWIND"),
+            "unknown command, send HELP for a list of commands"
+        );
+        assert_eq!(
+            error("REWIND 10 main extra"),
+            "unknown command, send HELP for a list of commands"
+        );
+        assert_eq!(error("REWIND ten"), "invalid number of seconds ten");
+        assert_eq!(error("REWIND -1"), "invalid number of seconds -1");
+        assert_eq!(
+            error("REWIND 18446744073709551615"),
+            "invalid number of seconds 18446744073709551615"
+        );
+        assert_eq!(error("REWIND 10 garden"), "unknown canvas garden");
+        assert_eq!(error("UNDO bob 10"), "bob did not write anything");
+        assert_eq!(error("PROTECT 1 2 3 x"), "invalid number x");
+        assert_eq!(error("RESIZE 10 -1"), "invalid number -1");
+        assert!(error("RESIZE 0 10").contains("not valid"));
+    }
+
+    #[test]
+    fn test_remove_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        remove_stale_socket(&path).unwrap();
+
+        // a socket which is still in use is kept
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        remove_stale_socket(&path).unwrap();
+        assert!(path.exists());
+
+        // once nobody listens anymore it is removed
+        drop(listener);
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        // other files are never removed
+        let file = dir.path().join("control.txt");
+        std::fs::write(&file, b"important").unwrap();
+        remove_stale_socket(&file).unwrap();
+        assert!(file.exists());
+    }
+}
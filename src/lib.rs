@@ -1,7 +1,6 @@
 #![feature(never_type)]
-#![feature(sync_unsafe_cell)]
-#![feature(int_roundings)]
-#![feature(test)]
+#![cfg_attr(feature = "unsafe-storage", feature(sync_unsafe_cell))]
+#![cfg_attr(test, feature(test))]
 #![deny(trivial_casts)]
 #![warn(
     rustdoc::missing_crate_level_docs,
@@ -1,13 +1,11 @@
-#![feature(never_type)]
-
 use ab_glyph::{Font, FontRef};
 use bytes::buf::Writer;
 use bytes::BytesMut;
 use clap::Parser;
-use image::imageops::FilterType;
 use rand::prelude::*;
+use std::collections::HashMap;
 use std::net::ToSocketAddrs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -21,23 +19,27 @@ use tracing_subscriber::util::SubscriberInitExt;
 use crate::cli::{CliOpts, TargetColor};
 use image::ImageReader;
 use itertools::Itertools;
-use pixeldike::net::protocol::Request;
+use pixeldike::net::protocol::{ParserMode, Request};
 use pixeldike::net::servers::{GenServer, TcpServer, TcpServerOptions, UnixSocketOptions, UnixSocketServer};
 #[cfg(feature = "udp")]
 use pixeldike::net::servers::{UdpServer, UdpServerOptions};
 #[cfg(feature = "ws")]
 use pixeldike::net::servers::{WsServer, WsServerOptions};
-use pixeldike::pixmap::{Color, Pixmap};
+use pixeldike::pixmap::{
+    Color, Decay, DecayOptions, Pixmap, ProtectionMask, ScaleFilter, Scaler, SharedPixmap,
+};
 use pixeldike::sinks::ffmpeg::{FfmpegOptions, FfmpegSink};
 use pixeldike::sinks::framebuffer::{FramebufferSink, FramebufferSinkOptions};
+use pixeldike::sinks::overlay::{
+    Overlay, OverlayImage, OverlayText, OverlayTextOptions, SharedOverlay, FONT_HERMIT_REGULAR,
+};
 use pixeldike::sinks::pixmap_file::{FileSink, FileSinkOptions};
 use pixeldike::DaemonResult;
 
 mod cli;
+mod control;
 mod main_utils;
 
-const FONT_HERMIT_REGULAR: &[u8] = include_bytes!("../resources/Hermit-Regular.otf");
-
 #[tokio::main]
 async fn main() {
     let args = cli::CliOpts::parse();
@@ -86,10 +88,159 @@ fn init_logger(args: &CliOpts) {
 }
 
 async fn start_server(opts: &cli::ServerOpts) {
-    // create a pixmap or load an existing snapshot
-    let pixmap = match &opts.file_opts.load_snapshot {
-        None => Arc::new(Pixmap::new(opts.width, opts.height).unwrap()),
-        Some(path) => {
+    let mut join_set: JoinSet<DaemonResult> = JoinSet::new();
+
+    // validate the configuration before anything is started
+    let all_canvas_opts = opts
+        .canvas_opts()
+        .unwrap_or_else(|e| panic!("Invalid canvas configuration: {}", e));
+    for url in &opts.listen {
+        let name = listener_canvas_name(url);
+        if !all_canvas_opts.iter().any(|canvas| canvas.name == name) {
+            panic!("Listen directive {} refers to unknown canvas {}", url, name);
+        }
+    }
+
+    // create all canvases together with their sinks
+    let mut canvases = HashMap::new();
+    for canvas_opts in all_canvas_opts {
+        let pixmap = start_canvas(opts, &canvas_opts, &mut join_set).await;
+        canvases.insert(canvas_opts.name.clone(), pixmap);
+    }
+
+    // configure and start all servers
+    for url in &opts.listen {
+        let parser_mode = listener_parser_mode(url);
+        let operator = listener_operator(url);
+        let pixmap = listener_canvas(url, &canvases);
+        match url.scheme() {
+            #[cfg(feature = "tcp")]
+            "tcp" => {
+                if !url.username().is_empty() {
+                    tracing::warn!(
+                        "{} listen directive specifies credentials which is not supported by the TCP server",
+                        url
+                    )
+                }
+                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1234))
+                    .to_socket_addrs()
+                    .expect("Could not resolve socket addr from listener url")
+                {
+                    TcpServer::new(TcpServerOptions {
+                        bind_addr,
+                        parser_mode,
+                        operator,
+                    })
+                    .start(pixmap.clone(), &mut join_set)
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not start tcp server on {}: {}", url, e));
+                }
+            }
+            "unix" => {
+                let path = PathBuf::from_str(url.path()).expect("Could not turn url path into system path");
+                UnixSocketServer::new(UnixSocketOptions {
+                    path,
+                    parser_mode,
+                    operator,
+                })
+                .start(pixmap.clone(), &mut join_set)
+                .await
+                .unwrap_or_else(|e| panic!("Could not start unix socket listener on {}: {}", url, e));
+            }
+            #[cfg(feature = "udp")]
+            "udp" => {
+                if operator {
+                    panic!(
+                        "{} listen directive cannot be reserved for operators because UDP senders are not verified",
+                        url
+                    );
+                }
+                if !url.username().is_empty() {
+                    tracing::info!("{}", url.authority());
+                    tracing::warn!(
+                        "{} listen directive specifies credentials which is not supported by the UDP server",
+                        url
+                    )
+                }
+                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1234))
+                    .to_socket_addrs()
+                    .expect("Could not resolve socket addr from listener url")
+                {
+                    UdpServer::new(UdpServerOptions {
+                        bind_addr,
+                        parser_mode,
+                    })
+                    .start(pixmap.clone(), &mut join_set)
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not start tcp server on {}: {}", url, e));
+                }
+            }
+            #[cfg(feature = "ws")]
+            "ws" => {
+                if !url.username().is_empty() {
+                    tracing::info!("{}", url.authority());
+                    tracing::warn!(
+                        "{} listen directive specifies credentials which is not supported by the WebSocket server",
+                        url
+                    )
+                }
+                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1235))
+                    .to_socket_addrs()
+                    .expect("Could not resolve socket addr from listener url")
+                {
+                    WsServer::new(WsServerOptions {
+                        bind_addr,
+                        parser_mode,
+                        operator,
+                    })
+                    .start(pixmap.clone(), &mut join_set)
+                    .await
+                    .unwrap_or_else(|e| panic!("Could not start tcp server on {}: {}", url, e));
+                }
+            }
+            proto => {
+                panic!("Unsupported server protocol {}", proto);
+            }
+        }
+    }
+
+    // configure the control socket
+    if let Some(path) = &opts.control_socket {
+        control::start(path.to_owned(), canvases.clone(), &mut join_set)
+            .unwrap_or_else(|e| panic!("Could not start control socket on {}: {}", path.display(), e));
+    }
+
+    // wait until one tasks exits
+    let result = join_set
+        .join_next()
+        .await
+        .expect("Nothing is supposed to be started which makes no sense. Review commandline flags.")
+        .expect("Could not join background task")
+        .unwrap_err();
+    tracing::error!("A background task exited unexpectedly: {}", result);
+
+    // cancel all other tasks
+    join_set.shutdown().await;
+}
+
+/// Create the pixmap of one canvas and start all of its sinks
+async fn start_canvas(
+    opts: &cli::ServerOpts,
+    canvas: &cli::CanvasOpts,
+    join_set: &mut JoinSet<DaemonResult>,
+) -> SharedPixmap {
+    // create a pixmap, map it onto a file or load an existing snapshot
+    let pixmap = match (&canvas.map_file, &canvas.load_snapshot) {
+        (Some(path), _) => Pixmap::map_file(path, canvas.width, canvas.height).unwrap_or_else(|e| {
+            panic!(
+                "Could not map canvas {} onto {}: {}",
+                canvas.name,
+                path.display(),
+                e
+            )
+        }),
+        (None, None) => Pixmap::new(canvas.width, canvas.height).unwrap(),
+        (None, Some(path)) => {
             let loaded_pixmap = pixeldike::sinks::pixmap_file::load_pixmap_file(path).await;
             match loaded_pixmap {
                 Err(e) => {
@@ -98,29 +249,72 @@ async fn start_server(opts: &cli::ServerOpts) {
                         path.display(),
                         e
                     );
-                    Arc::new(Pixmap::new(opts.width, opts.height).unwrap())
+                    Pixmap::new(canvas.width, canvas.height).unwrap()
                 }
                 Ok(loaded_pixmap) => {
                     let (width, height) = loaded_pixmap.get_size();
-                    if width != opts.width || height != opts.height {
+                    if width != canvas.width || height != canvas.height {
                         tracing::warn!(
                     "Stored snapshot has different dimensions than {}x{}, creating an empty pixmap instead",
-                    opts.width,
-                    opts.height
+                    canvas.width,
+                    canvas.height
                 );
-                        Arc::new(Pixmap::new(opts.width, opts.height).unwrap())
+                        Pixmap::new(canvas.width, canvas.height).unwrap()
                     } else {
-                        Arc::new(loaded_pixmap)
+                        loaded_pixmap
                     }
                 }
             }
         }
     };
+    let mut pixmap = pixmap.with_coordinate_policy(opts.coordinate_policy);
+    if opts.dirty_tile_size > 0 {
+        pixmap = pixmap.with_dirty_tracking(opts.dirty_tile_size);
+    }
+    if opts.attribution {
+        pixmap = pixmap.with_attribution();
+    }
+    if opts.history_len > 0 {
+        pixmap = pixmap.with_history(opts.history_len);
+    }
+    let pixmap = Arc::new(pixmap);
 
-    let mut join_set: JoinSet<DaemonResult> = JoinSet::new();
+    // configure protected regions
+    for rect in &canvas.protect {
+        pixmap.protect_rect(rect.x, rect.y, rect.width, rect.height);
+    }
+    if let Some(path) = &canvas.protect_mask {
+        let mask = load_protection_mask(path).expect("Could not load protection mask");
+        tracing::info!(
+            "Protecting {} pixels of canvas {} from clients",
+            mask.protected_pixels(),
+            canvas.name
+        );
+        pixmap.set_protection_mask(Some(mask));
+    }
+
+    // configure decay
+    if let Some(half_life_secs) = opts.decay_opts.half_life_secs {
+        let pixmap = pixmap.clone();
+        let decay = Decay::new(
+            DecayOptions {
+                half_life: Duration::try_from_secs_f64(half_life_secs)
+                    .ok()
+                    .filter(|half_life| !half_life.is_zero())
+                    .expect("Decay half-life must be a positive number of seconds"),
+                background: opts.decay_opts.background,
+                interval: interval(Duration::from_millis(opts.decay_opts.interval_ms)),
+            },
+            pixmap,
+        );
+        decay.start(join_set).expect("Could not start decay task");
+    }
+
+    // configure overlay
+    let overlay = start_overlay(opts, canvas, &pixmap, join_set);
 
     // configure snapshotting
-    if let Some(path) = &opts.file_opts.snapshot_file {
+    if let Some(path) = &canvas.snapshot_file {
         let pixmap = pixmap.clone();
         let sink = FileSink::new(
             FileSinkOptions {
@@ -129,30 +323,30 @@ async fn start_server(opts: &cli::ServerOpts) {
             },
             pixmap,
         );
-        sink.start(&mut join_set)
+        sink.start(join_set)
             .await
             .expect("Could not start persistence task");
     }
 
     // configure gui window
     #[cfg(feature = "windowing")]
-    if opts.open_window {
+    if canvas.open_window {
         let pixmap = pixmap.clone();
-        pixeldike::sinks::window::start(&mut join_set, pixmap)
+        pixeldike::sinks::window::start(join_set, pixmap, overlay.clone())
             .expect("Could not open window for live rendering");
     }
 
     // configure streaming sink
-    if opts.stream_opts.rtmp_dst_addr.is_some() || opts.stream_opts.rtsp_dst_addr.is_some() {
+    if canvas.rtmp_dst_addr.is_some() || canvas.rtsp_dst_addr.is_some() {
         // construct output spec depending on cli options
         let mut output_spec = Vec::new();
-        if let Some(rtsp_dst_addr) = &opts.stream_opts.rtsp_dst_addr {
+        if let Some(rtsp_dst_addr) = &canvas.rtsp_dst_addr {
             output_spec.append(&mut FfmpegOptions::make_rtsp_out_spec(
                 rtsp_dst_addr,
                 opts.stream_opts.framerate,
             ));
         }
-        if let Some(rtmp_dst_addr) = &opts.stream_opts.rtmp_dst_addr {
+        if let Some(rtmp_dst_addr) = &canvas.rtmp_dst_addr {
             output_spec.append(&mut FfmpegOptions::make_rtmp_out_spec(
                 rtmp_dst_addr,
                 opts.stream_opts.framerate,
@@ -161,7 +355,7 @@ async fn start_server(opts: &cli::ServerOpts) {
 
         // start the ffmpeg subprocess
         let pixmap = pixmap.clone();
-        let ffmpeg = FfmpegSink::new(
+        let mut ffmpeg = FfmpegSink::new(
             FfmpegOptions {
                 framerate: opts.stream_opts.framerate,
                 synthesize_audio: true,
@@ -170,132 +364,162 @@ async fn start_server(opts: &cli::ServerOpts) {
             },
             pixmap,
         );
-        ffmpeg
-            .start(&mut join_set)
-            .await
-            .expect("Could not start ffmpeg sink");
+        if let Some(overlay) = &overlay {
+            ffmpeg = ffmpeg.with_overlay(overlay.clone());
+        }
+        ffmpeg.start(join_set).await.expect("Could not start ffmpeg sink");
     }
 
     // configure framebuffer sink
-    if let Some(fb_device) = &opts.fb_opts.fb_device {
+    if let Some(fb_device) = &canvas.fb_device {
         let pixmap = pixmap.clone();
-        let sink = FramebufferSink::new(
+        let mut sink = FramebufferSink::new(
             FramebufferSinkOptions {
                 path: fb_device.to_owned(),
                 framerate: opts.fb_opts.fb_framerate,
             },
             pixmap,
         );
-        sink.start(&mut join_set)
+        if let Some(overlay) = &overlay {
+            sink = sink.with_overlay(overlay.clone());
+        }
+        sink.start(join_set)
             .await
             .expect("Coult not start task for framebuffer rendering");
     }
 
-    // configure and start all servers
-    for url in &opts.listen {
-        match url.scheme() {
-            #[cfg(feature = "tcp")]
-            "tcp" => {
-                if !url.username().is_empty() {
-                    tracing::warn!(
-                        "{} listen directive specifies credentials which is not supported by the TCP server",
-                        url
-                    )
-                }
-                if !url.path().is_empty() {
-                    tracing::warn!(
-                        "{} listen directive specifies a path which is not supported by the TCP server",
-                        url
-                    );
-                }
-                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1234))
-                    .to_socket_addrs()
-                    .expect("Could not resolve socket addr from listener url")
-                {
-                    TcpServer::new(TcpServerOptions { bind_addr })
-                        .start(pixmap.clone(), &mut join_set)
-                        .await
-                        .expect(&format!("Could not start tcp server on {}", url));
-                }
-            }
-            "unix" => {
-                let path = PathBuf::from_str(url.path()).expect("Could not turn url path into system path");
-                UnixSocketServer::new(UnixSocketOptions { path })
-                    .start(pixmap.clone(), &mut join_set)
-                    .await
-                    .expect(&format!("Could not start unix socket listener on {}", url));
-            }
-            #[cfg(feature = "udp")]
-            "udp" => {
-                if !url.username().is_empty() {
-                    tracing::info!("{}", url.authority());
-                    tracing::warn!(
-                        "{} listen directive specifies credentials which is not supported by the UDP server",
-                        url
-                    )
-                }
-                if !url.path().is_empty() {
-                    tracing::warn!(
-                        "{} listen directive specifies a path which is not supported by the UDP server",
-                        url
-                    );
-                }
-                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1234))
-                    .to_socket_addrs()
-                    .expect("Could not resolve socket addr from listener url")
-                {
-                    UdpServer::new(UdpServerOptions { bind_addr })
-                        .start(pixmap.clone(), &mut join_set)
-                        .await
-                        .expect(&format!("Could not start tcp server on {}", url));
-                }
-            }
-            #[cfg(feature = "ws")]
-            "ws" => {
-                if !url.username().is_empty() {
-                    tracing::info!("{}", url.authority());
-                    tracing::warn!(
-                        "{} listen directive specifies credentials which is not supported by the WebSocket server",
-                        url
-                    )
-                }
-                if url.path() != "/" {
-                    tracing::warn!(
-                        "{} listen directive specifies a path which is not supported by the WebSocket server. The WebSocket is instead available on all paths.",
-                        url
-                    );
-                }
+    pixmap
+}
 
-                for bind_addr in (url.host_str().unwrap(), url.port().unwrap_or(1235))
-                    .to_socket_addrs()
-                    .expect("Could not resolve socket addr from listener url")
-                {
-                    WsServer::new(WsServerOptions { bind_addr })
-                        .start(pixmap.clone(), &mut join_set)
-                        .await
-                        .expect(&format!("Could not start tcp server on {}", url));
-                }
-            }
-            proto => {
-                panic!("Unsupported server protocol {}", proto);
-            }
-        }
+/// Create the overlay of one canvas if one is configured and start updating its text
+fn start_overlay(
+    opts: &cli::ServerOpts,
+    canvas: &cli::CanvasOpts,
+    pixmap: &SharedPixmap,
+    join_set: &mut JoinSet<DaemonResult>,
+) -> Option<SharedOverlay> {
+    let overlay_opts = &opts.overlay_opts;
+    if overlay_opts.image.is_none() && overlay_opts.text.is_none() {
+        return None;
     }
+    let overlay = Arc::new(Overlay::new());
 
-    // wait until one tasks exits
-    let result = join_set
-        .join_next()
-        .await
-        .expect("Nothing is supposed to be started which makes no sense. Review commandline flags.")
-        .expect("Could not join background task")
-        .unwrap_err();
-    tracing::error!("A background task exited unexpectedly: {}", result);
+    if let Some(path) = &overlay_opts.image {
+        let img = ImageReader::open(path)
+            .expect("Could not open overlay image")
+            .decode()
+            .expect("Could not decode overlay image")
+            .to_rgba8();
+        let (x, y) = overlay_opts.image_position;
+        overlay.set_image(Some(OverlayImage::new(
+            x,
+            y,
+            img.width() as usize,
+            img.height() as usize,
+            img.pixels().map(|px| Color::from(px.0)).collect(),
+        )));
+    }
 
-    // cancel all other tasks
-    join_set.shutdown().await;
+    if let Some(template) = &overlay_opts.text {
+        let address = opts
+            .listen
+            .iter()
+            .filter(|url| listener_canvas_name(url) == canvas.name)
+            .map(|url| {
+                let mut url = url.clone();
+                url.set_query(None);
+                url.to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let text = OverlayText::new(
+            OverlayTextOptions {
+                template: template.clone(),
+                address,
+                position: overlay_opts.text_position,
+                size: overlay_opts.text_size,
+                color: overlay_opts.text_color,
+                background: overlay_opts.text_background,
+                interval: interval(Duration::from_secs(1)),
+            },
+            pixmap.clone(),
+            overlay.clone(),
+        );
+        text.start(join_set).expect("Could not start overlay text task");
+    }
+
+    Some(overlay)
+}
+
+/// Load an image in which all pixels that are neither black nor transparent mark protected regions
+fn load_protection_mask(path: &Path) -> anyhow::Result<ProtectionMask> {
+    let img = ImageReader::open(path)?.decode()?.to_rgba8();
+    let protected = img.pixels().map(|px| {
+        let [r, g, b, a] = px.0;
+        a != 0 && (r, g, b) != (0, 0, 0)
+    });
+    Ok(ProtectionMask::new(
+        img.width() as usize,
+        img.height() as usize,
+        protected.collect(),
+    ))
+}
+
+/// Determine the name of the canvas which a listener serves
+///
+/// The canvas is named by the `canvas` query parameter or, except for unix sockets whose path is the socket itself,
+/// by the path of the url.
+fn listener_canvas_name(url: &url::Url) -> String {
+    let name = match url.query_pairs().find(|(key, _)| key == "canvas") {
+        Some((_, name)) => name.into_owned(),
+        None if url.scheme() != "unix" => url.path().trim_matches('/').to_string(),
+        None => String::new(),
+    };
+    if name.is_empty() {
+        cli::ServerOpts::MAIN_CANVAS.to_string()
+    } else {
+        name
+    }
+}
+
+/// Look up the canvas which a listener serves
+fn listener_canvas<'a>(url: &url::Url, canvases: &'a HashMap<String, SharedPixmap>) -> &'a SharedPixmap {
+    let name = listener_canvas_name(url);
+    canvases
+        .get(&name)
+        .unwrap_or_else(|| panic!("Listen directive {} refers to unknown canvas {}", url, name))
+}
+
+/// Determine how strictly a listener parses requests from the `mode` query parameter of its url
+fn listener_parser_mode(url: &url::Url) -> ParserMode {
+    match url.query_pairs().find(|(key, _)| key == "mode") {
+        None => ParserMode::default(),
+        Some((_, mode)) => mode
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid parser mode in listen directive {}: {}", url, e)),
+    }
+}
+
+/// Determine whether a listener is reserved for operators from the `operator` query parameter of its url
+///
+/// The parameter may be given without a value.
+fn listener_operator(url: &url::Url) -> bool {
+    match url.query_pairs().find(|(key, _)| key == "operator") {
+        None => false,
+        Some((_, value)) if value.is_empty() => true,
+        Some((_, value)) => value
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid operator flag in listen directive {}: {}", url, e)),
+    }
 }
 
 async fn put_rectangle(opts: &cli::PutRectangleData) {
+    let mut client = main_utils::DynClient::connect(&opts.common.server)
+        .await
+        .expect("Could not connect to pixelflut server");
+    let features = client.features().await;
+    let use_rect = features.supports_command("RECT");
+
     // define how a request buffer is filled
     let fill_buf = |buf: &mut Writer<BytesMut>, x_min: usize, x_max: usize, y_min: usize, y_max: usize| {
         // select a color
@@ -306,19 +530,33 @@ async fn put_rectangle(opts: &cli::PutRectangleData) {
             TargetColor::Specific(c) => c,
         };
 
+        // let the server fill the whole rectangle at once if it is able to
+        if use_rect {
+            tracing::debug!(
+                "Filling command-buffer to fill #{color:X} from {x_min},{y_min} to {x_max},{y_max}"
+            );
+            let request = Request::FillRect {
+                x: x_min,
+                y: y_min,
+                width: x_max - x_min,
+                height: y_max - y_min,
+                color,
+            };
+            main_utils::write_request(buf, request, &features);
+            return;
+        }
+
         // accumulate color commands into one large buffer buffer
         tracing::debug!("Filling command-buffer to draw #{color:X} from {x_min},{y_min} to {x_max},{y_max}");
         let mut coords = (x_min..x_max).cartesian_product(y_min..y_max).collect::<Vec<_>>();
         coords.shuffle(&mut thread_rng());
         for (x, y) in coords {
-            Request::SetPixel { x, y, color }.write(buf).unwrap();
+            main_utils::write_request(buf, Request::SetPixel { x, y, color }, &features);
         }
     };
 
     // run main client loop
-    main_utils::DynClient::connect(&opts.common.server)
-        .await
-        .expect("Could not connect to pixelflut server")
+    client
         .run_loop(
             fill_buf,
             &opts.common,
@@ -328,6 +566,11 @@ async fn put_rectangle(opts: &cli::PutRectangleData) {
 }
 
 async fn put_image(opts: &cli::PutImageData) {
+    let mut client = main_utils::DynClient::connect(&opts.common.server)
+        .await
+        .expect("Could not connect to pixelflut server");
+    let features = client.features().await;
+
     // define how a request buffer is filled
     let fill_buf = |buf: &mut Writer<BytesMut>, x_min: usize, x_max: usize, y_min: usize, y_max: usize| {
         tracing::debug!("Opening image at {}", &opts.path.display());
@@ -338,38 +581,38 @@ async fn put_image(opts: &cli::PutImageData) {
             .to_rgb8();
 
         tracing::debug!("Resizing image to dimensions {}x{}", x_max - x_min, y_max - y_min);
-        let img = image::imageops::resize(
-            &img,
-            (x_max - x_min) as u32,
-            (y_max - y_min) as u32,
-            FilterType::Triangle,
-        );
+        let (width, height) = (x_max - x_min, y_max - y_min);
+        let colors = img.pixels().map(|px| Color::from(px.0)).collect::<Vec<_>>();
+        let mut scaled = Vec::new();
+        // averaging over all source pixels keeps large downscales from aliasing
+        Scaler::new(
+            img.width() as usize,
+            img.height() as usize,
+            width,
+            height,
+            ScaleFilter::Area,
+        )
+        .scale(&colors, &mut scaled);
 
         // accumulate color commands into one large buffer buffer
         tracing::debug!("Converting image to pixelflut commands");
         let mut coords = (x_min..x_max).cartesian_product(y_min..y_max).collect::<Vec<_>>();
         coords.shuffle(&mut thread_rng());
         for (x, y) in coords {
-            let color = img.get_pixel(x as u32, y as u32);
-            Request::SetPixel {
-                x,
-                y,
-                color: color.0.into(),
-            }
-            .write(buf)
-            .unwrap();
+            let color = scaled[(y - y_min) * width + (x - x_min)];
+            main_utils::write_request(buf, Request::SetPixel { x, y, color }, &features);
         }
     };
 
     // run main client loop
-    main_utils::DynClient::connect(&opts.common.server)
-        .await
-        .expect("Could not connect to pixelflut server")
-        .run_loop(fill_buf, &opts.common, false)
-        .await;
+    client.run_loop(fill_buf, &opts.common, false).await;
 }
 
 async fn put_text(opts: &cli::PutTextOpts) {
+    let mut client = main_utils::DynClient::connect(&opts.common.server)
+        .await
+        .expect("Could not connect to pixelflut server");
+    let features = client.features().await;
     let font = FontRef::try_from_slice(FONT_HERMIT_REGULAR).unwrap();
 
     // define how a request buffer is filled
@@ -404,13 +647,12 @@ async fn put_text(opts: &cli::PutTextOpts) {
             if let Some(outline) = font.outline_glyph(glyph) {
                 outline.draw(|x, y, coverage| {
                     if coverage >= 0.5 {
-                        Request::SetPixel {
+                        let request = Request::SetPixel {
                             x: x_min + (x as usize + i * glyph_width),
                             y: y_min + (y as usize),
                             color,
-                        }
-                        .write(buf)
-                        .unwrap();
+                        };
+                        main_utils::write_request(buf, request, &features);
                     }
                 });
             }
@@ -418,9 +660,7 @@ async fn put_text(opts: &cli::PutTextOpts) {
     };
 
     // run main client loop
-    main_utils::DynClient::connect(&opts.common.server)
-        .await
-        .expect("Could not connect to pixelflut server")
+    client
         .run_loop(
             fill_buf,
             &opts.common,
@@ -428,3 +668,53 @@ async fn put_text(opts: &cli::PutTextOpts) {
         )
         .await;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_listener_canvas_name() {
+        let name = |url: &str| listener_canvas_name(&url.parse().unwrap());
+        assert_eq!(name("tcp://0.0.0.0:1234"), "main");
+        assert_eq!(name("tcp://0.0.0.0:1234/"), "main");
+        assert_eq!(name("tcp://0.0.0.0:1234/kids"), "kids");
+        assert_eq!(name("tcp://0.0.0.0:1234/?canvas=kids&mode=strict"), "kids");
+        assert_eq!(name("ws://0.0.0.0:1235/main?canvas=kids"), "kids");
+        assert_eq!(name("unix:///run/pixelflut.sock"), "main");
+        assert_eq!(name("unix:///run/pixelflut.sock?canvas=kids"), "kids");
+    }
+
+    #[test]
+    fn test_listener_parser_mode() {
+        let mode = |url: &str| listener_parser_mode(&url.parse().unwrap());
+        assert_eq!(mode("tcp://0.0.0.0:1234"), ParserMode::default());
+        assert_eq!(mode("tcp://0.0.0.0:1234/?mode=strict"), ParserMode::Strict);
+        assert_eq!(mode("udp://0.0.0.0:1234/kids?mode=lenient"), ParserMode::Lenient);
+    }
+
+    #[test]
+    fn test_load_protection_mask() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mask.png");
+        let pixels = [[0, 0, 0, 255], [1, 0, 0, 255], [0, 0, 1, 255], [255, 255, 255, 0]];
+        image::RgbaImage::from_fn(4, 1, |x, _| image::Rgba(pixels[x as usize]))
+            .save(&path)
+            .unwrap();
+
+        // dark colors are protected as well, only black and transparent pixels are not
+        let mask = load_protection_mask(&path).unwrap();
+        assert_eq!(mask, ProtectionMask::new(4, 1, vec![false, true, true, false]));
+    }
+
+    #[test]
+    fn test_listener_operator() {
+        let operator = |url: &str| listener_operator(&url.parse().unwrap());
+        assert!(!operator("tcp://0.0.0.0:1234"));
+        assert!(operator("tcp://127.0.0.1:1236?operator"));
+        assert!(operator(
+            "unix:///run/pixelflut-admin.sock?mode=strict&operator=true"
+        ));
+        assert!(!operator("tcp://0.0.0.0:1234/kids?operator=false"));
+    }
+}
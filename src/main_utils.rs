@@ -3,11 +3,21 @@ use crate::cli::TargetDimension;
 use bytes::buf::Writer;
 use bytes::{BufMut, BytesMut};
 use pixeldike::net::clients::{TcpClient, UdpClient, UnixSocketClient};
-use pixeldike::net::protocol::{Request, Response};
+use pixeldike::net::protocol::{Features, Request, Response};
+use std::io::Write;
 use std::path::PathBuf;
 use tokio::io::AsyncWriteExt;
 use url::Url;
 
+/// Write a request into the buffer using the most efficient encoding that the server supports
+pub fn write_request(buf: &mut impl Write, request: Request, features: &Features) {
+    if features.supports_command("PB") {
+        request.write_binary(buf).unwrap();
+    } else {
+        request.write(buf).unwrap();
+    }
+}
+
 pub enum DynClient {
     Tcp(TcpClient),
     Udp(UdpClient),
@@ -66,6 +76,27 @@ impl DynClient {
         }
     }
 
+    async fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DynClient::Tcp(tcp) => tcp.flush().await,
+            DynClient::Udp(_) => Ok(()),
+            DynClient::Unix(unix) => unix.flush().await,
+        }
+    }
+
+    /// Query the capabilities of the connected server
+    ///
+    /// The underlying client caches the result and uses it to pick the most efficient request encoding.
+    pub async fn features(&mut self) -> Features {
+        let features = match self {
+            DynClient::Tcp(tcp) => tcp.features().await.clone(),
+            DynClient::Udp(udp) => udp.features().await.clone(),
+            DynClient::Unix(unix) => unix.features().await.clone(),
+        };
+        tracing::info!("Server supports the optional commands {:?}", features.commands);
+        features
+    }
+
     /// Run a generic client loop that fills its command buffer from the provided function.
     ///
     /// `fill_buf` should be a function that fills the provided buffer with pixelflut commands.
@@ -110,6 +141,7 @@ impl DynClient {
 
             // abort loop if only one iteration is requested
             if !opts.do_loop {
+                self.flush().await.expect("Could not flush commands to server");
                 break;
             }
 
@@ -11,3 +11,75 @@ pub use tcp_client::TcpClient;
 #[cfg(feature = "udp")]
 pub use udp_client::UdpClient;
 pub use unix_socket_client::UnixSocketClient;
+
+use crate::net::protocol::{Features, FrameError, Response, ResponseCodec};
+use anyhow::anyhow;
+use bytes::BytesMut;
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::Decoder;
+
+/// How long clients wait for the response to a *FEATURES* request before giving up on it
+const FEATURES_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Wait for the response of a server to a *FEATURES* request and interpret it
+///
+/// Servers which predate the command respond with an error line or, like servers which ignore unknown commands
+/// and UDP servers whose response got lost, not at all.
+/// All of this is interpreted as the server not supporting any optional features so that clients fall back to the
+/// plain protocol.
+async fn features_from_response(response: impl Future<Output = anyhow::Result<Response>>) -> Features {
+    match tokio::time::timeout(FEATURES_TIMEOUT, response).await {
+        Ok(Ok(Response::Features(features))) => features,
+        Ok(Ok(_)) => Features::default(),
+        Ok(Err(e)) => {
+            if !matches!(e.downcast_ref(), Some(FrameError::Parse(_))) {
+                tracing::warn!("Could not query features of pixelflut server: {e}");
+            }
+            Features::default()
+        }
+        Err(_) => {
+            tracing::warn!("Pixelflut server did not answer the FEATURES request, assuming it supports none");
+            Features::default()
+        }
+    }
+}
+
+/// Read from a stream until the codec yields one complete response
+///
+/// Data that is received beyond that response stays in `buf` for the next call.
+async fn read_response(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut BytesMut,
+    codec: &mut ResponseCodec,
+) -> anyhow::Result<Response> {
+    loop {
+        if let Some(frame) = codec.decode(buf)? {
+            return Ok(frame?);
+        }
+        if reader.read_buf(buf).await? == 0 {
+            let frame = codec
+                .decode_eof(buf)?
+                .ok_or_else(|| anyhow!("server closed the connection"))?;
+            return Ok(frame?);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "udp"))]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+
+    #[tokio::test]
+    async fn test_features_timeout() {
+        // like the servers which predate the command, this one never answers
+        let server = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let mut client = UdpClient::connect(&server.local_addr().unwrap()).await.unwrap();
+        assert_eq!(client.features().await, &Features::default());
+    }
+}
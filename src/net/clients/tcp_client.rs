@@ -1,14 +1,18 @@
-use crate::net::protocol::{parse_response_str, Request, Response};
+use crate::net::protocol::{Features, Request, Response, ResponseCodec};
+use bytes::BytesMut;
 use std::net::SocketAddr;
-use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 
 /// A pixelflut client that uses TCP and buffered read/write for communication with a pixelflut server.
 #[derive(Debug)]
 pub struct TcpClient {
-    reader: BufReader<OwnedReadHalf>,
+    reader: OwnedReadHalf,
+    read_buf: BytesMut,
+    codec: ResponseCodec,
     writer: BufWriter<OwnedWriteHalf>,
+    features: Option<Features>,
 }
 
 impl TcpClient {
@@ -16,8 +20,11 @@ impl TcpClient {
     pub async fn connect(addr: &SocketAddr) -> std::io::Result<Self> {
         let (reader, writer) = TcpStream::connect(addr).await?.into_split();
         Ok(Self {
-            reader: BufReader::new(reader),
+            reader,
+            read_buf: BytesMut::with_capacity(8 * 1024),
+            codec: ResponseCodec::default(),
             writer: BufWriter::new(writer),
+            features: None,
         })
     }
 
@@ -26,15 +33,17 @@ impl TcpClient {
     /// Note that because the TCP-Client uses buffered IO, your request may not be sent immediately.
     /// Use either `flush()` or `exchange()` appropriately.
     pub async fn send_request(&mut self, request: Request) -> std::io::Result<()> {
-        request.write_async(&mut self.writer).await
+        match &self.features {
+            Some(features) if features.supports_command("PB") => {
+                request.write_binary_async(&mut self.writer).await
+            }
+            _ => request.write_async(&mut self.writer).await,
+        }
     }
 
     /// Wait for the connected server to send a response
     pub async fn await_response(&mut self) -> anyhow::Result<Response> {
-        let mut buf = String::with_capacity(32);
-        self.reader.read_line(&mut buf).await?;
-        let response = parse_response_str(&buf)?;
-        Ok(response)
+        super::read_response(&mut self.reader, &mut self.read_buf, &mut self.codec).await
     }
 
     /// Send a single request to the connected server and wait for a response
@@ -47,6 +56,19 @@ impl TcpClient {
         Ok(response)
     }
 
+    /// Query the capabilities of the connected server
+    ///
+    /// The result is cached so that the server is only queried once.
+    /// Afterwards, requests are automatically sent in the most efficient encoding that the server supports.
+    /// Servers which do not answer in time are assumed to support no optional features at all.
+    pub async fn features(&mut self) -> &Features {
+        if self.features.is_none() {
+            let features = super::features_from_response(self.exchange(Request::GetFeatures)).await;
+            self.features = Some(features);
+        }
+        self.features.as_ref().unwrap()
+    }
+
     /// Flush the write buffer to immediately send all enqueued requests to the server.
     pub async fn flush(&mut self) -> std::io::Result<()> {
         self.writer.flush().await
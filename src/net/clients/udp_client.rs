@@ -1,9 +1,10 @@
-use crate::net::protocol::{parse_response_bin, Request, Response};
+use crate::net::protocol::{Features, Request, RequestCodec, Response, ResponseCodec};
 use anyhow::anyhow;
-use bytes::{BufMut, BytesMut};
+use bytes::BytesMut;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use tokio::net::UdpSocket;
+use tokio_util::codec::{Decoder, Encoder};
 
 /// A pixelflut client that uses UDP for communication with a pixelflut server.
 ///
@@ -12,6 +13,7 @@ use tokio::net::UdpSocket;
 #[derive(Debug)]
 pub struct UdpClient {
     socket: UdpSocket,
+    features: Option<Features>,
 }
 
 impl UdpClient {
@@ -23,28 +25,34 @@ impl UdpClient {
             UdpSocket::bind(SocketAddr::from_str("[::]:0").unwrap()).await?
         };
         socket.connect(addr).await?;
-        Ok(Self { socket })
+        Ok(Self {
+            socket,
+            features: None,
+        })
     }
 
     /// Send a single request to the configured server
     pub async fn send_request(&mut self, request: Request) -> std::io::Result<()> {
-        let mut buf = BytesMut::with_capacity(64).writer();
-        request.write(&mut buf).unwrap();
-        self.socket.send(&buf.get_ref()).await?;
+        let binary = matches!(&self.features, Some(features) if features.supports_command("PB"));
+        let mut buf = BytesMut::with_capacity(64);
+        RequestCodec::default()
+            .with_binary(binary)
+            .encode(request, &mut buf)?;
+        self.socket.send(&buf).await?;
         Ok(())
     }
 
     /// Wait for the server to send a response back
+    ///
+    /// Note that responses which do not fit into a single datagram (like the *STATE* of all but tiny canvases)
+    /// cannot be received via UDP.
     pub async fn await_response(&mut self) -> anyhow::Result<Response> {
-        let mut buf = BytesMut::with_capacity(64);
+        let mut buf = BytesMut::with_capacity(64 * 1024);
         self.socket.recv_buf(&mut buf).await?;
-        match buf.iter().enumerate().find(|(_, b)| **b == b'\n') {
-            Some((i, _)) => {
-                let response = parse_response_bin(&buf[0..i])?;
-                Ok(response)
-            }
-            None => Err(anyhow!("server did not return a valid response line")),
-        }
+        let frame = ResponseCodec::default()
+            .decode_eof(&mut buf)?
+            .ok_or_else(|| anyhow!("server did not return a valid response line"))?;
+        Ok(frame?)
     }
 
     /// Send a single request to the configured server and wait for a response back
@@ -54,6 +62,19 @@ impl UdpClient {
         Ok(response)
     }
 
+    /// Query the capabilities of the connected server
+    ///
+    /// The result is cached so that the server is only queried once.
+    /// Afterwards, requests are automatically sent in the most efficient encoding that the server supports.
+    /// Servers which do not answer in time are assumed to support no optional features at all.
+    pub async fn features(&mut self) -> &Features {
+        if self.features.is_none() {
+            let features = super::features_from_response(self.exchange(Request::GetFeatures)).await;
+            self.features = Some(features);
+        }
+        self.features.as_ref().unwrap()
+    }
+
     /// Send pre-encoded commands in bulk
     ///
     /// Note that because UDP is an unreliable transport mechanism, not all bytes might actually be sent.
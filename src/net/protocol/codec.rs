@@ -0,0 +1,440 @@
+//! Stream framing for pixelflut messages
+//!
+//! The codecs in this module implement [`Decoder`] and [`Encoder`] from `tokio_util` so that they can be used
+//! with `FramedRead`, `FramedWrite` and `Framed` or be driven manually over a [`BytesMut`] buffer.
+//! They handle messages that are split across multiple reads as well as clients which never send a newline.
+//!
+//! Decoding yields one `Result` per frame.
+//! A frame which cannot be parsed or which exceeds the configured maximum line length is reported as an
+//! [`Err(FrameError)`](FrameError) item while the stream itself stays usable.
+//! Only IO errors terminate a stream.
+
+use crate::net::protocol::{
+    next_request_len, parse_request_with_mode, parse_response_str, response_payload_len, ErrorCode, ParseErr,
+    ParserMode, Request, Response, StateEncoding,
+};
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Errors concerning a single frame which do not affect the rest of the stream
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+pub enum FrameError {
+    /// The line exceeded the maximum length and has been discarded
+    #[error("line too long")]
+    LineTooLong,
+    /// The line could not be parsed into a message
+    #[error(transparent)]
+    Parse(#[from] ParseErr),
+}
+
+impl From<FrameError> for ErrorCode {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::LineTooLong => ErrorCode::LineTooLong,
+            FrameError::Parse(e) => e.into(),
+        }
+    }
+}
+
+/// Length limiting that is shared by all codecs
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct LineLimit {
+    /// The maximum length of a line, excluding its trailing newline
+    max_line_len: usize,
+    /// Whether the remainder of an overly long line is currently being skipped
+    discarding: bool,
+}
+
+impl LineLimit {
+    fn new(max_line_len: usize) -> Self {
+        Self {
+            max_line_len,
+            discarding: false,
+        }
+    }
+
+    /// Skip the remainder of a previously discarded line
+    ///
+    /// Returns whether decoding can continue with the data that is left in `src`.
+    fn skip_discarded(&mut self, src: &mut BytesMut) -> bool {
+        if self.discarding {
+            match memchr::memchr(b'\n', src) {
+                Some(i) => {
+                    src.advance(i + 1);
+                    self.discarding = false;
+                }
+                None => src.clear(),
+            }
+        }
+        !self.discarding
+    }
+
+    /// Whether a complete frame of `len` bytes (including its newline) is too long
+    fn exceeds(&self, len: usize) -> bool {
+        len > self.max_line_len.saturating_add(1)
+    }
+
+    /// Check whether the incomplete line that is buffered in `src` is already too long
+    ///
+    /// If it is, the buffered data is dropped and the rest of the line will be skipped once it arrives.
+    fn check_incomplete(&mut self, src: &mut BytesMut) -> Option<FrameError> {
+        if src.len() > self.max_line_len {
+            src.clear();
+            self.discarding = true;
+            Some(FrameError::LineTooLong)
+        } else {
+            None
+        }
+    }
+}
+
+/// A codec that frames [`Request`]s
+///
+/// Requests are newline terminated text lines or fixed size binary *PB* commands.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RequestCodec {
+    limit: LineLimit,
+    mode: ParserMode,
+    binary: bool,
+}
+
+impl RequestCodec {
+    /// Create a codec which rejects request lines that are longer than `max_line_len` bytes
+    pub fn new(max_line_len: usize) -> Self {
+        Self {
+            limit: LineLimit::new(max_line_len),
+            mode: ParserMode::default(),
+            binary: false,
+        }
+    }
+
+    /// Configure how strictly decoded requests are checked against the protocol syntax
+    pub fn with_mode(self, mode: ParserMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Configure whether requests are encoded using the binary *PB* command where possible
+    pub fn with_binary(self, binary: bool) -> Self {
+        Self { binary, ..self }
+    }
+
+    /// The maximum length of a request line that this codec accepts
+    pub fn max_line_len(&self) -> usize {
+        self.limit.max_line_len
+    }
+}
+
+impl Default for RequestCodec {
+    /// Create a codec without a limit on the line length
+    fn default() -> Self {
+        Self::new(usize::MAX)
+    }
+}
+
+impl Decoder for RequestCodec {
+    type Item = Result<Request, FrameError>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if !self.limit.skip_discarded(src) {
+            return Ok(None);
+        }
+
+        match next_request_len(src) {
+            Some(len) if self.limit.exceeds(len) => {
+                src.advance(len);
+                Ok(Some(Err(FrameError::LineTooLong)))
+            }
+            Some(len) => {
+                let frame = src.split_to(len);
+                Ok(Some(
+                    parse_request_with_mode(&frame, self.mode).map_err(FrameError::from),
+                ))
+            }
+            None => Ok(self.limit.check_incomplete(src).map(Err)),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            // a final line is allowed to omit its newline
+            None if !buf.is_empty() => {
+                let frame = buf.split();
+                Ok(Some(
+                    parse_request_with_mode(&frame, self.mode).map_err(FrameError::from),
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Request> for RequestCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut writer = dst.writer();
+        match self.binary {
+            true => item.write_binary(&mut writer),
+            false => item.write(&mut writer),
+        }
+    }
+}
+
+/// A codec that frames [`Response`]s
+///
+/// Responses are newline terminated text lines.
+/// A *STATE* response in raw `rgb` encoding is followed by a binary payload which does not count towards the
+/// line length and is only yielded once it has been received completely.
+///
+/// *Note*: The text of *HELP* responses spans multiple lines of which only the first one is recognized.
+/// The following lines are yielded as unparsable frames.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResponseCodec {
+    limit: LineLimit,
+}
+
+impl ResponseCodec {
+    /// Create a codec which rejects response lines that are longer than `max_line_len` bytes
+    pub fn new(max_line_len: usize) -> Self {
+        Self {
+            limit: LineLimit::new(max_line_len),
+        }
+    }
+
+    /// The maximum length of a response line that this codec accepts
+    pub fn max_line_len(&self) -> usize {
+        self.limit.max_line_len
+    }
+}
+
+impl Default for ResponseCodec {
+    /// Create a codec without a limit on the line length
+    fn default() -> Self {
+        Self::new(usize::MAX)
+    }
+}
+
+/// Parse a single response line which may or may not include its trailing newline
+fn parse_response_line(line: &[u8]) -> Result<Response, FrameError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseErr::UnknownCommand)?;
+    Ok(parse_response_str(line)?)
+}
+
+impl Decoder for ResponseCodec {
+    type Item = Result<Response, FrameError>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if !self.limit.skip_discarded(src) {
+            return Ok(None);
+        }
+
+        let Some(i) = memchr::memchr(b'\n', src) else {
+            return Ok(self.limit.check_incomplete(src).map(Err));
+        };
+        if self.limit.exceeds(i + 1) {
+            src.advance(i + 1);
+            return Ok(Some(Err(FrameError::LineTooLong)));
+        }
+
+        // some responses carry a binary payload after their header line
+        let payload_len = std::str::from_utf8(&src[..i]).ok().and_then(response_payload_len);
+        if let Some(len) = payload_len {
+            let frame_len = i + 1 + len;
+            if src.len() < frame_len {
+                return Ok(None);
+            }
+            src.advance(i + 1);
+            return Ok(Some(Ok(Response::State {
+                encoding: StateEncoding::Rgb,
+                data: src.split_to(len).freeze(),
+            })));
+        }
+
+        let line = src.split_to(i + 1);
+        Ok(Some(parse_response_line(&line)))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            // a final line is allowed to omit its newline but a payload must always be complete
+            None if memchr::memchr(b'\n', buf).is_some() => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "response payload is incomplete",
+            )),
+            None if !buf.is_empty() => {
+                let line = buf.split();
+                Ok(Some(parse_response_line(&line)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Response> for ResponseCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.write(&mut dst.writer())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::protocol::HelpTopic;
+    use crate::pixmap::Color;
+
+    #[test]
+    fn test_partial_requests() {
+        let mut codec = RequestCodec::new(64);
+        let mut buf = BytesMut::from(&b"PX 1 2 "[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"AABBCC\nSIZE\nPX 3");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Ok(Request::SetPixel {
+                x: 1,
+                y: 2,
+                color: Color::from((0xAA, 0xBB, 0xCC))
+            }))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Ok(Request::GetSize)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert_eq!(&buf[..], b"PX 3");
+
+        // the last line of a stream does not need a newline
+        buf.extend_from_slice(b" 4");
+        assert_eq!(
+            codec.decode_eof(&mut buf).unwrap(),
+            Some(Ok(Request::GetPixel { x: 3, y: 4 }))
+        );
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_binary_requests() {
+        let request = Request::SetPixel {
+            x: 42,
+            y: 1337,
+            color: Color::from((0xAA, 0xBB, 0xCC)),
+        };
+        let mut codec = RequestCodec::default().with_binary(true);
+        let mut buf = BytesMut::new();
+        codec.encode(request, &mut buf).unwrap();
+        codec.encode(Request::GetSize, &mut buf).unwrap();
+        assert!(buf.starts_with(b"PB"));
+
+        let mut partial = buf.split_to(4);
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buf);
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(Ok(request)));
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(Ok(Request::GetSize)));
+    }
+
+    #[test]
+    fn test_request_line_too_long() {
+        let mut codec = RequestCodec::new(16);
+
+        // complete lines that are too long are rejected
+        let mut buf = BytesMut::from(&b"PX 1 2 AABBCC                 \nSIZE\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Err(FrameError::LineTooLong))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Ok(Request::GetSize)));
+
+        // incomplete lines are discarded until their end arrives
+        buf.extend_from_slice(b"HELP 012345678901234567890123456789");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Err(FrameError::LineTooLong))
+        );
+        assert!(buf.is_empty());
+        buf.extend_from_slice(b"01234567890123456789");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"0123\nSIZE\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Ok(Request::GetSize)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_requests_keep_stream_usable() {
+        let mut codec = RequestCodec::new(64);
+        let mut buf = BytesMut::from(&b"FOO\nPX 1 2 ZZZZZZ\nSIZE\n"[..]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Err(FrameError::Parse(ParseErr::UnknownCommand)))
+        );
+        assert!(matches!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Err(FrameError::Parse(_)))
+        ));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Ok(Request::GetSize)));
+    }
+
+    #[test]
+    fn test_responses() {
+        let mut codec = ResponseCodec::default();
+        let mut buf = BytesMut::new();
+        codec
+            .encode(
+                Response::Size {
+                    width: 800,
+                    height: 600,
+                },
+                &mut buf,
+            )
+            .unwrap();
+        codec
+            .encode(
+                Response::State {
+                    encoding: StateEncoding::Rgb,
+                    data: vec![0xAB; 12].into(),
+                },
+                &mut buf,
+            )
+            .unwrap();
+        codec.encode(Response::Help(HelpTopic::Size), &mut buf).unwrap();
+
+        // feed the encoded data into a fresh buffer in small chunks
+        let mut input = BytesMut::new();
+        let mut responses = Vec::new();
+        while !buf.is_empty() {
+            let chunk = buf.split_to(buf.len().min(5));
+            input.extend_from_slice(&chunk);
+            while let Some(frame) = codec.decode(&mut input).unwrap() {
+                responses.push(frame);
+            }
+        }
+
+        assert_eq!(
+            responses[0],
+            Ok(Response::Size {
+                width: 800,
+                height: 600
+            })
+        );
+        assert_eq!(
+            responses[1],
+            Ok(Response::State {
+                encoding: StateEncoding::Rgb,
+                data: vec![0xAB; 12].into()
+            })
+        );
+        assert_eq!(responses[2], Ok(Response::Help(HelpTopic::Size)));
+    }
+
+    #[test]
+    fn test_incomplete_response_payload() {
+        let mut codec = ResponseCodec::new(32);
+        let mut buf = BytesMut::from(&b"STATE rgb 6\n\x01\x02\x03"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(codec.decode_eof(&mut buf).is_err());
+    }
+}
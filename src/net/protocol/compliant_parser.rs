@@ -1,10 +1,14 @@
 //! A pixelflut request parser implementation that is fully compliant to the wire protocol
 
 use anyhow::anyhow;
+use std::str::FromStr;
 use thiserror::Error;
 
-use crate::net::protocol::{HelpTopic, Request, Response};
+use crate::net::protocol::{
+    ErrorCode, Features, HelpTopic, PixelWriter, Request, Response, StateEncoding, PB_LEN, PB_PREFIX,
+};
 use crate::pixmap::Color;
+use base64::prelude::*;
 
 /// Errors that can occur while parsing an input buffer
 #[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
@@ -17,42 +21,217 @@ pub enum ParseErr {
     InvalidCommand,
 }
 
-/// Parse the arguments to a PxSet command
+impl From<ParseErr> for ErrorCode {
+    fn from(value: ParseErr) -> Self {
+        match value {
+            ParseErr::UnknownCommand => ErrorCode::UnknownCommand,
+            ParseErr::InvalidCommand => ErrorCode::InvalidCommand,
+        }
+    }
+}
+
+/// Parse a hex encoded color in any notation supported by [`Color::from_hex_str()`]
 #[inline(always)]
-fn parse_px_set_args(x: &str, y: &str, px: &str) -> Result<Request, ParseErr> {
-    let xres = x.parse();
-    let yres = y.parse();
-    let cres = u32::from_str_radix(px, 16);
-    match (xres, yres, cres) {
-        (Ok(x), Ok(y), Ok(color)) => Ok(Request::SetPixel {
-            x,
-            y,
-            color: Color::from(color),
-        }),
-        (_, _, _) => Err(ParseErr::UnknownCommand),
+fn parse_color(px: &[u8]) -> Result<Color, ParseErr> {
+    Color::from_hex_bytes(px).map_err(|_| ParseErr::UnknownCommand)
+}
+
+/// Parse an unsigned decimal number directly from bytes
+///
+/// This is considerably faster than going through [`str::parse()`] because no UTF-8 validation is required.
+#[inline(always)]
+fn parse_decimal(token: &[u8]) -> Option<usize> {
+    if token.is_empty() {
+        return None;
+    }
+    let mut result: usize = 0;
+    for &b in token {
+        let digit = b.wrapping_sub(b'0');
+        if digit > 9 {
+            return None;
+        }
+        result = result.checked_mul(10)?.checked_add(digit as usize)?;
+    }
+    Some(result)
+}
+
+/// How strictly requests are checked against the documented protocol syntax
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ParserMode {
+    /// Only accept requests that follow the documented syntax exactly
+    ///
+    /// Keywords must be uppercase, tokens must be separated by exactly one space and lines must be terminated by a
+    /// single `\n`.
+    /// Numbers must not have leading zeros and colors must be given as 2, 3, 6 or 8 hex digits without a `#` prefix.
+    Strict,
+    /// Tolerate common deviations from the documented syntax
+    ///
+    /// Keywords are matched case-insensitively, tokens may be separated by any amount of spaces and tabs and lines
+    /// may be terminated by `\r\n`.
+    /// Numbers may have leading zeros and colors may have a `#` prefix or use any notation supported by
+    /// [`Color::from_hex_bytes()`].
+    #[default]
+    Lenient,
+}
+
+/// An error which indicates that a string does not name a [`ParserMode`]
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+#[error("unknown parser mode, expected either 'strict' or 'lenient'")]
+pub struct UnknownParserModeError;
+
+impl FromStr for ParserMode {
+    type Err = UnknownParserModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("strict") {
+            Ok(ParserMode::Strict)
+        } else if s.eq_ignore_ascii_case("lenient") {
+            Ok(ParserMode::Lenient)
+        } else {
+            Err(UnknownParserModeError)
+        }
+    }
+}
+
+impl ParserMode {
+    /// Whether `token` is the given keyword which is expected to be specified in uppercase
+    #[inline(always)]
+    fn is_keyword(self, token: &[u8], keyword: &[u8]) -> bool {
+        match self {
+            ParserMode::Strict => token == keyword,
+            ParserMode::Lenient => token.eq_ignore_ascii_case(keyword),
+        }
+    }
+
+    /// Parse a number argument of a request
+    #[inline(always)]
+    fn parse_number(self, token: &[u8]) -> Result<usize, ParseErr> {
+        if self == ParserMode::Strict && token.len() > 1 && token[0] == b'0' {
+            return Err(ParseErr::InvalidCommand);
+        }
+        parse_decimal(token).ok_or(ParseErr::InvalidCommand)
+    }
+
+    /// Parse a color argument of a request
+    ///
+    /// The `#` prefix is only tolerated in lenient mode, so strict mode checks the length of the bare digits.
+    #[inline(always)]
+    fn parse_color(self, token: &[u8]) -> Result<Color, ParseErr> {
+        if self == ParserMode::Strict
+            && (token.first() == Some(&b'#') || !matches!(token.len(), 2 | 3 | 6 | 8))
+        {
+            return Err(ParseErr::InvalidCommand);
+        }
+        Color::from_hex_bytes(token).map_err(|_| ParseErr::InvalidCommand)
     }
 }
 
+/// Parse the arguments to a PxSet command
+#[inline(always)]
+fn parse_px_set_args(x: &[u8], y: &[u8], px: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    Ok(Request::SetPixel {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+        color: mode.parse_color(px)?,
+    })
+}
+
 /// Parse the arguments to a PxGet command
 #[inline(always)]
-fn parse_px_get_args(x: &str, y: &str) -> Result<Request, ParseErr> {
-    let xres = x.parse();
-    let yres = y.parse();
-    match (xres, yres) {
-        (Ok(x), Ok(y)) => Ok(Request::GetPixel { x, y }),
-        (_, _) => Err(ParseErr::UnknownCommand),
+fn parse_px_get_args(x: &[u8], y: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    Ok(Request::GetPixel {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+    })
+}
+
+/// Parse the arguments to a Rect command
+#[inline(always)]
+fn parse_rect_args(args: [&[u8]; 5], mode: ParserMode) -> Result<Request, ParseErr> {
+    let [x, y, width, height, px] = args;
+    Ok(Request::FillRect {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+        width: mode.parse_number(width)?,
+        height: mode.parse_number(height)?,
+        color: mode.parse_color(px)?,
+    })
+}
+
+/// Parse the arguments to a GetRect command
+#[inline(always)]
+fn parse_get_rect_args(args: [&[u8]; 4], mode: ParserMode) -> Result<Request, ParseErr> {
+    let [x, y, width, height] = args;
+    Ok(Request::GetRect {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+        width: mode.parse_number(width)?,
+        height: mode.parse_number(height)?,
+    })
+}
+
+/// Parse the arguments to an Offset command
+#[inline(always)]
+fn parse_offset_args(x: &[u8], y: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    Ok(Request::SetOffset {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+    })
+}
+
+/// Parse the arguments to a Who command
+#[inline(always)]
+fn parse_who_args(x: &[u8], y: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    Ok(Request::GetWriter {
+        x: mode.parse_number(x)?,
+        y: mode.parse_number(y)?,
+    })
+}
+
+/// Parse the encoding argument of a State command
+///
+/// Encodings are documented in lowercase which is what strict mode requires.
+#[inline(always)]
+fn parse_state_encoding(token: &[u8], mode: ParserMode) -> Result<StateEncoding, ParseErr> {
+    let is = |encoding: &[u8]| match mode {
+        ParserMode::Strict => token == encoding,
+        ParserMode::Lenient => token.eq_ignore_ascii_case(encoding),
+    };
+    if is(b"rgb") {
+        Ok(StateEncoding::Rgb)
+    } else if is(b"rgb64") {
+        Ok(StateEncoding::RgbBase64)
+    } else {
+        Err(ParseErr::InvalidCommand)
     }
 }
 
 /// Parse the arguments to a Help command
 #[inline(always)]
-fn parse_help_args(token: &str) -> Result<Request, ParseErr> {
-    match token {
-        "help" | "HELP" | "general" | "GENERAL" => Ok(Request::Help(HelpTopic::General)),
-        "size" | "SIZE" => Ok(Request::Help(HelpTopic::Size)),
-        "px" | "PX" => Ok(Request::Help(HelpTopic::Px)),
-        _ => Err(ParseErr::InvalidCommand),
-    }
+fn parse_help_args(token: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    let is = |keyword: &[u8]| mode.is_keyword(token, keyword);
+    let topic = if is(b"HELP") || is(b"GENERAL") {
+        HelpTopic::General
+    } else if is(b"SIZE") {
+        HelpTopic::Size
+    } else if is(b"PX") {
+        HelpTopic::Px
+    } else if is(b"OFFSET") {
+        HelpTopic::Offset
+    } else if is(b"RECT") {
+        HelpTopic::Rect
+    } else if is(b"STATE") {
+        HelpTopic::State
+    } else if is(b"GETRECT") {
+        HelpTopic::GetRect
+    } else if is(b"FEATURES") || is(b"CAPS") {
+        HelpTopic::Features
+    } else if is(b"WHO") {
+        HelpTopic::Who
+    } else {
+        return Err(ParseErr::InvalidCommand);
+    };
+    Ok(Request::Help(topic))
 }
 
 /// Parse the data part of a PxData response
@@ -60,17 +239,107 @@ fn parse_help_args(token: &str) -> Result<Request, ParseErr> {
 fn parse_px_data(x: &str, y: &str, px: &str) -> Result<Response, ParseErr> {
     let xres = x.parse();
     let yres = y.parse();
-    let cres = u32::from_str_radix(px, 16);
+    let cres = parse_color(px.as_bytes());
     match (xres, yres, cres) {
-        (Ok(x), Ok(y), Ok(color)) => Ok(Response::PxData {
-            x,
-            y,
-            color: Color::from(color),
-        }),
+        (Ok(x), Ok(y), Ok(color)) => Ok(Response::PxData { x, y, color }),
         (_, _, _) => Err(ParseErr::UnknownCommand),
     }
 }
 
+/// Parse the data part of a RectData response
+#[inline(always)]
+fn parse_rect_data(x: &str, y: &str, width: &str, height: &str, data: &str) -> Result<Response, ParseErr> {
+    let (Ok(x), Ok(y), Ok(width), Ok(height)) = (x.parse(), y.parse(), width.parse(), height.parse()) else {
+        return Err(ParseErr::InvalidCommand);
+    };
+    if !data.is_ascii()
+        || Some(data.len()) != usize::checked_mul(width, height).and_then(|i| i.checked_mul(6))
+    {
+        return Err(ParseErr::InvalidCommand);
+    }
+    let colors = (0..data.len())
+        .step_by(6)
+        .map(|i| u32::from_str_radix(&data[i..i + 6], 16).map(Color::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ParseErr::InvalidCommand)?;
+    Ok(Response::RectData {
+        x,
+        y,
+        width,
+        height,
+        colors,
+    })
+}
+
+/// Parse the data part of a base64 encoded State response
+#[inline(always)]
+fn parse_state_data(encoding: &str, data: &str) -> Result<Response, ParseErr> {
+    match parse_state_encoding(encoding.as_bytes(), ParserMode::Lenient)? {
+        StateEncoding::RgbBase64 => match BASE64_STANDARD.decode(data) {
+            Ok(data) => Ok(Response::State {
+                encoding: StateEncoding::RgbBase64,
+                data: data.into(),
+            }),
+            Err(_) => Err(ParseErr::InvalidCommand),
+        },
+        // raw rgb data is not part of the line and must be read separately, see `response_payload_len()`
+        StateEncoding::Rgb => Err(ParseErr::InvalidCommand),
+    }
+}
+
+/// Parse the `key=value` pairs of a Features response
+///
+/// Unknown keys are ignored so that servers can announce new capabilities without breaking older clients.
+fn parse_features_data<'s>(pairs: impl Iterator<Item = &'s str>) -> Result<Response, ParseErr> {
+    let parse_list = |value: &str| {
+        value
+            .split(',')
+            .filter(|i| !i.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+
+    let mut features = Features::default();
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').ok_or(ParseErr::InvalidCommand)?;
+        match key {
+            "commands" => features.commands = parse_list(value),
+            "colors" => features.colors = parse_list(value),
+            "maxline" => features.max_line_len = Some(value.parse().map_err(|_| ParseErr::InvalidCommand)?),
+            "ratelimit" => features.rate_limit = Some(value.parse().map_err(|_| ParseErr::InvalidCommand)?),
+            "transports" => features.transports = parse_list(value),
+            _ => {}
+        }
+    }
+    Ok(Response::Features(features))
+}
+
+/// Parse the data part of a WriterData response
+fn parse_writer_data<'s>(mut tokens: impl Iterator<Item = &'s str>) -> Result<Response, ParseErr> {
+    let mut number = || tokens.next().and_then(|token| token.parse().ok());
+    let (Some(x), Some(y)) = (number(), number()) else {
+        return Err(ParseErr::InvalidCommand);
+    };
+    let writer = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some("-"), None, None) => None,
+        (Some(identity), Some(time), None) => Some(PixelWriter {
+            identity: identity.to_string(),
+            time: PixelWriter::time_from_unix(time.parse().map_err(|_| ParseErr::InvalidCommand)?),
+        }),
+        _ => return Err(ParseErr::InvalidCommand),
+    };
+    Ok(Response::WriterData { x, y, writer })
+}
+
+/// Parse the code and message of an Error response
+fn parse_error_data(data: &str) -> Result<Response, ParseErr> {
+    let (code, message) = data.trim().split_once(' ').unwrap_or((data.trim(), ""));
+    match ErrorCode::from_name(code) {
+        Some(code) => Ok(Response::error(code, message.trim())),
+        None => Err(ParseErr::InvalidCommand),
+    }
+}
+
 #[inline(always)]
 fn parse_size_data(width: &str, height: &str) -> Result<Response, ParseErr> {
     let width = width.parse();
@@ -87,32 +356,42 @@ fn parse_help_data(topic: &str) -> Result<Response, ParseErr> {
         "help" | "HELP" | "general" | "GENERAL" => Ok(Response::Help(HelpTopic::General)),
         "size" | "SIZE" => Ok(Response::Help(HelpTopic::Size)),
         "px" | "PX" => Ok(Response::Help(HelpTopic::Px)),
+        "offset" | "OFFSET" => Ok(Response::Help(HelpTopic::Offset)),
+        "rect" | "RECT" => Ok(Response::Help(HelpTopic::Rect)),
+        "state" | "STATE" => Ok(Response::Help(HelpTopic::State)),
+        "getrect" | "GETRECT" => Ok(Response::Help(HelpTopic::GetRect)),
+        "features" | "FEATURES" | "caps" | "CAPS" => Ok(Response::Help(HelpTopic::Features)),
+        "who" | "WHO" => Ok(Response::Help(HelpTopic::Who)),
         _ => Err(ParseErr::InvalidCommand),
     }
 }
 
+/// The maximum number of tokens that a request can consist of
+const MAX_REQUEST_TOKENS: usize = 6;
+
 /// A statically sized buffer containing input tokens.
 ///
 /// This is useful during parsing because it can be allocated on the stack instead of the heap as a Vec would.
-struct TokBuf<'s, const MAX_TOKS: usize> {
-    /// Storage for up to 4 input tokens
-    tokens: [Option<&'s str>; MAX_TOKS],
+/// Tokens are either string slices or raw byte slices.
+struct TokBuf<'s, T: ?Sized, const MAX_TOKS: usize> {
+    /// Storage for up to `MAX_TOKS` input tokens
+    tokens: [Option<&'s T>; MAX_TOKS],
     /// How many tokens are actually present in the buffer
     len: usize,
 }
 
-impl<'s, const MAX_TOKS: usize> TokBuf<'s, MAX_TOKS> {
+impl<'s, T: ?Sized, const MAX_TOKS: usize> TokBuf<'s, T, MAX_TOKS> {
     #[inline(always)]
-    fn tokens(&self) -> &[&'s str] {
+    fn tokens(&self) -> &[&'s T] {
         debug_assert_eq!(self.len, self.tokens.iter().filter(|i| i.is_some()).count());
         // Safety: Option is repr(transparent) and we know how many of them are a Some variant
         unsafe { std::mem::transmute(&self.tokens[0..self.len]) }
     }
 }
 
-impl<'s, const MAX_TOKS: usize> FromIterator<&'s str> for TokBuf<'s, MAX_TOKS> {
+impl<'s, T: ?Sized, const MAX_TOKS: usize> FromIterator<&'s T> for TokBuf<'s, T, MAX_TOKS> {
     #[inline(always)]
-    fn from_iter<T: IntoIterator<Item = &'s str>>(iter: T) -> Self {
+    fn from_iter<I: IntoIterator<Item = &'s T>>(iter: I) -> Self {
         let mut this = Self {
             tokens: [None; MAX_TOKS],
             len: 0,
@@ -127,50 +406,176 @@ impl<'s, const MAX_TOKS: usize> FromIterator<&'s str> for TokBuf<'s, MAX_TOKS> {
     }
 }
 
-/// Try to parse a single pixelflut request
+/// Try to parse a single pixelflut request in [lenient mode](ParserMode::Lenient)
 #[inline(always)]
 pub fn parse_request_str(line: &str) -> Result<Request, ParseErr> {
-    let tokens: TokBuf<'_, 4> = line.split_whitespace().collect();
-    let tokens = tokens.tokens();
-    match tokens.len() {
-        4 => parse_px_set_args(tokens[1], tokens[2], tokens[3]),
-        3 => parse_px_get_args(tokens[1], tokens[2]),
-        2 => parse_help_args(tokens[1]),
-        1 => match tokens[0] {
-            "SIZE" | "size" => Ok(Request::GetSize),
-            "HELP" | "help" => Ok(Request::Help(HelpTopic::General)),
-            _ => Err(ParseErr::UnknownCommand),
-        },
-        0 => Err(ParseErr::InvalidCommand),
-        _ => unreachable!(),
+    parse_text_request(line.as_bytes(), ParserMode::Lenient)
+}
+
+/// Split a request line into its tokens according to the parser mode
+///
+/// One more token than any request can have is collected so that surplus arguments can be detected.
+#[inline(always)]
+fn tokenize(line: &[u8], mode: ParserMode) -> Result<TokBuf<'_, [u8], { MAX_REQUEST_TOKENS + 1 }>, ParseErr> {
+    match mode {
+        ParserMode::Strict => {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let tokens: TokBuf<'_, [u8], { MAX_REQUEST_TOKENS + 1 }> = line.split(|&b| b == b' ').collect();
+            match tokens.tokens().iter().any(|token| token.is_empty()) {
+                true => Err(ParseErr::InvalidCommand),
+                false => Ok(tokens),
+            }
+        }
+        ParserMode::Lenient => Ok(line
+            .split(u8::is_ascii_whitespace)
+            .filter(|token| !token.is_empty())
+            .collect()),
     }
 }
 
-/// Parse a single request from a byte slice
+/// Parse a single text request directly from its bytes
 #[inline(always)]
-pub fn parse_request_bin(line: &[u8]) -> anyhow::Result<Request> {
-    if line.is_ascii() {
-        // Safety: This is fine because the bytes are already checked to be ascii
-        let str = unsafe { std::str::from_utf8_unchecked(line) };
-        Ok(parse_request_str(str)?)
+fn parse_text_request(line: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    let tokens = tokenize(line, mode)?;
+    let Some((&cmd, args)) = tokens.tokens().split_first() else {
+        return Err(ParseErr::InvalidCommand);
+    };
+    let is = |keyword: &[u8]| mode.is_keyword(cmd, keyword);
+
+    if is(b"PX") {
+        match *args {
+            [x, y, px] => parse_px_set_args(x, y, px, mode),
+            [x, y] => parse_px_get_args(x, y, mode),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"RECT") {
+        let args = <[&[u8]; 5]>::try_from(args).map_err(|_| ParseErr::InvalidCommand)?;
+        parse_rect_args(args, mode)
+    } else if is(b"GETRECT") {
+        let args = <[&[u8]; 4]>::try_from(args).map_err(|_| ParseErr::InvalidCommand)?;
+        parse_get_rect_args(args, mode)
+    } else if is(b"OFFSET") {
+        match *args {
+            [x, y] => parse_offset_args(x, y, mode),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"WHO") {
+        match *args {
+            [x, y] => parse_who_args(x, y, mode),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"SIZE") {
+        match args {
+            [] => Ok(Request::GetSize),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"STATE") {
+        match *args {
+            [] => Ok(Request::GetState(StateEncoding::Rgb)),
+            [encoding] => Ok(Request::GetState(parse_state_encoding(encoding, mode)?)),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"HELP") {
+        match *args {
+            [] => Ok(Request::Help(HelpTopic::General)),
+            [topic] => parse_help_args(topic, mode),
+            _ => Err(ParseErr::InvalidCommand),
+        }
+    } else if is(b"FEATURES") || is(b"CAPS") {
+        match args {
+            [] => Ok(Request::GetFeatures),
+            _ => Err(ParseErr::InvalidCommand),
+        }
     } else {
-        Err(anyhow!("request buffer does not contain an ascii string"))
+        Err(ParseErr::UnknownCommand)
+    }
+}
+
+/// Parse the content of a binary *PB* command which is known to have exactly [`PB_LEN`] bytes
+#[inline(always)]
+fn parse_pb(cmd: &[u8; PB_LEN]) -> Request {
+    Request::SetPixel {
+        x: u16::from_le_bytes([cmd[2], cmd[3]]) as usize,
+        y: u16::from_le_bytes([cmd[4], cmd[5]]) as usize,
+        color: Color::from([cmd[6], cmd[7], cmd[8], cmd[9]]),
+    }
+}
+
+/// Determine the length of the first complete request contained in the buffer.
+///
+/// Binary *PB* commands have a fixed length while all other requests are terminated by a newline which is included
+/// in the returned length.
+/// If the buffer does not yet contain a complete request, `None` is returned.
+#[inline(always)]
+pub fn next_request_len(buf: &[u8]) -> Option<usize> {
+    if buf.starts_with(PB_PREFIX) {
+        (buf.len() >= PB_LEN).then_some(PB_LEN)
+    } else {
+        memchr::memchr(b'\n', buf).map(|i| i + 1)
+    }
+}
+
+/// Parse a single request from a byte slice in [lenient mode](ParserMode::Lenient)
+///
+/// The slice may either contain a text request or exactly one binary *PB* command.
+/// Text requests are parsed directly from the bytes so that no UTF-8 validation is necessary.
+#[inline(always)]
+pub fn parse_request_bin(line: &[u8]) -> Result<Request, ParseErr> {
+    parse_request_with_mode(line, ParserMode::Lenient)
+}
+
+/// Parse a single request from a byte slice with the given strictness
+///
+/// Binary *PB* commands are not affected by the parser mode because their encoding leaves no room for deviations.
+#[inline(always)]
+pub fn parse_request_with_mode(line: &[u8], mode: ParserMode) -> Result<Request, ParseErr> {
+    if let Ok(cmd) = <&[u8; PB_LEN]>::try_from(line) {
+        if cmd.starts_with(PB_PREFIX) {
+            return Ok(parse_pb(cmd));
+        }
     }
+    parse_text_request(line, mode)
 }
 
 /// Try to parse a single pixelflut response
 #[inline(always)]
 pub fn parse_response_str(line: &str) -> Result<Response, ParseErr> {
-    let tokens: TokBuf<'_, 4> = line.split_whitespace().collect();
+    // features, errors and writers consist of a variable number of tokens and are therefore handled separately
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some("FEATURES" | "features") => return parse_features_data(words),
+        Some("ERR" | "err") => return parse_error_data(line.trim_start()[3..].trim_start()),
+        Some("WHO" | "who") => return parse_writer_data(words),
+        _ => {}
+    }
+
+    let tokens: TokBuf<'_, str, 6> = line.split_whitespace().collect();
     let tokens = tokens.tokens();
     match tokens.len() {
+        6 => parse_rect_data(tokens[1], tokens[2], tokens[3], tokens[4], tokens[5]),
         4 => parse_px_data(tokens[1], tokens[2], tokens[3]),
-        3 => parse_size_data(tokens[1], tokens[2]),
+        3 => match tokens[0] {
+            "STATE" | "state" => parse_state_data(tokens[1], tokens[2]),
+            _ => parse_size_data(tokens[1], tokens[2]),
+        },
         2 => parse_help_data(tokens[1]),
         _ => Err(ParseErr::UnknownCommand),
     }
 }
 
+/// Determine whether the line is the header of a response that is followed by a binary payload.
+///
+/// This is the case for *STATE* responses in [`StateEncoding::Rgb`] encoding which are sent as a header line
+/// (`STATE rgb <len>`) followed by `len` bytes of raw rgb data.
+/// If the line is such a header, the length of the payload is returned.
+pub fn response_payload_len(line: &str) -> Option<usize> {
+    let tokens: TokBuf<'_, str, 4> = line.split_whitespace().collect();
+    match tokens.tokens() {
+        ["STATE" | "state", "rgb" | "RGB", len] => len.parse().ok(),
+        _ => None,
+    }
+}
+
 /// Parse a single pixelflut response from a byte slice
 #[inline(always)]
 pub fn parse_response_bin(line: &[u8]) -> anyhow::Result<Response> {
@@ -187,6 +592,7 @@ pub fn parse_response_bin(line: &[u8]) -> anyhow::Result<Response> {
 mod test {
     use super::*;
     use ::test::Bencher;
+    use bytes::Bytes;
     use std::hint::black_box;
 
     #[test]
@@ -214,6 +620,446 @@ mod test {
                 color: Color::from((0xAA, 0xBB, 0xCC)),
             },
         );
+        run_test(
+            "PX 1 2 AABBCC80",
+            Request::SetPixel {
+                x: 1,
+                y: 2,
+                color: Color::from_rgba(0xAA, 0xBB, 0xCC, 0x80),
+            },
+        );
+        run_test(
+            "PX 1 2 aabbccff",
+            Request::SetPixel {
+                x: 1,
+                y: 2,
+                color: Color::from((0xAA, 0xBB, 0xCC)),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_rect() {
+        assert_eq!(
+            parse_request_str("RECT 1 2 30 40 AABBCC"),
+            Ok(Request::FillRect {
+                x: 1,
+                y: 2,
+                width: 30,
+                height: 40,
+                color: Color::from((0xAA, 0xBB, 0xCC)),
+            })
+        );
+        assert_eq!(parse_request_str("HELP RECT"), Ok(Request::Help(HelpTopic::Rect)));
+        assert_eq!(
+            parse_request_str("RECT 1 2 30 -4 AABBCC"),
+            Err(ParseErr::InvalidCommand)
+        );
+    }
+
+    #[test]
+    fn test_parse_state() {
+        assert_eq!(
+            parse_request_str("STATE"),
+            Ok(Request::GetState(StateEncoding::Rgb))
+        );
+        assert_eq!(
+            parse_request_str("STATE rgb"),
+            Ok(Request::GetState(StateEncoding::Rgb))
+        );
+        assert_eq!(
+            parse_request_str("STATE rgb64"),
+            Ok(Request::GetState(StateEncoding::RgbBase64))
+        );
+        assert_eq!(parse_request_str("STATE foo"), Err(ParseErr::InvalidCommand));
+        assert_eq!(
+            parse_request_str("HELP STATE"),
+            Ok(Request::Help(HelpTopic::State))
+        );
+    }
+
+    #[test]
+    fn test_parse_get_rect() {
+        assert_eq!(
+            parse_request_str("GETRECT 1 2 30 40"),
+            Ok(Request::GetRect {
+                x: 1,
+                y: 2,
+                width: 30,
+                height: 40,
+            })
+        );
+        assert_eq!(
+            parse_request_str("HELP GETRECT"),
+            Ok(Request::Help(HelpTopic::GetRect))
+        );
+        assert_eq!(
+            parse_request_str("GETRECT 1 2 x 4"),
+            Err(ParseErr::InvalidCommand)
+        );
+    }
+
+    #[test]
+    fn test_rect_response_roundtrip() {
+        let response = Response::RectData {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 1,
+            colors: vec![Color::from((0xAA, 0xBB, 0xCC)), Color::from((0x00, 0x01, 0x02))],
+        };
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(buf, b"RECT 1 2 2 1 AABBCC000102\n");
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+        assert_eq!(
+            parse_response_str("RECT 1 2 2 1 AABBCC"),
+            Err(ParseErr::InvalidCommand)
+        );
+
+        // lines which do not fit onto the stack are assembled on the heap
+        let response = Response::RectData {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+            colors: (0..200)
+                .map(|i| Color::from((i as u8, 0x80, 0xFF - i as u8)))
+                .collect(),
+        };
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), "RECT 0 0 20 10 \n".len() + 200 * 6);
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+    }
+
+    #[tokio::test]
+    async fn test_write_response_async() {
+        let responses = [
+            Response::Help(HelpTopic::Px),
+            Response::Size {
+                width: 800,
+                height: 600,
+            },
+            Response::error(ErrorCode::OutOfBounds, "pixel 5,5 is outside of the canvas"),
+            Response::State {
+                encoding: StateEncoding::Rgb,
+                data: Bytes::from_static(&[0x00, 0x11, 0x22]),
+            },
+        ];
+        for response in responses {
+            let mut buf = Vec::new();
+            response.write(&mut buf).unwrap();
+            let mut async_buf = Vec::new();
+            response.write_async(&mut async_buf).await.unwrap();
+            assert_eq!(async_buf, buf);
+        }
+    }
+
+    #[test]
+    fn test_parse_features() {
+        assert_eq!(parse_request_str("FEATURES"), Ok(Request::GetFeatures));
+        assert_eq!(parse_request_str("CAPS"), Ok(Request::GetFeatures));
+        assert_eq!(
+            parse_request_str("HELP FEATURES"),
+            Ok(Request::Help(HelpTopic::Features))
+        );
+
+        // unknown keys are ignored and optional values may be omitted
+        assert_eq!(
+            parse_response_str("FEATURES commands=PB,RECT foo=bar colors= transports=tcp"),
+            Ok(Response::Features(Features {
+                commands: vec!["PB".to_string(), "RECT".to_string()],
+                colors: vec![],
+                max_line_len: None,
+                rate_limit: None,
+                transports: vec!["tcp".to_string()],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_features_response_roundtrip() {
+        let response = Response::Features(Features {
+            commands: vec!["PB".to_string(), "STATE".to_string()],
+            colors: vec!["rgb".to_string(), "rgba".to_string()],
+            max_line_len: Some(64),
+            rate_limit: Some(1000),
+            transports: vec!["tcp".to_string(), "udp".to_string()],
+        });
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            b"FEATURES commands=PB,STATE colors=rgb,rgba maxline=64 ratelimit=1000 transports=tcp,udp\n"
+        );
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+    }
+
+    #[test]
+    fn test_error_response_roundtrip() {
+        let response = Response::error(ErrorCode::OutOfBounds, "pixel 5,5 is outside of the canvas");
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(buf, b"ERR OUT_OF_BOUNDS pixel 5,5 is outside of the canvas\n");
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+
+        assert_eq!(
+            parse_response_str("ERR LINE_TOO_LONG\n"),
+            Ok(Response::error(ErrorCode::LineTooLong, ""))
+        );
+        assert_eq!(parse_response_str("ERR FOO bar"), Err(ParseErr::InvalidCommand));
+    }
+
+    #[test]
+    fn test_who_roundtrip() {
+        assert_eq!(
+            parse_request_str("WHO 1 2"),
+            Ok(Request::GetWriter { x: 1, y: 2 })
+        );
+        assert_eq!(parse_request_str("HELP WHO"), Ok(Request::Help(HelpTopic::Who)));
+        assert_eq!(parse_request_str("WHO 1"), Err(ParseErr::InvalidCommand));
+
+        let response = Response::WriterData {
+            x: 1,
+            y: 2,
+            writer: Some(PixelWriter {
+                identity: "10.0.0.1".to_string(),
+                time: PixelWriter::time_from_unix(1700000000),
+            }),
+        };
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(buf, b"WHO 1 2 10.0.0.1 1700000000\n");
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+
+        let response = Response::WriterData {
+            x: 1,
+            y: 2,
+            writer: None,
+        };
+        assert_eq!(response.to_string(), "WHO 1 2 -");
+        assert_eq!(parse_response_str("WHO 1 2 -"), Ok(response));
+        assert_eq!(parse_response_str("WHO 1 2 foo"), Err(ParseErr::InvalidCommand));
+    }
+
+    #[test]
+    fn test_state_response_roundtrip() {
+        let data = Bytes::from_static(&[0x00, 0x11, 0x22, 0x0a, 0x0a, 0x0a]);
+
+        let response = Response::State {
+            encoding: StateEncoding::RgbBase64,
+            data: data.clone(),
+        };
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        assert_eq!(parse_response_bin(&buf).unwrap(), response);
+
+        let response = Response::State {
+            encoding: StateEncoding::Rgb,
+            data: data.clone(),
+        };
+        let mut buf = Vec::new();
+        response.write(&mut buf).unwrap();
+        let header_len = buf.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let header = std::str::from_utf8(&buf[..header_len]).unwrap();
+        assert_eq!(response_payload_len(header), Some(data.len()));
+        assert_eq!(&buf[header_len..], &data);
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(
+            parse_request_str("OFFSET 10 20"),
+            Ok(Request::SetOffset { x: 10, y: 20 })
+        );
+        assert_eq!(
+            parse_request_str("HELP OFFSET"),
+            Ok(Request::Help(HelpTopic::Offset))
+        );
+        assert_eq!(
+            parse_request_str("PX 10 20"),
+            Ok(Request::GetPixel { x: 10, y: 20 })
+        );
+        assert_eq!(parse_request_str("OFFSET 10 -2"), Err(ParseErr::InvalidCommand));
+    }
+
+    #[test]
+    fn test_parse_pb() {
+        let cmd = b"PB\x2a\x00\x01\x01\xaa\xbb\xcc\xff";
+        let expected = Request::SetPixel {
+            x: 42,
+            y: 257,
+            color: Color::from((0xAA, 0xBB, 0xCC)),
+        };
+        assert_eq!(parse_request_bin(cmd).unwrap(), expected);
+
+        // binary commands may contain newline bytes which must not be treated as line endings
+        let cmd = b"PB\x0a\x00\x0a\x00\x0a\x0a\x0a\x80PX 1 2\n";
+        assert_eq!(next_request_len(cmd), Some(PB_LEN));
+        assert_eq!(next_request_len(&cmd[PB_LEN..]), Some(7));
+        assert_eq!(next_request_len(&cmd[..PB_LEN - 1]), None);
+        assert_eq!(
+            parse_request_bin(&cmd[..PB_LEN]).unwrap(),
+            Request::SetPixel {
+                x: 10,
+                y: 10,
+                color: Color::from_rgba(0x0A, 0x0A, 0x0A, 0x80),
+            }
+        );
+    }
+
+    #[test]
+    fn test_pb_roundtrip() {
+        let req = Request::SetPixel {
+            x: 1234,
+            y: 42,
+            color: Color::from_rgba(0x12, 0x34, 0x56, 0x78),
+        };
+        let mut buf = Vec::new();
+        req.write_binary(&mut buf).unwrap();
+        assert_eq!(buf.len(), PB_LEN);
+        assert_eq!(parse_request_bin(&buf).unwrap(), req);
+
+        // requests that cannot be encoded in binary fall back to text
+        let req = Request::SetPixel {
+            x: 100_000,
+            y: 42,
+            color: Color::from((0x12, 0x34, 0x56)),
+        };
+        let mut buf = Vec::new();
+        req.write_binary(&mut buf).unwrap();
+        assert_eq!(buf, b"PX 100000 42 123456\n");
+    }
+
+    #[test]
+    fn test_parse_raw_bytes() {
+        assert_eq!(
+            parse_request_bin(b"PX 1 2 \xff\xfe\n"),
+            Err(ParseErr::InvalidCommand)
+        );
+        assert_eq!(parse_request_bin(b"\xffPX\n"), Err(ParseErr::UnknownCommand));
+        assert_eq!(
+            parse_request_bin(b"PX 99999999999999999999999 1\n"),
+            Err(ParseErr::InvalidCommand)
+        );
+        assert_eq!(
+            parse_request_bin(b"PX\t1  2\r\n"),
+            Ok(Request::GetPixel { x: 1, y: 2 })
+        );
+    }
+
+    quickcheck! {
+        fn test_set_pixel_roundtrip(x: usize, y: usize, color: Color) -> bool {
+            let req = Request::SetPixel { x, y, color };
+            let mut buf = Vec::new();
+            req.write(&mut buf).unwrap();
+            buf == format!("PX {} {} {:X}\n", x, y, color).as_bytes()
+                && parse_request_bin(&buf) == Ok(req)
+                && parse_request_with_mode(&buf, ParserMode::Strict) == Ok(req)
+        }
+
+        fn test_get_rect_roundtrip(x: usize, y: usize, width: usize, height: usize) -> bool {
+            let req = Request::GetRect { x, y, width, height };
+            let mut buf = Vec::new();
+            req.write(&mut buf).unwrap();
+            parse_request_bin(&buf) == Ok(req) && parse_request_with_mode(&buf, ParserMode::Strict) == Ok(req)
+        }
+    }
+
+    #[test]
+    fn test_parser_modes() {
+        let strict = |line: &str| parse_request_with_mode(line.as_bytes(), ParserMode::Strict);
+        let lenient = |line: &str| parse_request_with_mode(line.as_bytes(), ParserMode::Lenient);
+        let px = Request::SetPixel {
+            x: 1,
+            y: 2,
+            color: Color::from((0xAA, 0xBB, 0xCC)),
+        };
+
+        // documented syntax is accepted by both modes
+        for line in ["PX 1 2 AABBCC\n", "PX 1 2 aabbcc", "HELP PX\n", "STATE rgb64\n"] {
+            assert!(strict(line).is_ok(), "{line:?} should be accepted in strict mode");
+            assert_eq!(strict(line), lenient(line));
+        }
+
+        // common deviations are only tolerated in lenient mode
+        for line in ["px 1 2 AABBCC\n", "Px 1 2 AABBCC\n"] {
+            assert_eq!(strict(line), Err(ParseErr::UnknownCommand), "{line:?}");
+            assert_eq!(lenient(line), Ok(px), "{line:?}");
+        }
+        for line in [
+            "PX\t1 2  AABBCC\n",
+            " PX 1 2 AABBCC\n",
+            "PX 1 2 AABBCC\r\n",
+            "PX 01 002 AABBCC\n",
+            "PX 1 2 #AABBCC\n",
+        ] {
+            assert_eq!(strict(line), Err(ParseErr::InvalidCommand), "{line:?}");
+            assert_eq!(lenient(line), Ok(px), "{line:?}");
+        }
+        assert_eq!(lenient("help state"), Ok(Request::Help(HelpTopic::State)));
+        assert_eq!(strict("help state"), Err(ParseErr::UnknownCommand));
+        assert_eq!(strict("STATE RGB"), Err(ParseErr::InvalidCommand));
+        assert_eq!(strict("PX 1 2 ABCDE"), Err(ParseErr::InvalidCommand));
+        assert_eq!(strict("PX 1 2 #ABCDE"), Err(ParseErr::InvalidCommand));
+        assert_eq!(strict("PX 1 2 #AB"), Err(ParseErr::InvalidCommand));
+        assert!(lenient("PX 1 2 #AB").is_ok());
+        assert!(lenient("PX 1 2 ABCDE").is_ok());
+        assert!(strict("PX 0 0 AABBCC").is_ok());
+
+        // both modes validate the command keyword, the number of arguments and their format
+        for parse in [strict, lenient] {
+            assert_eq!(parse("FOO 1 2 AABBCC"), Err(ParseErr::UnknownCommand));
+            assert_eq!(parse("PX 1 2 AABBCC 4"), Err(ParseErr::InvalidCommand));
+            assert_eq!(parse("SIZE 1"), Err(ParseErr::InvalidCommand));
+            assert_eq!(parse("PX x 2"), Err(ParseErr::InvalidCommand));
+            assert_eq!(parse("PX 1 2 XYZXYZ"), Err(ParseErr::InvalidCommand));
+            assert_eq!(parse(""), Err(ParseErr::InvalidCommand));
+        }
+    }
+
+    #[test]
+    fn test_parser_mode_from_str() {
+        assert_eq!("strict".parse(), Ok(ParserMode::Strict));
+        assert_eq!("Lenient".parse(), Ok(ParserMode::Lenient));
+        assert_eq!("foo".parse::<ParserMode>(), Err(UnknownParserModeError));
+    }
+
+    #[test]
+    fn test_parse_color_notations() {
+        fn run_test(line: &str, color: Color) {
+            let req = parse_request_str(line);
+            let res = Request::SetPixel { x: 1, y: 2, color };
+            assert_eq!(req, Ok(res), "{:06x?} != Ok({:06x?})", req, res);
+        }
+
+        run_test("PX 1 2 ab", Color::from((0xAB, 0xAB, 0xAB)));
+        run_test("PX 1 2 f80", Color::from((0xFF, 0x88, 0x00)));
+        run_test("PX 1 2 #AABBCC", Color::from((0xAA, 0xBB, 0xCC)));
+        run_test("PX 1 2 #AABBCC80", Color::from_rgba(0xAA, 0xBB, 0xCC, 0x80));
+    }
+
+    #[test]
+    fn test_parse_invalid_color() {
+        assert_eq!(parse_request_str("PX 1 2 AABBCCD"), Err(ParseErr::InvalidCommand));
+        assert_eq!(
+            parse_request_str("PX 1 2 AABBCCDDEE"),
+            Err(ParseErr::InvalidCommand)
+        );
+    }
+
+    #[test]
+    fn test_translucent_request_roundtrip() {
+        let req = Request::SetPixel {
+            x: 3,
+            y: 4,
+            color: Color::from_rgba(0x12, 0x34, 0x56, 0x78),
+        };
+        let mut buf = Vec::new();
+        req.write(&mut buf).unwrap();
+        assert_eq!(buf, b"PX 3 4 12345678\n");
+        assert_eq!(parse_request_bin(&buf).unwrap(), req);
     }
 
     #[bench]
@@ -228,6 +1074,46 @@ mod test {
         b.iter(move || parse_request_str(black_box(cmd)).unwrap());
     }
 
+    #[bench]
+    fn bench_parse_set_pixel_pb(b: &mut Bencher) {
+        let cmd = b"PB\x11\x00\xd0\x1d\x12\xfb\xa5\xff";
+        b.iter(move || parse_request_bin(black_box(cmd)).unwrap());
+    }
+
+    #[bench]
+    fn bench_parse_rect(b: &mut Bencher) {
+        let cmd = b"RECT 17 7632 100 200 12FBA5\n";
+        b.iter(move || parse_request_bin(black_box(cmd)).unwrap());
+    }
+
+    #[bench]
+    fn bench_write_set_pixel(b: &mut Bencher) {
+        let req = Request::SetPixel {
+            x: 17,
+            y: 7632,
+            color: Color::from((0x12, 0xFB, 0xA5)),
+        };
+        let mut buf = Vec::with_capacity(64);
+        b.iter(move || {
+            buf.clear();
+            black_box(req).write(&mut buf).unwrap();
+        });
+    }
+
+    #[bench]
+    fn bench_write_set_pixel_pb(b: &mut Bencher) {
+        let req = Request::SetPixel {
+            x: 17,
+            y: 7632,
+            color: Color::from((0x12, 0xFB, 0xA5)),
+        };
+        let mut buf = Vec::with_capacity(64);
+        b.iter(move || {
+            buf.clear();
+            black_box(req).write_binary(&mut buf).unwrap();
+        });
+    }
+
     #[bench]
     fn bench_parse_size(b: &mut Bencher) {
         let cmd = "SIZE";
@@ -2,10 +2,22 @@
 
 use crate::pixmap::Color;
 use crate::texts;
+use base64::prelude::*;
+use bytes::Bytes;
 use std::fmt::{Display, Formatter};
 use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+/// The prefix with which binary *PB* commands start
+pub const PB_PREFIX: &[u8; 2] = b"PB";
+
+/// The total length in bytes of one binary *PB* command.
+///
+/// It consists of the [`PB_PREFIX`], x and y coordinates encoded as little-endian u16 and 4 color bytes in RGBA
+/// order.
+pub const PB_LEN: usize = PB_PREFIX.len() + 2 * size_of::<u16>() + 4;
+
 /// The help topics that can be requested from the server
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum HelpTopic {
@@ -15,6 +27,169 @@ pub enum HelpTopic {
     Size,
     /// Help about the *PX* command (both set and get variants)
     Px,
+    /// Help about the *OFFSET* command
+    Offset,
+    /// Help about the *RECT* command
+    Rect,
+    /// Help about the *STATE* command
+    State,
+    /// Help about the *GETRECT* command
+    GetRect,
+    /// Help about the *FEATURES* command
+    Features,
+    /// Help about the *WHO* command
+    Who,
+}
+
+impl HelpTopic {
+    /// The help text which the server sends for this topic
+    pub fn text(&self) -> &'static str {
+        match self {
+            HelpTopic::General => texts::HELP_GENERAL,
+            HelpTopic::Size => texts::HELP_SIZE,
+            HelpTopic::Px => texts::HELP_PX,
+            HelpTopic::Offset => texts::HELP_OFFSET,
+            HelpTopic::Rect => texts::HELP_RECT,
+            HelpTopic::State => texts::HELP_STATE,
+            HelpTopic::GetRect => texts::HELP_GETRECT,
+            HelpTopic::Features => texts::HELP_FEATURES,
+            HelpTopic::Who => texts::HELP_WHO,
+        }
+    }
+}
+
+/// The encodings in which the whole canvas can be transferred via the *STATE* command
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StateEncoding {
+    /// Raw rgb bytes which are sent as a length-prefixed binary blob
+    Rgb,
+    /// Rgb bytes which are base64 encoded and sent as one line of text
+    ///
+    /// This is useful for transports that cannot carry binary data like the text channel of a WebSocket.
+    RgbBase64,
+}
+
+impl StateEncoding {
+    /// The name by which the encoding is identified on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateEncoding::Rgb => "rgb",
+            StateEncoding::RgbBase64 => "rgb64",
+        }
+    }
+}
+
+/// The capabilities of a pixelflut server as announced in response to a *FEATURES* request
+///
+/// Values which are not known to this implementation are preserved so that clients can still inspect them.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Features {
+    /// Optional commands that are understood in addition to `HELP`, `SIZE` and `PX` (e.g. `PB` or `RECT`)
+    pub commands: Vec<String>,
+    /// Color notations which are accepted by the server (e.g. `rgb` or `rgba`)
+    pub colors: Vec<String>,
+    /// The maximum length in bytes of a single request line
+    pub max_line_len: Option<usize>,
+    /// The maximum number of requests per second that are accepted from one client
+    pub rate_limit: Option<usize>,
+    /// Transports over which the server can be reached (e.g. `tcp` or `udp`)
+    pub transports: Vec<String>,
+}
+
+impl Features {
+    /// Whether the server announced support for the given optional command
+    pub fn supports_command(&self, command: &str) -> bool {
+        self.commands.iter().any(|c| c.eq_ignore_ascii_case(command))
+    }
+
+    /// Whether the server announced support for the given color notation
+    pub fn supports_color(&self, notation: &str) -> bool {
+        self.colors.iter().any(|c| c.eq_ignore_ascii_case(notation))
+    }
+}
+
+/// Formats the features as space separated `key=value` pairs in which lists are separated by commas
+impl Display for Features {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "commands={} colors={}",
+            self.commands.join(","),
+            self.colors.join(",")
+        ))?;
+        if let Some(max_line_len) = self.max_line_len {
+            f.write_fmt(format_args!(" maxline={}", max_line_len))?;
+        }
+        if let Some(rate_limit) = self.rate_limit {
+            f.write_fmt(format_args!(" ratelimit={}", rate_limit))?;
+        }
+        f.write_fmt(format_args!(" transports={}", self.transports.join(",")))
+    }
+}
+
+/// Who last wrote a pixel and when, as reported in response to a *WHO* request
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PixelWriter {
+    /// The identity of the writer, usually the address of the client which wrote the pixel
+    ///
+    /// It never contains whitespace so that it can be transferred as a single token.
+    pub identity: String,
+    /// When the pixel was written, with a precision of one second
+    pub time: SystemTime,
+}
+
+impl PixelWriter {
+    /// The identity which is reported for writes that are not attributed to any client
+    pub const UNKNOWN_IDENTITY: &'static str = "unknown";
+
+    /// The time of the write in seconds since the unix epoch, as it is transferred on the wire
+    pub fn unix_time(&self) -> u64 {
+        self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+
+    /// Reconstruct the time of a write from the number of seconds since the unix epoch
+    pub fn time_from_unix(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+}
+
+/// The kinds of errors that a server reports to its clients via [`Response::Error`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ErrorCode {
+    /// The request is not a known pixelflut command
+    UnknownCommand,
+    /// The command is known but its arguments are invalid
+    InvalidCommand,
+    /// The request addresses pixels outside of the canvas
+    OutOfBounds,
+    /// The request is longer than the server is willing to buffer
+    LineTooLong,
+    /// The request writes pixels in a region which the operator protected from clients
+    Protected,
+}
+
+impl ErrorCode {
+    /// The name by which the error code is identified on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "UNKNOWN_COMMAND",
+            ErrorCode::InvalidCommand => "INVALID_COMMAND",
+            ErrorCode::OutOfBounds => "OUT_OF_BOUNDS",
+            ErrorCode::LineTooLong => "LINE_TOO_LONG",
+            ErrorCode::Protected => "PROTECTED",
+        }
+    }
+
+    /// Look up an error code by the name with which it is identified on the wire
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "UNKNOWN_COMMAND" => Some(ErrorCode::UnknownCommand),
+            "INVALID_COMMAND" => Some(ErrorCode::InvalidCommand),
+            "OUT_OF_BOUNDS" => Some(ErrorCode::OutOfBounds),
+            "LINE_TOO_LONG" => Some(ErrorCode::LineTooLong),
+            "PROTECTED" => Some(ErrorCode::Protected),
+            _ => None,
+        }
+    }
 }
 
 /// A request to a pixelflut server
@@ -38,63 +213,174 @@ pub enum Request {
         /// The y coordinate of the pixel
         y: usize,
         /// The color to which the pixel should be set
+        ///
+        /// If the color is not fully opaque, it is composited over the pixels current color instead of replacing it.
         color: Color,
     },
+    /// Fill a rectangle with one color
+    FillRect {
+        /// The x coordinate of the rectangles top left corner
+        x: usize,
+        /// The y coordinate of the rectangles top left corner
+        y: usize,
+        /// The width of the rectangle
+        width: usize,
+        /// The height of the rectangle
+        height: usize,
+        /// The color with which the rectangle should be filled
+        ///
+        /// If the color is not fully opaque, it is composited over the current color of each pixel.
+        color: Color,
+    },
+    /// Get the colors of all pixels in a rectangle
+    GetRect {
+        /// The x coordinate of the rectangles top left corner
+        x: usize,
+        /// The y coordinate of the rectangles top left corner
+        y: usize,
+        /// The width of the rectangle
+        width: usize,
+        /// The height of the rectangle
+        height: usize,
+    },
+    /// Get the content of the whole canvas at once
+    GetState(StateEncoding),
+    /// Get the capabilities of the server
+    GetFeatures,
+    /// Set an offset that is added to the coordinates of all following pixel requests on the same connection
+    SetOffset {
+        /// The offset in x direction
+        x: usize,
+        /// The offset in y direction
+        y: usize,
+    },
+    /// Get who last wrote one pixel and when
+    GetWriter {
+        /// The x coordinate of the pixel
+        x: usize,
+        /// The y coordinate of the pixel
+        y: usize,
+    },
 }
 
 impl Request {
+    /// Assemble the text representation of this request including its trailing newline without allocating
+    fn encode(&self) -> LineBuf {
+        let mut line = LineBuf::new();
+        match self {
+            Request::Help(topic) => line.push(match topic {
+                HelpTopic::General => b"HELP",
+                HelpTopic::Size => b"HELP SIZE",
+                HelpTopic::Px => b"HELP PX",
+                HelpTopic::Offset => b"HELP OFFSET",
+                HelpTopic::Rect => b"HELP RECT",
+                HelpTopic::State => b"HELP STATE",
+                HelpTopic::GetRect => b"HELP GETRECT",
+                HelpTopic::Features => b"HELP FEATURES",
+                HelpTopic::Who => b"HELP WHO",
+            }),
+            Request::GetSize => line.push(b"SIZE"),
+            Request::GetPixel { x, y } => line.push(b"PX ").push_decimal(*x).push(b" ").push_decimal(*y),
+            Request::SetPixel { x, y, color } => line
+                .push(b"PX ")
+                .push_decimal(*x)
+                .push(b" ")
+                .push_decimal(*y)
+                .push(b" ")
+                .push_color(*color),
+            Request::FillRect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => line
+                .push(b"RECT ")
+                .push_decimal(*x)
+                .push(b" ")
+                .push_decimal(*y)
+                .push(b" ")
+                .push_decimal(*width)
+                .push(b" ")
+                .push_decimal(*height)
+                .push(b" ")
+                .push_color(*color),
+            Request::GetRect { x, y, width, height } => line
+                .push(b"GETRECT ")
+                .push_decimal(*x)
+                .push(b" ")
+                .push_decimal(*y)
+                .push(b" ")
+                .push_decimal(*width)
+                .push(b" ")
+                .push_decimal(*height),
+            Request::GetState(encoding) => line.push(b"STATE ").push(encoding.name().as_bytes()),
+            Request::GetFeatures => line.push(b"FEATURES"),
+            Request::SetOffset { x, y } => line.push(b"OFFSET ").push_decimal(*x).push(b" ").push_decimal(*y),
+            Request::GetWriter { x, y } => line.push(b"WHO ").push_decimal(*x).push(b" ").push_decimal(*y),
+        };
+        line.push(b"\n");
+        line
+    }
+
     /// Write the binary representation of this request into the given writer
     pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(self.encode().as_bytes())
+    }
+
+    /// Encode this request as a binary *PB* command if it is a [`Request::SetPixel`] that can be represented that way
+    fn encode_pb(&self) -> Option<[u8; PB_LEN]> {
         match self {
-            Request::Help(topic) => match topic {
-                HelpTopic::General => writer.write_all("HELP\n".as_bytes()),
-                HelpTopic::Size => writer.write_all("HELP SIZE\n".as_bytes()),
-                HelpTopic::Px => writer.write_all("HELP PX\n".as_bytes()),
-            },
-            Request::GetSize => writer.write_all("SIZE\n".as_bytes()),
-            Request::GetPixel { x, y } => writer.write_all(format!("PX {} {}\n", x, y).as_bytes()),
-            Request::SetPixel { x, y, color } => {
-                writer.write_all(format!("PX {} {} {:X}\n", x, y, color).as_bytes())
+            Request::SetPixel { x, y, color } if *x <= u16::MAX as usize && *y <= u16::MAX as usize => {
+                let mut buf = [0u8; PB_LEN];
+                buf[0..2].copy_from_slice(PB_PREFIX);
+                buf[2..4].copy_from_slice(&(*x as u16).to_le_bytes());
+                buf[4..6].copy_from_slice(&(*y as u16).to_le_bytes());
+                buf[6..10].copy_from_slice(&Into::<[u8; 4]>::into(*color));
+                Some(buf)
             }
+            _ => None,
+        }
+    }
+
+    /// Write this request into the given writer, using the compact binary *PB* encoding if possible.
+    ///
+    /// Only [`Request::SetPixel`] requests whose coordinates fit into a u16 can be encoded in binary.
+    /// All other requests are written in their normal text representation like [`write()`](Self::write) does.
+    pub fn write_binary(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self.encode_pb() {
+            Some(buf) => writer.write_all(&buf),
+            None => self.write(writer),
+        }
+    }
+
+    /// Write this request into the given async writer, using the compact binary *PB* encoding if possible.
+    ///
+    /// See [`write_binary()`](Self::write_binary) for which requests can be encoded in binary.
+    pub async fn write_binary_async(&self, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
+        match self.encode_pb() {
+            Some(buf) => writer.write_all(&buf).await,
+            None => self.write_async(writer).await,
         }
     }
 
     /// Write the binary representation of this request into the given async writer
     pub async fn write_async(&self, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
-        match self {
-            Request::Help(topic) => match topic {
-                HelpTopic::General => writer.write_all("HELP\n".as_bytes()).await,
-                HelpTopic::Size => writer.write_all("HELP SIZE\n".as_bytes()).await,
-                HelpTopic::Px => writer.write_all("HELP PX\n".as_bytes()).await,
-            },
-            Request::GetSize => writer.write_all("SIZE\n".as_bytes()).await,
-            Request::GetPixel { x, y } => writer.write_all(format!("PX {} {}\n", x, y).as_bytes()).await,
-            Request::SetPixel { x, y, color } => {
-                writer
-                    .write_all(format!("PX {} {} {:X}\n", x, y, color).as_bytes())
-                    .await
-            }
-        }
+        writer.write_all(self.encode().as_bytes()).await
     }
 }
 
 impl Display for Request {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Request::Help(topic) => match topic {
-                HelpTopic::General => f.write_str("HELP"),
-                HelpTopic::Size => f.write_str("HELP SIZE"),
-                HelpTopic::Px => f.write_str("HELP PX"),
-            },
-            Request::GetSize => f.write_str("SIZE"),
-            Request::GetPixel { x, y } => f.write_fmt(format_args!("PX {} {}", x, y)),
-            Request::SetPixel { x, y, color } => f.write_fmt(format_args!("PX {} {} {:X}", x, y, color)),
-        }
+        let line = self.encode();
+        let line = line.as_bytes();
+        let line = std::str::from_utf8(&line[..line.len() - 1]).map_err(|_| std::fmt::Error)?;
+        f.write_str(line)
     }
 }
 
 /// The response of a pixelflut server
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Response {
     /// Help about a specific topic with more information about that topic
     Help(HelpTopic),
@@ -114,58 +400,311 @@ pub enum Response {
         /// The color of the pixel
         color: Color,
     },
+    /// Color data of all pixels in a rectangle
+    RectData {
+        /// The x coordinate of the rectangles top left corner
+        x: usize,
+        /// The y coordinate of the rectangles top left corner
+        y: usize,
+        /// The width of the rectangle
+        width: usize,
+        /// The height of the rectangle
+        height: usize,
+        /// The colors of all pixels in the rectangle, row by row
+        colors: Vec<Color>,
+    },
+    /// The content of the whole canvas
+    State {
+        /// The encoding which was requested and in which the data is sent over the wire
+        encoding: StateEncoding,
+        /// Rgb data of all pixels, row by row
+        ///
+        /// This is always the decoded data, even if it is transferred in base64 encoding.
+        data: Bytes,
+    },
+    /// The capabilities of the server
+    Features(Features),
+    /// Who last wrote a specific pixel
+    WriterData {
+        /// X coordinate of the pixel
+        x: usize,
+        /// Y coordinate of the pixel
+        y: usize,
+        /// The last writer of the pixel or `None` if it was not written since the server started tracking writers
+        writer: Option<PixelWriter>,
+    },
+    /// The server could not handle a request
+    Error {
+        /// What kind of error occurred
+        code: ErrorCode,
+        /// A human readable description of the error
+        message: String,
+    },
 }
 
 impl Response {
-    /// Write the binary representation of this response into the given writer
-    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+    /// Construct an error response from any error message
+    pub fn error(code: ErrorCode, message: impl Display) -> Self {
+        Response::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Response {
+    /// Assemble the text representation of this response including its trailing newline
+    ///
+    /// Data which follows the line as it is, like help texts or the binary canvas data of a *STATE* response, is
+    /// returned separately so that it does not need to be copied.
+    fn encode(&self) -> (LineBuf, &[u8]) {
+        let mut line = LineBuf::new();
+        let mut payload: &[u8] = &[];
         match self {
-            Response::Help(topic) => match topic {
-                HelpTopic::General => writer.write_all(texts::HELP_GENERAL.as_bytes()),
-                HelpTopic::Size => writer.write_all(texts::HELP_SIZE.as_bytes()),
-                HelpTopic::Px => writer.write_all(texts::HELP_PX.as_bytes()),
-            },
+            Response::Help(topic) => return (line, topic.text().as_bytes()),
             Response::Size { width, height } => {
-                writer.write_all(format!("SIZE {} {}\n", width, height).as_bytes())
+                line.push(b"SIZE ")
+                    .push_decimal(*width)
+                    .push(b" ")
+                    .push_decimal(*height);
             }
             Response::PxData { x, y, color } => {
-                writer.write_all(format!("PX {} {} {:X}\n", x, y, color).as_bytes())
+                line.push(b"PX ")
+                    .push_decimal(*x)
+                    .push(b" ")
+                    .push_decimal(*y)
+                    .push(b" ")
+                    .push_color(*color);
+            }
+            Response::RectData {
+                x,
+                y,
+                width,
+                height,
+                colors,
+            } => {
+                line.reserve(colors.len() * 6 + 64)
+                    .push(b"RECT ")
+                    .push_decimal(*x)
+                    .push(b" ")
+                    .push_decimal(*y)
+                    .push(b" ")
+                    .push_decimal(*width)
+                    .push(b" ")
+                    .push_decimal(*height)
+                    .push(b" ");
+                for color in colors {
+                    line.push_rgb(*color);
+                }
+            }
+            Response::Features(features) => {
+                line.push(b"FEATURES commands=")
+                    .push_list(&features.commands)
+                    .push(b" colors=")
+                    .push_list(&features.colors);
+                if let Some(max_line_len) = features.max_line_len {
+                    line.push(b" maxline=").push_decimal(max_line_len);
+                }
+                if let Some(rate_limit) = features.rate_limit {
+                    line.push(b" ratelimit=").push_decimal(rate_limit);
+                }
+                line.push(b" transports=").push_list(&features.transports);
+            }
+            Response::WriterData { x, y, writer } => {
+                line.push(b"WHO ")
+                    .push_decimal(*x)
+                    .push(b" ")
+                    .push_decimal(*y)
+                    .push(b" ");
+                match writer {
+                    Some(writer) => line
+                        .push(writer.identity.as_bytes())
+                        .push(b" ")
+                        .push_decimal(writer.unix_time() as usize),
+                    None => line.push(b"-"),
+                };
+            }
+            Response::Error { code, message } => {
+                line.push(b"ERR ")
+                    .push(code.name().as_bytes())
+                    .push(b" ")
+                    .push(message.as_bytes());
+            }
+            Response::State { encoding, data } => {
+                line.push(b"STATE ").push(encoding.name().as_bytes()).push(b" ");
+                match encoding {
+                    StateEncoding::Rgb => {
+                        line.push_decimal(data.len());
+                        payload = data;
+                    }
+                    StateEncoding::RgbBase64 => {
+                        line.push_base64(data);
+                    }
+                }
             }
         }
+        line.push(b"\n");
+        (line, payload)
+    }
+
+    /// Write the binary representation of this response into the given writer
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let (line, payload) = self.encode();
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(payload)
     }
 
     /// Write the binary representation of this response into the given async writer
     pub async fn write_async(&self, writer: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
-        match self {
-            Response::Help(topic) => match topic {
-                HelpTopic::General => writer.write_all(texts::HELP_GENERAL.as_bytes()).await,
-                HelpTopic::Size => writer.write_all(texts::HELP_SIZE.as_bytes()).await,
-                HelpTopic::Px => writer.write_all(texts::HELP_PX.as_bytes()).await,
-            },
-            Response::Size { width, height } => {
-                writer
-                    .write_all(format!("SIZE {} {}\n", width, height).as_bytes())
-                    .await
-            }
-            Response::PxData { x, y, color } => {
-                writer
-                    .write_all(format!("PX {} {} {:X}\n", x, y, color).as_bytes())
-                    .await
-            }
-        }
+        let (line, payload) = self.encode();
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(payload).await
     }
 }
 
+/// Formats the response like it is sent over the wire but without its trailing newline
+///
+/// Help texts are shown completely while the binary data of a *STATE* response is left out.
 impl Display for Response {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Response::Help(topic) => match topic {
-                HelpTopic::General => f.write_str(texts::HELP_GENERAL),
-                HelpTopic::Size => f.write_str(texts::HELP_SIZE),
-                HelpTopic::Px => f.write_str(texts::HELP_PX),
-            },
-            Response::Size { width, height } => f.write_fmt(format_args!("SIZE {} {}", width, height)),
-            Response::PxData { x, y, color } => f.write_fmt(format_args!("PX {} {} {:X}", x, y, color)),
+        if let Response::Help(topic) = self {
+            return f.write_str(topic.text());
+        }
+        let (line, _) = self.encode();
+        let line = line.as_bytes();
+        let line = std::str::from_utf8(&line[..line.len() - 1]).map_err(|_| std::fmt::Error)?;
+        f.write_str(line)
+    }
+}
+
+/// The number of bytes that a [`LineBuf`] can hold on the stack
+///
+/// This is enough for every request and for the responses which are sent most often, like *PX* and *SIZE*.
+const LINE_BUF_LEN: usize = 128;
+
+/// A buffer in which a single protocol line is assembled
+///
+/// Lines which fit into [`LINE_BUF_LEN`] bytes are assembled on the stack without any heap allocations.
+/// Longer ones, like the responses to *GETRECT*, are moved onto the heap once they outgrow the stack.
+#[derive(Debug, Clone)]
+struct LineBuf {
+    buf: [u8; LINE_BUF_LEN],
+    len: usize,
+    spilled: Vec<u8>,
+}
+
+impl LineBuf {
+    #[inline(always)]
+    fn new() -> Self {
+        Self {
+            buf: [0; LINE_BUF_LEN],
+            len: 0,
+            spilled: Vec::new(),
+        }
+    }
+
+    /// Make room for at least `additional` more bytes so that long lines are only allocated once
+    fn reserve(&mut self, additional: usize) -> &mut Self {
+        if !self.spilled.is_empty() || self.len + additional > LINE_BUF_LEN {
+            self.spill();
+            self.spilled.reserve(additional);
+        }
+        self
+    }
+
+    /// Move the line onto the heap so that it can grow beyond the stack buffer
+    fn spill(&mut self) {
+        if self.spilled.is_empty() {
+            self.spilled.extend_from_slice(&self.buf[..self.len]);
+        }
+    }
+
+    /// Append raw bytes to the line
+    #[inline(always)]
+    fn push(&mut self, bytes: &[u8]) -> &mut Self {
+        if self.spilled.is_empty() && self.len + bytes.len() <= LINE_BUF_LEN {
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        } else {
+            self.spill();
+            self.spilled.extend_from_slice(bytes);
+        }
+        self
+    }
+
+    /// Append the decimal representation of a number to the line
+    #[inline(always)]
+    fn push_decimal(&mut self, mut value: usize) -> &mut Self {
+        let mut digits = [0u8; 20];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.push(&digits[i..])
+    }
+
+    /// Append a color in the same notation as its [`UpperHex`](std::fmt::UpperHex) implementation produces
+    #[inline(always)]
+    fn push_color(&mut self, color: Color) -> &mut Self {
+        let channels: [u8; 4] = color.into();
+        let len = if color.is_opaque() { 3 } else { 4 };
+        self.push_hex(&channels[..len])
+    }
+
+    /// Append the rgb channels of a color as exactly 6 hex digits, ignoring its transparency
+    #[inline(always)]
+    fn push_rgb(&mut self, color: Color) -> &mut Self {
+        self.push_hex(&<[u8; 3]>::from(color))
+    }
+
+    /// Append two upper case hex digits per byte
+    #[inline(always)]
+    fn push_hex(&mut self, bytes: &[u8]) -> &mut Self {
+        const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+        for byte in bytes {
+            self.push(&[
+                HEX_DIGITS[(byte >> 4) as usize],
+                HEX_DIGITS[(byte & 0xF) as usize],
+            ]);
+        }
+        self
+    }
+
+    /// Append the items of a list separated by commas
+    fn push_list(&mut self, items: &[String]) -> &mut Self {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.push(b",");
+            }
+            self.push(item.as_bytes());
+        }
+        self
+    }
+
+    /// Append the base64 encoding of binary data
+    fn push_base64(&mut self, data: &[u8]) -> &mut Self {
+        let encoded_len = base64::encoded_len(data.len(), true).expect("data is too large to be encoded");
+        self.spill();
+        let start = self.spilled.len();
+        self.spilled.resize(start + encoded_len, 0);
+        BASE64_STANDARD
+            .encode_slice(data, &mut self.spilled[start..])
+            .expect("buffer is large enough for the encoded data");
+        self
+    }
+
+    /// The bytes that have been assembled so far
+    #[inline(always)]
+    fn as_bytes(&self) -> &[u8] {
+        if self.spilled.is_empty() {
+            &self.buf[..self.len]
+        } else {
+            &self.spilled
         }
     }
 }
@@ -1,9 +1,13 @@
 //! Definitions for the network protocol
 
+mod codec;
 mod compliant_parser;
 mod dtypes;
 
 pub use dtypes::*;
 
-pub use compliant_parser::{parse_request_bin, parse_request_str};
-pub use compliant_parser::{parse_response_bin, parse_response_str};
+pub use codec::{FrameError, RequestCodec, ResponseCodec};
+
+pub use compliant_parser::{next_request_len, parse_request_bin, parse_request_str, parse_request_with_mode};
+pub use compliant_parser::{parse_response_bin, parse_response_str, response_payload_len};
+pub use compliant_parser::{ParseErr, ParserMode, UnknownParserModeError};
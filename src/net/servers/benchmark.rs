@@ -1,25 +1,131 @@
-use crate::pixmap::{Pixmap, SharedPixmap};
+use crate::net::protocol::{next_request_len, parse_request_bin, Response};
+use crate::net::servers::ConnectionState;
+use crate::pixmap::{Color, Pixmap, SharedPixmap};
 use std::hint::black_box;
 use test::Bencher;
 
 #[bench]
 fn bench_1000_requests(b: &mut Bencher) {
     let pixmap = SharedPixmap::new(Pixmap::new(800, 600).unwrap());
+    let mut state = ConnectionState::default();
 
     // run the benchmark
     b.iter(|| {
         #[allow(clippy::needless_range_loop)]
         for i in 0..COMMANDS.len() {
             let line = black_box(COMMANDS[i]);
-            let result = super::handle_request(line, &pixmap);
-            assert_eq!(result, Ok(None));
+            let result = super::handle_request(line, &pixmap, &mut state);
+            assert_eq!(result, None);
         }
+        state.flush_changes(&pixmap);
+    })
+}
+
+#[bench]
+fn bench_1000_requests_pb(b: &mut Bencher) {
+    let pixmap = SharedPixmap::new(Pixmap::new(800, 600).unwrap());
+    let mut state = ConnectionState::default();
+    let commands = COMMANDS
+        .iter()
+        .map(|line| {
+            let mut buf = Vec::new();
+            parse_request_bin(line).unwrap().write_binary(&mut buf).unwrap();
+            buf
+        })
+        .collect::<Vec<_>>();
+
+    // run the benchmark
+    b.iter(|| {
+        for line in &commands {
+            let result = super::handle_request(black_box(line), &pixmap, &mut state);
+            assert_eq!(result, None);
+        }
+        state.flush_changes(&pixmap);
+    })
+}
+
+#[bench]
+fn bench_1000_requests_stream(b: &mut Bencher) {
+    let pixmap = SharedPixmap::new(Pixmap::new(800, 600).unwrap());
+    let mut state = ConnectionState::default();
+    let stream = COMMANDS.join(&b'\n');
+
+    // run the benchmark the same way in which stream based servers split their input
+    b.iter(|| {
+        let mut buf = black_box(&stream[..]);
+        while let Some(len) = next_request_len(buf) {
+            let result = super::handle_request(&buf[..len], &pixmap, &mut state);
+            assert_eq!(result, None);
+            buf = &buf[len..];
+        }
+        state.flush_changes(&pixmap);
+    })
+}
+
+// The following benchmarks measure the throughput of the pixmap storage backend.
+// Run them once with and once without the `unsafe-storage` feature to compare both backends.
+
+#[bench]
+fn bench_1000_requests_parallel(b: &mut Bencher) {
+    const THREADS: usize = 4;
+    let pixmap = SharedPixmap::new(Pixmap::new(800, 600).unwrap());
+
+    // run the benchmark with multiple connections drawing onto the same pixmap at the same time
+    b.iter(|| {
+        std::thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    let mut state = ConnectionState::default();
+                    for line in COMMANDS {
+                        let result = super::handle_request(black_box(line), &pixmap, &mut state);
+                        assert_eq!(result, None);
+                    }
+                    state.flush_changes(&pixmap);
+                });
+            }
+        })
+    })
+}
+
+#[bench]
+fn bench_fill_rect(b: &mut Bencher) {
+    let pixmap = Pixmap::new(800, 600).unwrap();
+    let color = Color::from((0xAB, 0xCD, 0xEF));
+
+    // run the benchmark
+    b.iter(|| {
+        pixmap
+            .fill_rect(black_box(100), black_box(100), 200, 200, color)
+            .unwrap();
+    })
+}
+
+#[bench]
+fn bench_rgb_data(b: &mut Bencher) {
+    let pixmap = Pixmap::new(800, 600).unwrap();
+
+    // run the benchmark the same way in which sinks take a snapshot of the pixmap
+    b.iter(|| black_box(pixmap.rgb_data()))
+}
+
+#[bench]
+fn bench_1000_responses(b: &mut Bencher) {
+    let mut buf = Vec::with_capacity(32 * 1024);
+    let color = Color::from((0xAB, 0xCD, 0xEF));
+
+    // run the benchmark
+    b.iter(|| {
+        buf.clear();
+        for i in 0..1000 {
+            Response::PxData { x: i, y: i, color }.write(&mut buf).unwrap();
+        }
+        black_box(&buf);
     })
 }
 
 // generated in python with:
 // lines = (f"b\"PX {random.randrange(0, 800)} {random.randrange(0, 600)} {random.randrange(0, 0xFFFFFF):x}\",\n" for _ in range(0, 1000))
-const COMMANDS: &'static [&'static [u8]] = &[
+const COMMANDS: &[&[u8]] = &[
     b"PX 99 367 67ed98",
     b"PX 94 464 39b467",
     b"PX 235 311 eb4937",
@@ -15,8 +15,12 @@ mod unix_sock_server;
 #[cfg(feature = "ws")]
 mod ws_server;
 
-use crate::net::protocol::{parse_request_bin, Request, Response};
-use crate::pixmap::SharedPixmap;
+use crate::net::protocol::{
+    parse_request_with_mode, ErrorCode, Features, FrameError, ParserMode, PixelWriter, Request, Response,
+};
+use crate::pixmap::{
+    ChangeRecorder, InvalidCoordinatesError, PixelChange, PixmapBatch, SharedPixmap, WriterId,
+};
 
 #[cfg(feature = "tcp")]
 pub use tcp_server::{TcpServer, TcpServerOptions};
@@ -26,13 +30,141 @@ pub use unix_sock_server::{UnixSocketOptions, UnixSocketServer};
 #[cfg(feature = "ws")]
 pub use ws_server::{WsServer, WsServerOptions};
 
-/// Handle a single request
+/// The maximum length of a single request line that stream based servers buffer before discarding it
+const MAX_LINE_LEN: usize = 64;
+
+/// Describe the capabilities of this server as they are announced to clients in response to a *FEATURES* request
+///
+/// *WHO* is only announced to operators if the pixmap keeps track of its writers and *STATE* only on transports
+/// which can carry its response.
+fn server_features(pixmap: &SharedPixmap, state: &ConnectionState) -> Features {
+    let to_strings = |items: &[&str]| items.iter().map(|i| i.to_string()).collect();
+    let mut transports = Vec::new();
+    if cfg!(feature = "tcp") {
+        transports.push("tcp".to_string());
+    }
+    if cfg!(feature = "udp") {
+        transports.push("udp".to_string());
+    }
+    transports.push("unix".to_string());
+    if cfg!(feature = "ws") {
+        transports.push("ws".to_string());
+    }
+
+    let mut commands: Vec<String> = to_strings(&["PB", "RECT", "OFFSET", "FEATURES"]);
+    if !state.connectionless {
+        commands.extend(to_strings(&["GETRECT", "STATE"]));
+    }
+    if state.operator && pixmap.has_attribution() {
+        commands.push("WHO".to_string());
+    }
+
+    Features {
+        commands,
+        colors: to_strings(&["rgb", "rgba", "rgb-short", "gray"]),
+        max_line_len: Some(MAX_LINE_LEN),
+        rate_limit: None,
+        transports,
+    }
+}
+
+/// State that is kept by servers for the duration of one client connection
+///
+/// For connectionless transports like UDP, the state only lives for as long as one datagram is processed.
+///
+/// Servers must call [`flush_changes()`](Self::flush_changes) after handling a chunk of requests.
+#[derive(Debug, Default)]
+struct ConnectionState {
+    /// Offset that is added to the coordinates of all pixel requests
+    offset: (usize, usize),
+    /// How strictly requests on this connection are parsed, as configured on the listener
+    parser_mode: ParserMode,
+    /// Changes to the pixmap which were made by this connection but not yet published to subscribers
+    changes: ChangeRecorder,
+    /// To whom writes from this connection are attributed
+    writer: WriterId,
+    /// How many pixels this connection wrote since the last flush
+    written_pixels: u64,
+    /// Whether responses are sent as single datagrams to a sender address which is not verified
+    connectionless: bool,
+    /// Whether the listener is reserved for operators who may see what other clients did
+    operator: bool,
+    /// The batch through which the pixmap is accessed until the next flush
+    batch: Option<PixmapBatch>,
+}
+
+impl ConnectionState {
+    /// Create the initial state of a connection that was accepted by a listener with the given parser mode
+    fn new(parser_mode: ParserMode) -> Self {
+        Self {
+            parser_mode,
+            ..Self::default()
+        }
+    }
+
+    /// Mark the connection as one of a connectionless transport
+    ///
+    /// Requests whose responses are much larger than the request itself are rejected on such transports because
+    /// a spoofed sender address would turn them into an amplification attack on somebody else.
+    #[cfg_attr(not(feature = "udp"), allow(dead_code))]
+    fn connectionless(self) -> Self {
+        Self {
+            connectionless: true,
+            ..self
+        }
+    }
+
+    /// Allow the connection to use commands which are reserved for operators, like *WHO*
+    fn operator(self, operator: bool) -> Self {
+        Self { operator, ..self }
+    }
+
+    /// Attribute all writes from this connection to the client with the given identity
+    ///
+    /// The identity is usually the address of the client.
+    /// If no more writers can be registered, the writes are not attributed to anybody.
+    fn with_writer(self, pixmap: &SharedPixmap, identity: &str) -> Self {
+        let writer = pixmap.register_writer(identity).unwrap_or_else(|e| {
+            tracing::warn!("Not attributing writes of {identity}: {e}");
+            WriterId::UNKNOWN
+        });
+        Self { writer, ..self }
+    }
+
+    /// Translate coordinates from the clients point of view into coordinates on the pixmap
+    #[inline(always)]
+    fn translate(&self, x: usize, y: usize) -> (usize, usize) {
+        (x.saturating_add(self.offset.0), y.saturating_add(self.offset.1))
+    }
+
+    /// The batch through which the requests of the current chunk access the pixmap
+    #[inline(always)]
+    fn batch(&mut self, pixmap: &SharedPixmap) -> &mut PixmapBatch {
+        self.batch.get_or_insert_with(|| pixmap.batch(self.writer))
+    }
+
+    /// Publish the changes which were made since the last call to subscribers of the pixmap
+    ///
+    /// This also ends the current batch so that the next chunk of requests sees resizes and changes to the
+    /// protected regions.
+    fn flush_changes(&mut self, pixmap: &SharedPixmap) {
+        self.batch = None;
+        self.changes.flush(pixmap);
+        if self.written_pixels > 0 {
+            pixmap.count_written_pixels(std::mem::take(&mut self.written_pixels));
+        }
+    }
+}
+
+/// Handle a single request line
 ///
-/// This is the core request handling method that is run by all servers.
+/// This is the core request handling method that is run by servers which receive whole messages from their
+/// transport.
 /// It parses requests, handles them and generates responses.
 /// The actual IO is left to the specific server though.
+/// Errors are reported to the client as a [`Response::Error`].
 #[allow(unused)]
-fn handle_request(line: &[u8], pixmap: &SharedPixmap) -> Result<Option<Response>, String> {
+fn handle_request(line: &[u8], pixmap: &SharedPixmap, state: &mut ConnectionState) -> Option<Response> {
     tracing::trace!(
         "Handling single request {:?}",
         match line.is_ascii() {
@@ -41,23 +173,472 @@ fn handle_request(line: &[u8], pixmap: &SharedPixmap) -> Result<Option<Response>
         }
     );
 
-    let parse_result = parse_request_bin(line);
-    match parse_result {
-        Err(e) => Err(e.to_string()),
-        Ok(request) => match request {
-            Request::Help(topic) => Ok(Some(Response::Help(topic))),
-            Request::GetSize => {
-                let (width, height) = pixmap.get_size();
-                Ok(Some(Response::Size { width, height }))
-            }
-            Request::GetPixel { x, y } => {
-                let color = pixmap.get_pixel(x, y).map_err(|e| format!("{}", e))?;
-                Ok(Some(Response::PxData { x, y, color }))
+    let request = parse_request_with_mode(line, state.parser_mode);
+    handle_frame(request.map_err(FrameError::from), pixmap, state)
+}
+
+/// Handle a single frame that was produced by a [`RequestCodec`](crate::net::protocol::RequestCodec)
+///
+/// Stream based servers use this instead of [`handle_request()`] because the codec already parsed the request.
+fn handle_frame(
+    frame: Result<Request, FrameError>,
+    pixmap: &SharedPixmap,
+    state: &mut ConnectionState,
+) -> Option<Response> {
+    match frame {
+        Ok(request) => process_request(request, pixmap, state).unwrap_or_else(Some),
+        Err(e) => {
+            tracing::debug!("Received invalid request: {e}");
+            Some(Response::error(e.into(), e))
+        }
+    }
+}
+
+/// Execute a single request, returning an error response if that is not possible
+fn process_request(
+    request: Request,
+    pixmap: &SharedPixmap,
+    state: &mut ConnectionState,
+) -> Result<Option<Response>, Response> {
+    match request {
+        Request::Help(topic) => Ok(Some(Response::Help(topic))),
+        Request::GetSize => {
+            let (width, height) = pixmap.get_size();
+            Ok(Some(Response::Size { width, height }))
+        }
+        Request::GetPixel { x, y } => {
+            let (px, py) = state.translate(x, y);
+            let color = state.batch(pixmap).get_pixel(px, py).map_err(out_of_bounds)?;
+            Ok(Some(Response::PxData { x, y, color }))
+        }
+        Request::SetPixel { x, y, color } => {
+            let (x, y) = state.translate(x, y);
+            let batch = state.batch(pixmap);
+            check_unprotected(batch, x, y, 1, 1)?;
+            if color.is_opaque() {
+                batch.set_pixel(x, y, color).map_err(out_of_bounds)?;
+            } else {
+                batch.blend_pixel(x, y, color).map_err(out_of_bounds)?;
             }
-            Request::SetPixel { x, y, color } => {
-                pixmap.set_pixel(x, y, color).map_err(|e| format!("{}", e))?;
-                Ok(None)
+            state.changes.record(pixmap, PixelChange::Pixel { x, y, color });
+            state.written_pixels += 1;
+            Ok(None)
+        }
+        Request::FillRect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        } => {
+            let (x, y) = state.translate(x, y);
+            let batch = state.batch(pixmap);
+            check_unprotected(batch, x, y, width, height)?;
+            batch
+                .fill_rect(x, y, width, height, color)
+                .map_err(out_of_bounds)?;
+            state.changes.record(
+                pixmap,
+                PixelChange::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                },
+            );
+            state.written_pixels = state
+                .written_pixels
+                .saturating_add(width.saturating_mul(height) as u64);
+            Ok(None)
+        }
+        Request::GetRect { .. } if state.connectionless => Err(Response::error(
+            ErrorCode::UnknownCommand,
+            "GETRECT is not available on connectionless transports",
+        )),
+        Request::GetRect { x, y, width, height } => {
+            let (px, py) = state.translate(x, y);
+            let colors = state
+                .batch(pixmap)
+                .copy_rect(px, py, width, height)
+                .map_err(out_of_bounds)?;
+            Ok(Some(Response::RectData {
+                x,
+                y,
+                width,
+                height,
+                colors,
+            }))
+        }
+        Request::GetState(_) if state.connectionless => Err(Response::error(
+            ErrorCode::UnknownCommand,
+            "STATE is not available on connectionless transports",
+        )),
+        Request::GetState(encoding) => Ok(Some(Response::State {
+            encoding,
+            data: pixmap.rgb_data().into(),
+        })),
+        Request::GetFeatures => Ok(Some(Response::Features(server_features(pixmap, state)))),
+        Request::SetOffset { x, y } => {
+            state.offset = (x, y);
+            Ok(None)
+        }
+        Request::GetWriter { .. } if !state.operator => Err(Response::error(
+            ErrorCode::UnknownCommand,
+            "WHO is only available on operator listeners",
+        )),
+        Request::GetWriter { x, y } => {
+            if !pixmap.has_attribution() {
+                return Err(Response::error(
+                    ErrorCode::UnknownCommand,
+                    "this server does not keep track of who writes pixels",
+                ));
             }
-        },
+            let (px, py) = state.translate(x, y);
+            let writer = state
+                .batch(pixmap)
+                .last_write(px, py)
+                .map_err(out_of_bounds)?
+                .map(|write| PixelWriter {
+                    identity: pixmap
+                        .writer_identity(write.writer)
+                        .unwrap_or_else(|| PixelWriter::UNKNOWN_IDENTITY.to_string()),
+                    time: write.time,
+                });
+            Ok(Some(Response::WriterData { x, y, writer }))
+        }
+    }
+}
+
+/// Reject writes to a rectangle which overlaps a region that the operator protected from clients
+///
+/// Such writes are rejected as a whole so that clients do not end up with partially drawn rectangles.
+#[inline(always)]
+fn check_unprotected(
+    batch: &PixmapBatch,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Result<(), Response> {
+    if batch.is_protected(x, y, width, height) {
+        return Err(Response::error(
+            ErrorCode::Protected,
+            format_args!("{width}x{height} pixels at {x},{y} overlap a protected region"),
+        ));
+    }
+    Ok(())
+}
+
+/// Convert an error about invalid coordinates into the error response that is sent to clients
+fn out_of_bounds(e: InvalidCoordinatesError) -> Response {
+    Response::error(ErrorCode::OutOfBounds, e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::protocol::StateEncoding;
+    use crate::pixmap::{Color, Pixmap, SubscriptionError};
+
+    #[test]
+    fn test_offset_is_applied_per_connection() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+        let mut other_state = ConnectionState::default();
+
+        assert_eq!(handle_request(b"OFFSET 10 5\n", &pixmap, &mut state), None);
+        assert_eq!(handle_request(b"PX 1 2 ABCDEF\n", &pixmap, &mut state), None);
+
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        assert_eq!(pixmap.get_pixel(11, 7).unwrap(), color);
+        assert_eq!(
+            handle_request(b"PX 1 2\n", &pixmap, &mut state),
+            Some(Response::PxData { x: 1, y: 2, color })
+        );
+        assert_eq!(
+            handle_request(b"PX 11 7\n", &pixmap, &mut other_state),
+            Some(Response::PxData { x: 11, y: 7, color })
+        );
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+        let code = |line: &[u8], state: &mut ConnectionState| match handle_request(line, &pixmap, state) {
+            Some(Response::Error { code, .. }) => Some(code),
+            _ => None,
+        };
+
+        assert_eq!(code(b"FOO\n", &mut state), Some(ErrorCode::UnknownCommand));
+        assert_eq!(code(b"OFFSET 1 x\n", &mut state), Some(ErrorCode::InvalidCommand));
+        assert_eq!(code(b"PX 0 20\n", &mut state), Some(ErrorCode::OutOfBounds));
+        assert_eq!(code(b"PX 19 0\n", &mut state), None);
+
+        // protection applies from the next chunk of requests on
+        pixmap.protect_rect(0, 0, 5, 1);
+        assert_eq!(code(b"PX 3 0 FFFFFF\n", &mut state), None);
+        state.flush_changes(&pixmap);
+        assert_eq!(code(b"PX 4 0 FFFFFF\n", &mut state), Some(ErrorCode::Protected));
+        assert_eq!(
+            code(b"RECT 3 0 4 4 FFFFFF\n", &mut state),
+            Some(ErrorCode::Protected)
+        );
+        assert_eq!(code(b"PX 4 0\n", &mut state), None);
+        assert_eq!(code(b"PX 5 0 FFFFFF\n", &mut state), None);
+        assert_eq!(pixmap.get_pixel(4, 0).unwrap(), Color::default());
+    }
+
+    #[test]
+    fn test_activity_is_counted() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+        let client = pixmap.client_connected();
+        assert_eq!(pixmap.connected_clients(), 1);
+
+        handle_request(b"PX 1 1 FFFFFF\n", &pixmap, &mut state);
+        handle_request(b"RECT 0 0 2 3 FFFFFF\n", &pixmap, &mut state);
+        handle_request(b"PX 30 30 FFFFFF\n", &pixmap, &mut state);
+        assert_eq!(pixmap.written_pixels(), 0);
+        state.flush_changes(&pixmap);
+        assert_eq!(pixmap.written_pixels(), 7);
+
+        drop(client);
+        assert_eq!(pixmap.connected_clients(), 0);
+    }
+
+    #[test]
+    fn test_parser_mode_of_connection() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut strict = ConnectionState::new(ParserMode::Strict);
+        let mut lenient = ConnectionState::new(ParserMode::Lenient);
+
+        assert_eq!(handle_request(b"px 1 2 #ABCDEF\r\n", &pixmap, &mut lenient), None);
+        assert!(matches!(
+            handle_request(b"px 1 2 #ABCDEF\r\n", &pixmap, &mut strict),
+            Some(Response::Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            handle_request(b"PX 1 2 #ABCDEF\n", &pixmap, &mut strict),
+            Some(Response::Error {
+                code: ErrorCode::InvalidCommand,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_change_events() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap().with_change_events(2));
+        let mut subscriber = pixmap.subscribe_changes().unwrap();
+        let mut state = ConnectionState::default();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+
+        // changes are published in one batch when the connection flushes them
+        handle_request(b"OFFSET 1 1\n", &pixmap, &mut state);
+        handle_request(b"PX 1 2 ABCDEF\n", &pixmap, &mut state);
+        handle_request(b"PX 30 2 ABCDEF\n", &pixmap, &mut state);
+        handle_request(b"RECT 0 0 2 3 ABCDEF\n", &pixmap, &mut state);
+        assert_eq!(subscriber.try_recv(), Ok(None));
+        state.flush_changes(&pixmap);
+        assert_eq!(
+            subscriber.try_recv().unwrap().unwrap()[..],
+            [
+                PixelChange::Pixel { x: 2, y: 3, color },
+                PixelChange::Rect {
+                    x: 1,
+                    y: 1,
+                    width: 2,
+                    height: 3,
+                    color
+                }
+            ]
+        );
+
+        // subscribers which fall behind are told so
+        for _ in 0..3 {
+            handle_request(b"PX 0 0 ABCDEF\n", &pixmap, &mut state);
+            state.flush_changes(&pixmap);
+        }
+        assert_eq!(subscriber.try_recv(), Err(SubscriptionError::Lagged(1)));
+        assert_eq!(subscriber.try_recv().unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_who() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap().with_attribution());
+        let mut state = ConnectionState::default()
+            .operator(true)
+            .with_writer(&pixmap, "10.0.0.1");
+        let mut other_state = ConnectionState::default()
+            .operator(true)
+            .with_writer(&pixmap, "10.0.0.2");
+        let writer = |line: &[u8], state: &mut ConnectionState| match handle_request(line, &pixmap, state) {
+            Some(Response::WriterData { writer, .. }) => writer.map(|writer| writer.identity),
+            response => panic!("server did not respond with the writer of a pixel: {response:?}"),
+        };
+
+        assert_eq!(writer(b"WHO 1 2\n", &mut state), None);
+        handle_request(b"PX 1 2 ABCDEF\n", &pixmap, &mut state);
+        handle_request(b"RECT 2 2 2 2 ABCDEF80\n", &pixmap, &mut other_state);
+        pixmap.set_pixel(3, 3, Color::default()).unwrap();
+        assert_eq!(
+            writer(b"WHO 1 2\n", &mut other_state).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(writer(b"WHO 2 3\n", &mut state).as_deref(), Some("10.0.0.2"));
+        assert_eq!(writer(b"WHO 3 3\n", &mut state).as_deref(), Some("unknown"));
+
+        // the offset of the connection is applied
+        handle_request(b"OFFSET 1 1\n", &pixmap, &mut state);
+        assert_eq!(writer(b"WHO 0 1\n", &mut state).as_deref(), Some("10.0.0.1"));
+
+        // other clients may not see who wrote what
+        let mut client_state = ConnectionState::default();
+        assert!(matches!(
+            handle_request(b"WHO 1 2\n", &pixmap, &mut client_state),
+            Some(Response::Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+        let Some(Response::Features(features)) = handle_request(b"FEATURES\n", &pixmap, &mut client_state)
+        else {
+            panic!("server did not respond with its features")
+        };
+        assert!(!features.supports_command("WHO"));
+        let Some(Response::Features(features)) = handle_request(b"FEATURES\n", &pixmap, &mut state) else {
+            panic!("server did not respond with its features")
+        };
+        assert!(features.supports_command("WHO"));
+
+        // the command is rejected if writers are not tracked
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        assert!(matches!(
+            handle_request(b"WHO 1 2\n", &pixmap, &mut state),
+            Some(Response::Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_state() {
+        let pixmap = SharedPixmap::new(Pixmap::new(2, 2).unwrap());
+        let mut state = ConnectionState::default();
+        pixmap.set_pixel(1, 0, Color::from((0xAA, 0xBB, 0xCC))).unwrap();
+
+        let Some(Response::State { encoding, data }) = handle_request(b"STATE\n", &pixmap, &mut state) else {
+            panic!("server did not respond with canvas state")
+        };
+        assert_eq!(encoding, StateEncoding::Rgb);
+        assert_eq!(&data[..], &[0, 0, 0, 0xAA, 0xBB, 0xCC, 0, 0, 0, 0, 0, 0]);
+
+        // the response would not fit into a datagram and could be reflected onto a spoofed sender
+        let mut state = ConnectionState::default().connectionless();
+        assert!(matches!(
+            handle_request(b"STATE\n", &pixmap, &mut state),
+            Some(Response::Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+        let Some(Response::Features(features)) = handle_request(b"FEATURES\n", &pixmap, &mut state) else {
+            panic!("server did not respond with its features")
+        };
+        assert!(!features.supports_command("STATE"));
+    }
+
+    #[test]
+    fn test_get_rect() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        pixmap.set_pixel(12, 7, color).unwrap();
+
+        assert_eq!(handle_request(b"OFFSET 10 5\n", &pixmap, &mut state), None);
+        assert_eq!(
+            handle_request(b"GETRECT 1 1 2 2\n", &pixmap, &mut state),
+            Some(Response::RectData {
+                x: 1,
+                y: 1,
+                width: 2,
+                height: 2,
+                colors: vec![Color::default(), Color::default(), Color::default(), color],
+            })
+        );
+        assert!(matches!(
+            handle_request(b"GETRECT 9 0 2 1\n", &pixmap, &mut state),
+            Some(Response::Error {
+                code: ErrorCode::OutOfBounds,
+                ..
+            })
+        ));
+
+        // a short request could otherwise be reflected as a large response onto a spoofed sender
+        let mut state = ConnectionState::default().connectionless();
+        assert!(matches!(
+            handle_request(b"GETRECT 0 0 20 20\n", &pixmap, &mut state),
+            Some(Response::Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+        let Some(Response::Features(features)) = handle_request(b"FEATURES\n", &pixmap, &mut state) else {
+            panic!("server did not respond with its features")
+        };
+        assert!(!features.supports_command("GETRECT"));
+    }
+
+    #[test]
+    fn test_features() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+
+        let Some(Response::Features(features)) = handle_request(b"FEATURES\n", &pixmap, &mut state) else {
+            panic!("server did not respond with its features")
+        };
+        assert!(features.supports_command("PB"));
+        assert!(features.supports_command("rect"));
+        assert!(features.supports_color("rgba"));
+        assert_eq!(features.max_line_len, Some(MAX_LINE_LEN));
+        assert_eq!(
+            handle_request(b"CAPS\n", &pixmap, &mut state),
+            Some(Response::Features(features))
+        );
+    }
+
+    #[test]
+    fn test_rect() {
+        let pixmap = SharedPixmap::new(Pixmap::new(20, 20).unwrap());
+        let mut state = ConnectionState::default();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+
+        assert_eq!(
+            handle_request(b"RECT 2 3 4 5 ABCDEF\n", &pixmap, &mut state),
+            None
+        );
+        assert_eq!(pixmap.get_pixel(2, 3).unwrap(), color);
+        assert_eq!(pixmap.get_pixel(5, 7).unwrap(), color);
+        assert_ne!(pixmap.get_pixel(6, 7).unwrap(), color);
+        assert_ne!(pixmap.get_pixel(5, 8).unwrap(), color);
+
+        // empty rectangles at the edge of the canvas are accepted
+        assert_eq!(
+            handle_request(b"RECT 20 20 0 0 000000\n", &pixmap, &mut state),
+            None
+        );
+        assert!(matches!(
+            handle_request(b"RECT 18 0 4 1 000000\n", &pixmap, &mut state),
+            Some(Response::Error {
+                code: ErrorCode::OutOfBounds,
+                ..
+            })
+        ));
     }
 }
@@ -1,19 +1,24 @@
-use crate::net::servers::GenServer;
+use crate::net::protocol::{ParserMode, RequestCodec, ResponseCodec};
+use crate::net::servers::{ConnectionState, GenServer, MAX_LINE_LEN};
 use crate::pixmap::SharedPixmap;
 use crate::DaemonResult;
 use async_trait::async_trait;
-use bytes::{BufMut, BytesMut};
-use std::io::Write;
+use bytes::BytesMut;
 use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::task::{AbortHandle, JoinSet};
+use tokio_util::codec::{Decoder, Encoder};
 
 /// Options with which the `TcpServer` is configured
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct TcpServerOptions {
     /// The address to which the server binds
     pub bind_addr: SocketAddr,
+    /// How strictly requests are checked against the protocol syntax
+    pub parser_mode: ParserMode,
+    /// Whether clients may use commands which are reserved for operators, like *WHO*
+    pub operator: bool,
 }
 
 /// A server implementation using TCP to transport pixelflut messages.
@@ -24,29 +29,43 @@ pub struct TcpServer {
 
 impl TcpServer {
     #[tracing::instrument(skip_all)]
-    async fn handle_listener(listener: TcpListener, pixmap: SharedPixmap) -> anyhow::Result<!> {
+    async fn handle_listener(
+        listener: TcpListener,
+        pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
+    ) -> anyhow::Result<!> {
         loop {
             let (stream, remote_addr) = listener.accept().await?;
             let pixmap = pixmap.clone();
             tokio::spawn(async move {
-                if let Err(e) = TcpServer::handle_connection(stream, remote_addr, pixmap).await {
+                if let Err(e) =
+                    TcpServer::handle_connection(stream, remote_addr, pixmap, parser_mode, operator).await
+                {
                     tracing::warn!("Got error while handling tcp connection: {e}");
                 }
             });
         }
     }
 
-    #[tracing::instrument(skip_all, fields(remote = _remote_addr.to_string()))]
+    #[tracing::instrument(skip_all, fields(remote = remote_addr.to_string()))]
     async fn handle_connection(
         mut stream: TcpStream,
-        _remote_addr: SocketAddr,
+        remote_addr: SocketAddr,
         pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
     ) -> anyhow::Result<()> {
-        const MAX_LINE_LEN: usize = 32;
         tracing::debug!("Client connected");
 
+        let _client = pixmap.client_connected();
+        let mut state = ConnectionState::new(parser_mode)
+            .operator(operator)
+            .with_writer(&pixmap, &remote_addr.ip().to_string());
         let mut req_buf = BytesMut::with_capacity(8 * 1024);
-        let mut resp_buf = BytesMut::with_capacity(2 * 1024).writer();
+        let mut resp_buf = BytesMut::with_capacity(2 * 1024);
+        let mut request_codec = RequestCodec::new(MAX_LINE_LEN).with_mode(parser_mode);
+        let mut response_codec = ResponseCodec::default();
         loop {
             // fill the line buffer from the network
             let n = stream.read_buf(&mut req_buf).await?;
@@ -56,37 +75,22 @@ impl TcpServer {
             }
             tracing::trace!("Received {}KiB stream data: {:?}", n / 1024, req_buf);
 
-            // handle all lines contained in the buffer
-            while let Some((i, _)) = req_buf.iter().enumerate().find(|(_, &b)| b == b'\n') {
-                let line = req_buf.split_to(i + 1);
-                let result = super::handle_request(&line, &pixmap);
-                match result {
-                    Err(e) => {
-                        resp_buf.write_fmt(format_args!("{}\n", e)).unwrap();
-                    }
-                    Ok(Some(response)) => response.write(&mut resp_buf).unwrap(),
-                    Ok(None) => {}
+            // handle all lines and binary commands contained in the buffer
+            while let Some(frame) = request_codec.decode(&mut req_buf)? {
+                if let Some(response) = super::handle_frame(frame, &pixmap, &mut state) {
+                    response_codec.encode(response, &mut resp_buf)?;
                 }
             }
-
-            // clear the buffer if someone is deliberately not sending a newline
-            if req_buf.len() > MAX_LINE_LEN {
-                tracing::warn!(
-                    "Request buffer has {}B but no lines left in it. Client is probably misbehaving.",
-                    req_buf.len()
-                );
-                req_buf.clear();
-                resp_buf.write_all("line too long\n".as_bytes()).unwrap();
-            }
+            state.flush_changes(&pixmap);
 
             // write accumulated responses back to the sender
-            if !resp_buf.get_ref().is_empty() {
+            if !resp_buf.is_empty() {
                 tracing::trace!(
                     "Sending back {}KiB response: {:?}",
-                    resp_buf.get_ref().len() / 1024,
-                    resp_buf.get_ref()
+                    resp_buf.len() / 1024,
+                    resp_buf
                 );
-                stream.write_all_buf(resp_buf.get_mut()).await?;
+                stream.write_all_buf(&mut resp_buf).await?;
             }
         }
     }
@@ -108,10 +112,11 @@ impl GenServer for TcpServer {
         let listener = TcpListener::bind(self.options.bind_addr).await?;
         tracing::info!("Started TCP Server on {}", self.options.bind_addr);
 
-        let handle = join_set
-            .build_task()
-            .name("tcp_server")
-            .spawn(async move { TcpServer::handle_listener(listener, pixmap).await })?;
+        let parser_mode = self.options.parser_mode;
+        let operator = self.options.operator;
+        let handle = join_set.build_task().name("tcp_server").spawn(async move {
+            TcpServer::handle_listener(listener, pixmap, parser_mode, operator).await
+        })?;
         Ok(handle)
     }
 }
@@ -1,24 +1,31 @@
+use crate::net::protocol::{ParserMode, RequestCodec, ResponseCodec};
 use crate::net::servers::gen_server::GenServer;
+use crate::net::servers::{ConnectionState, MAX_LINE_LEN};
 use crate::pixmap::SharedPixmap;
 use crate::DaemonResult;
 use async_trait::async_trait;
-use bytes::{BufMut, Bytes, BytesMut};
-use std::io::Write;
+use bytes::BytesMut;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::task::{AbortHandle, JoinSet};
+use tokio_util::codec::{Decoder, Encoder};
 
 /// Options with which the `UdpServer` is configured
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UdpServerOptions {
     /// The address to which the server binds
     pub bind_addr: SocketAddr,
+    /// How strictly requests are checked against the protocol syntax
+    pub parser_mode: ParserMode,
 }
 
 /// A server implementation using UDP to receive pixelflut messages.
 ///
-/// *Note*: This server **never** sends data back.
+/// The responses to all requests of one datagram, e.g. to *PX* reads, *SIZE*, *FEATURES*, *HELP* or errors, are
+/// collected and sent back to its sender as one datagram.
+/// Because that sender address can be spoofed, *STATE* and *GETRECT* are refused so that the canvas cannot be
+/// reflected onto somebody else, writes are not attributed to anybody and *WHO* is never answered.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UdpServer {
     options: UdpServerOptions,
@@ -42,17 +49,22 @@ impl UdpServer {
             .map(|i| {
                 let pixmap = pixmap.clone();
                 let socket = socket.clone();
+                let parser_mode = self.options.parser_mode;
                 let handle = join_set
                     .build_task()
                     .name(&format!("udp_server{}", i))
-                    .spawn(async move { UdpServer::listen(pixmap, socket).await })?;
+                    .spawn(async move { UdpServer::listen(pixmap, socket, parser_mode).await })?;
                 Ok(handle)
             })
             .collect::<anyhow::Result<Vec<_>>>()
     }
 
     #[tracing::instrument(skip_all)]
-    async fn listen(pixmap: SharedPixmap, socket: Arc<UdpSocket>) -> anyhow::Result<!> {
+    async fn listen(
+        pixmap: SharedPixmap,
+        socket: Arc<UdpSocket>,
+        parser_mode: ParserMode,
+    ) -> anyhow::Result<!> {
         loop {
             // fill a buffer from the network
             let mut req_buf = BytesMut::with_capacity(4 * 1024);
@@ -62,7 +74,7 @@ impl UdpServer {
             let pixmap = pixmap.clone();
             let socket = socket.clone();
             tokio::spawn(
-                async move { Self::handle_requests(sender, req_buf.freeze(), pixmap, socket).await },
+                async move { Self::handle_requests(sender, req_buf, pixmap, socket, parser_mode).await },
             );
         }
     }
@@ -70,29 +82,36 @@ impl UdpServer {
     #[tracing::instrument(skip_all, fields(remote = sender.to_string()))]
     async fn handle_requests(
         sender: SocketAddr,
-        mut buf: Bytes,
+        mut buf: BytesMut,
         pixmap: SharedPixmap,
         socket: Arc<UdpSocket>,
+        parser_mode: ParserMode,
     ) {
         tracing::trace!("Received {}KiB UDP datagram: {:?}", buf.len() / 1024, buf);
 
-        let mut resp_buf = BytesMut::with_capacity(2 * 1024).writer();
+        // writes are not attributed because the sender address of a datagram can be spoofed
+        let mut state = ConnectionState::new(parser_mode).connectionless();
+        let mut resp_buf = BytesMut::with_capacity(2 * 1024);
+        let mut request_codec = RequestCodec::new(MAX_LINE_LEN).with_mode(parser_mode);
+        let mut response_codec = ResponseCodec::default();
 
-        // handle all lines contained in the request buffer
-        while let Some((i, _)) = buf.iter().enumerate().find(|(_, &b)| b == b'\n') {
-            let line = buf.split_to(i + 1);
-            let result = super::handle_request(&line, &pixmap);
-            match result {
+        // handle all lines and binary commands contained in the datagram, the last line may omit its newline
+        loop {
+            let frame = match request_codec.decode_eof(&mut buf) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
                 Err(e) => {
-                    resp_buf.write_fmt(format_args!("{}\n", e)).unwrap();
+                    tracing::warn!("Could not decode UDP datagram: {e}");
+                    break;
                 }
-                Ok(Some(response)) => response.write(&mut resp_buf).unwrap(),
-                Ok(None) => {}
+            };
+            if let Some(response) = super::handle_frame(frame, &pixmap, &mut state) {
+                response_codec.encode(response, &mut resp_buf).unwrap();
             }
         }
+        state.flush_changes(&pixmap);
 
         // write accumulated responses back to the sender
-        let resp_buf = resp_buf.into_inner();
         if !resp_buf.is_empty() {
             tracing::trace!(
                 "Sending back {}KiB response: {:?}",
@@ -122,10 +141,11 @@ impl GenServer for UdpServer {
         let socket = Arc::new(UdpSocket::bind(self.options.bind_addr).await?);
         tracing::info!("Started UDP Server on {}", self.options.bind_addr);
 
+        let parser_mode = self.options.parser_mode;
         let handle = join_set
             .build_task()
             .name("udp_server")
-            .spawn(async move { UdpServer::listen(pixmap, socket).await })?;
+            .spawn(async move { UdpServer::listen(pixmap, socket, parser_mode).await })?;
         Ok(handle)
     }
 }
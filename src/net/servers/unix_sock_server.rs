@@ -1,19 +1,24 @@
-use crate::net::servers::GenServer;
+use crate::net::protocol::{ParserMode, RequestCodec, ResponseCodec};
+use crate::net::servers::{ConnectionState, GenServer, MAX_LINE_LEN};
 use crate::pixmap::SharedPixmap;
 use crate::DaemonResult;
 use async_trait::async_trait;
-use bytes::{BufMut, BytesMut};
-use std::io::Write;
+use bytes::BytesMut;
 use std::path::PathBuf;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{UnixListener, UnixStream};
 use tokio::task::{AbortHandle, JoinSet};
+use tokio_util::codec::{Decoder, Encoder};
 
 /// Options with which the `UnixSocketServer` is configured
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct UnixSocketOptions {
     /// The path at which a socket should be created
     pub path: PathBuf,
+    /// How strictly requests are checked against the protocol syntax
+    pub parser_mode: ParserMode,
+    /// Whether clients may use commands which are reserved for operators, like *WHO*
+    pub operator: bool,
 }
 
 /// A server implementation using unix domain sockets to transport pixelflut messages.
@@ -24,12 +29,19 @@ pub struct UnixSocketServer {
 
 impl UnixSocketServer {
     #[tracing::instrument(skip_all)]
-    async fn handle_listener(listener: UnixListener, pixmap: SharedPixmap) -> anyhow::Result<!> {
+    async fn handle_listener(
+        listener: UnixListener,
+        pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
+    ) -> anyhow::Result<!> {
         loop {
             let (stream, _) = listener.accept().await?;
             let pixmap = pixmap.clone();
             tokio::spawn(async move {
-                if let Err(e) = UnixSocketServer::handle_connection(stream, pixmap).await {
+                if let Err(e) =
+                    UnixSocketServer::handle_connection(stream, pixmap, parser_mode, operator).await
+                {
                     tracing::warn!("Got error while handling unix socket stream: {e}");
                 }
             });
@@ -37,12 +49,27 @@ impl UnixSocketServer {
     }
 
     #[tracing::instrument(skip_all)]
-    async fn handle_connection(mut stream: UnixStream, pixmap: SharedPixmap) -> anyhow::Result<()> {
-        const MAX_LINE_LEN: usize = 32;
+    async fn handle_connection(
+        mut stream: UnixStream,
+        pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
+    ) -> anyhow::Result<()> {
         tracing::debug!("Client connected");
 
+        // local clients are told apart by the user as which they run
+        let identity = match stream.peer_cred() {
+            Ok(cred) => format!("uid:{}", cred.uid()),
+            Err(_) => "unix".to_string(),
+        };
+        let _client = pixmap.client_connected();
+        let mut state = ConnectionState::new(parser_mode)
+            .operator(operator)
+            .with_writer(&pixmap, &identity);
         let mut req_buf = BytesMut::with_capacity(16 * 1024);
-        let mut resp_buf = BytesMut::with_capacity(2 * 1024).writer();
+        let mut resp_buf = BytesMut::with_capacity(2 * 1024);
+        let mut request_codec = RequestCodec::new(MAX_LINE_LEN).with_mode(parser_mode);
+        let mut response_codec = ResponseCodec::default();
         loop {
             // fill the line buffer from the socket
             let n = stream.read_buf(&mut req_buf).await?;
@@ -52,37 +79,22 @@ impl UnixSocketServer {
             }
             tracing::trace!("Received {}KiB stream data: {:?}", n / 1024, req_buf);
 
-            // handle all lines contained in the buffer
-            while let Some((i, _)) = req_buf.iter().enumerate().find(|(_, &b)| b == b'\n') {
-                let line = req_buf.split_to(i + 1);
-                let result = super::handle_request(&line, &pixmap);
-                match result {
-                    Err(e) => {
-                        resp_buf.write_fmt(format_args!("{}\n", e)).unwrap();
-                    }
-                    Ok(Some(response)) => response.write(&mut resp_buf).unwrap(),
-                    Ok(None) => {}
+            // handle all lines and binary commands contained in the buffer
+            while let Some(frame) = request_codec.decode(&mut req_buf)? {
+                if let Some(response) = super::handle_frame(frame, &pixmap, &mut state) {
+                    response_codec.encode(response, &mut resp_buf)?;
                 }
             }
-
-            // clear the buffer if someone is deliberately not sending a newline
-            if req_buf.len() > MAX_LINE_LEN {
-                tracing::warn!(
-                    "Request buffer has {}B but no lines left in it. Client is probably misbehaving.",
-                    req_buf.len()
-                );
-                req_buf.clear();
-                resp_buf.write_all("line too long\n".as_bytes()).unwrap();
-            }
+            state.flush_changes(&pixmap);
 
             // write accumulated responses back to the sender
-            if !resp_buf.get_ref().is_empty() {
+            if !resp_buf.is_empty() {
                 tracing::trace!(
                     "Sending back {}KiB response: {:?}",
-                    resp_buf.get_ref().len() / 1024,
-                    resp_buf.get_ref()
+                    resp_buf.len() / 1024,
+                    resp_buf
                 );
-                stream.write_all_buf(resp_buf.get_mut()).await?;
+                stream.write_all_buf(&mut resp_buf).await?;
             }
         }
     }
@@ -104,10 +116,11 @@ impl GenServer for UnixSocketServer {
         let listener = UnixListener::bind(&self.options.path)?;
         tracing::info!("Started unix listener on {}", self.options.path.display());
 
-        let handle = join_set
-            .build_task()
-            .name("unix_listener")
-            .spawn(async move { UnixSocketServer::handle_listener(listener, pixmap).await })?;
+        let parser_mode = self.options.parser_mode;
+        let operator = self.options.operator;
+        let handle = join_set.build_task().name("unix_listener").spawn(async move {
+            UnixSocketServer::handle_listener(listener, pixmap, parser_mode, operator).await
+        })?;
         Ok(handle)
     }
 }
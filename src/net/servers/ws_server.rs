@@ -1,4 +1,5 @@
-use crate::net::servers::GenServer;
+use crate::net::protocol::{ParserMode, Response, StateEncoding};
+use crate::net::servers::{ConnectionState, GenServer};
 use crate::pixmap::SharedPixmap;
 use crate::DaemonResult;
 use anyhow::anyhow;
@@ -14,6 +15,10 @@ use tokio_tungstenite::tungstenite::Message;
 pub struct WsServerOptions {
     /// The address to which the server binds
     pub bind_addr: SocketAddr,
+    /// How strictly requests are checked against the protocol syntax
+    pub parser_mode: ParserMode,
+    /// Whether clients may use commands which are reserved for operators, like *WHO*
+    pub operator: bool,
 }
 
 /// A server implementation using WebSocket to transport pixelflut messages
@@ -24,26 +29,39 @@ pub struct WsServer {
 
 impl WsServer {
     #[tracing::instrument(skip_all)]
-    async fn handle_listener(listener: TcpListener, pixmap: SharedPixmap) -> anyhow::Result<!> {
+    async fn handle_listener(
+        listener: TcpListener,
+        pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
+    ) -> anyhow::Result<!> {
         loop {
             let (stream, remote_addr) = listener.accept().await?;
             let pixmap = pixmap.clone();
             tokio::spawn(async move {
-                if let Err(e) = WsServer::handle_connection(stream, remote_addr, pixmap).await {
+                if let Err(e) =
+                    WsServer::handle_connection(stream, remote_addr, pixmap, parser_mode, operator).await
+                {
                     tracing::error!("Got error while handling WebSocket connection: {e}");
                 }
             });
         }
     }
 
-    #[tracing::instrument(skip_all, fields(remote = _remote_addr.to_string()))]
+    #[tracing::instrument(skip_all, fields(remote = remote_addr.to_string()))]
     async fn handle_connection(
         stream: TcpStream,
-        _remote_addr: SocketAddr,
+        remote_addr: SocketAddr,
         pixmap: SharedPixmap,
+        parser_mode: ParserMode,
+        operator: bool,
     ) -> anyhow::Result<()> {
         tracing::debug!("Client connected; performing WebSocket handshake");
         let mut stream = tokio_tungstenite::accept_async(stream).await?;
+        let _client = pixmap.client_connected();
+        let mut state = ConnectionState::new(parser_mode)
+            .operator(operator)
+            .with_writer(&pixmap, &remote_addr.ip().to_string());
 
         loop {
             let request = stream.next().await;
@@ -52,16 +70,27 @@ impl WsServer {
                 Some(Err(e)) => return Err(anyhow!("{}", e)),
                 Some(Ok(msg)) => match msg {
                     Message::Text(msg) => msg.as_bytes(),
-                    Message::Binary(msg) => &msg,
+                    Message::Binary(msg) => msg,
                     Message::Close(_) => return Err(anyhow!("WebSocket connection was closed")),
                     _ => return Err(anyhow!("Got unexpected websocket message: {msg:?}")),
                 },
             };
-            let result = super::handle_request(request, &pixmap);
+            let result = super::handle_request(request, &pixmap, &mut state);
+            state.flush_changes(&pixmap);
             match result {
-                Err(e) => stream.send(Message::Text(e)).await?,
-                Ok(Some(response)) => stream.send(Message::Text(format!("{}", response))).await?,
-                Ok(None) => {}
+                Some(
+                    response @ Response::State {
+                        encoding: StateEncoding::Rgb,
+                        ..
+                    },
+                ) => {
+                    // raw canvas data is not valid text and is therefore sent as a binary message
+                    let mut buf = Vec::new();
+                    response.write(&mut buf)?;
+                    stream.send(Message::Binary(buf)).await?
+                }
+                Some(response) => stream.send(Message::Text(format!("{}", response))).await?,
+                None => {}
             }
         }
     }
@@ -83,10 +112,12 @@ impl GenServer for WsServer {
         let listener = TcpListener::bind(self.options.bind_addr).await?;
         tracing::info!("Started WebSocket Server on {}", self.options.bind_addr);
 
+        let parser_mode = self.options.parser_mode;
+        let operator = self.options.operator;
         let handle = join_set
             .build_task()
             .name("ws_server")
-            .spawn(async move { WsServer::handle_listener(listener, pixmap).await })?;
+            .spawn(async move { WsServer::handle_listener(listener, pixmap, parser_mode, operator).await })?;
         Ok(handle)
     }
 }
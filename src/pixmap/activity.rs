@@ -0,0 +1,50 @@
+//! Counters of client activity on a [`Pixmap`](super::Pixmap)
+//!
+//! They are only meant to be displayed to people watching the canvas, so they are updated without any ordering
+//! guarantees.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// How many clients are connected to a pixmap and how much they wrote
+#[derive(Debug, Default)]
+pub(super) struct Activity {
+    clients: Arc<AtomicUsize>,
+    written_pixels: AtomicU64,
+}
+
+impl Activity {
+    pub fn client_connected(&self) -> ClientGuard {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+        ClientGuard {
+            clients: self.clients.clone(),
+        }
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    pub fn count_written_pixels(&self, pixels: u64) {
+        self.written_pixels.fetch_add(pixels, Ordering::Relaxed);
+    }
+
+    pub fn written_pixels(&self) -> u64 {
+        self.written_pixels.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts a client as connected to a pixmap for as long as it is alive
+///
+/// Guards are handed out by [`Pixmap::client_connected()`](super::Pixmap::client_connected).
+#[derive(Debug)]
+#[must_use = "the client is counted as disconnected as soon as the guard is dropped"]
+pub struct ClientGuard {
+    clients: Arc<AtomicUsize>,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,185 @@
+//! Tracking of who last wrote each pixel of a [`Pixmap`](super::Pixmap) and when
+//!
+//! Writers are identified by a string such as the remote address of a client which is registered once and then
+//! referred to by a compact [`WriterId`].
+//! Every pixel stores the id of its last writer together with the time of that write in one atomic value so that
+//! both are always read consistently.
+
+use crate::pixmap::Color;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// How many writers can be registered at one pixmap
+///
+/// This bounds the memory which clients can make the registry use by connecting from many addresses and lets
+/// [`WriteStamps`] store the id of a writer in 16 bits.
+const MAX_WRITERS: usize = u16::MAX as usize;
+/// The number of bits in which [`WriteStamps`] store the time of a write
+const TIME_BITS: u32 = 48;
+
+/// A compact handle for an identity which writes to a pixmap
+///
+/// Ids are handed out by [`Pixmap::register_writer()`](super::Pixmap::register_writer) and are only meaningful
+/// for the pixmap which handed them out.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct WriterId(u32);
+
+impl WriterId {
+    /// The writer of all changes which are not attributed to anybody in particular
+    pub const UNKNOWN: WriterId = WriterId(0);
+
+    /// A color which distinguishes this writer from others when rendering an attribution heatmap
+    ///
+    /// Colors are spread around the color wheel so that writers which registered one after another are easy to tell
+    /// apart.
+    /// Unknown writers are rendered in gray.
+    pub fn heatmap_color(self) -> Color {
+        if self == Self::UNKNOWN {
+            return Color::from((0x80, 0x80, 0x80));
+        }
+        // stepping by the golden angle never repeats a hue and keeps consecutive ones far apart
+        let hue = (self.0 as f32 * 137.507_77) % 360.0;
+        let channel = |n: f32| {
+            let k = (n + hue / 60.0) % 6.0;
+            let value = 1.0 - (k.min(4.0 - k).clamp(0.0, 1.0));
+            (value * 255.0) as u8
+        };
+        Color::from((channel(5.0), channel(3.0), channel(1.0)))
+    }
+}
+
+/// An error which indicates that a writer could not be registered because the registry is full
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+#[error("cannot tell apart more than {MAX_WRITERS} writers")]
+pub struct TooManyWritersError;
+
+/// Information about the last write to one pixel
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LastWrite {
+    /// Who wrote the pixel
+    pub writer: WriterId,
+    /// When the pixel was written, with a precision of one millisecond
+    pub time: SystemTime,
+}
+
+/// The identities of all writers which registered at a pixmap
+#[derive(Debug, Default)]
+pub(super) struct WriterRegistry {
+    writers: Mutex<Writers>,
+}
+
+#[derive(Debug, Default)]
+struct Writers {
+    ids: HashMap<String, WriterId>,
+    /// The identity of every writer, indexed by its id minus one
+    identities: Vec<String>,
+}
+
+impl WriterRegistry {
+    /// Get the id of a writer, registering it if its identity is not known yet
+    pub fn register(&self, identity: &str) -> Result<WriterId, TooManyWritersError> {
+        let mut writers = self.writers.lock().unwrap();
+        if let Some(id) = writers.ids.get(identity) {
+            return Ok(*id);
+        }
+        if writers.identities.len() >= MAX_WRITERS {
+            return Err(TooManyWritersError);
+        }
+        let id = WriterId(writers.identities.len() as u32 + 1);
+        writers.ids.insert(identity.to_string(), id);
+        writers.identities.push(identity.to_string());
+        Ok(id)
+    }
+
+    /// Look up the id of an identity without registering it
+    pub fn find(&self, identity: &str) -> Option<WriterId> {
+        self.writers.lock().unwrap().ids.get(identity).copied()
+    }
+
+    /// Look up the identity with which a writer was registered
+    pub fn identity(&self, writer: WriterId) -> Option<String> {
+        let index = (writer.0 as usize).checked_sub(1)?;
+        self.writers.lock().unwrap().identities.get(index).cloned()
+    }
+}
+
+/// The last writer and write time of every pixel
+///
+/// Times are stored as milliseconds since the tracking started, offset by one so that 0 marks pixels which have not
+/// been written since.
+#[derive(Debug)]
+pub(super) struct WriteStamps {
+    epoch: SystemTime,
+    stamps: Box<[AtomicU64]>,
+}
+
+impl WriteStamps {
+    pub fn new(len: usize) -> Self {
+        Self::from_stamps(SystemTime::now(), vec![0; len])
+    }
+
+    fn from_stamps(epoch: SystemTime, stamps: Vec<u64>) -> Self {
+        Self {
+            epoch,
+            stamps: stamps.into_iter().map(AtomicU64::new).collect(),
+        }
+    }
+
+    /// Create the stamps for a pixmap of a different size in which the overlapping region is preserved
+    pub fn resized(&self, old_width: usize, width: usize, height: usize) -> Self {
+        let old_height = self.stamps.len() / old_width;
+        let columns = width.min(old_width);
+        let mut stamps = Vec::with_capacity(width * height);
+        for row in 0..height {
+            if row < old_height {
+                stamps.extend(
+                    self.stamps[row * old_width..row * old_width + columns]
+                        .iter()
+                        .map(|stamp| stamp.load(Ordering::Relaxed)),
+                );
+            }
+            stamps.resize((row + 1) * width, 0);
+        }
+        Self::from_stamps(self.epoch, stamps)
+    }
+
+    /// Encode a write which `writer` makes at `time`
+    #[inline(always)]
+    pub fn encode(&self, writer: WriterId, time: SystemTime) -> u64 {
+        let millis = (time.duration_since(self.epoch).unwrap_or_default().as_millis() as u64)
+            .min((1 << TIME_BITS) - 2);
+        u64::from(writer.0) << TIME_BITS | (millis + 1)
+    }
+
+    /// Record that all pixels with an index in the given range were written
+    #[inline(always)]
+    pub fn stamp(&self, range: Range<usize>, stamp: u64) {
+        for pixel in &self.stamps[range] {
+            pixel.store(stamp, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the last write to the pixel with the given index
+    pub fn get(&self, i: usize) -> Option<LastWrite> {
+        self.decode(self.stamps[i].load(Ordering::Relaxed))
+    }
+
+    /// Get the last write to every pixel
+    pub fn all(&self) -> impl Iterator<Item = Option<LastWrite>> + '_ {
+        self.stamps
+            .iter()
+            .map(|stamp| self.decode(stamp.load(Ordering::Relaxed)))
+    }
+
+    fn decode(&self, stamp: u64) -> Option<LastWrite> {
+        let millis = (stamp & ((1 << TIME_BITS) - 1)).checked_sub(1)?;
+        Some(LastWrite {
+            writer: WriterId((stamp >> TIME_BITS) as u32),
+            time: self.epoch + Duration::from_millis(millis),
+        })
+    }
+}
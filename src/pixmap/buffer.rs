@@ -0,0 +1,254 @@
+//! Backends which hold the raw pixel data of a [`Pixmap`](super::Pixmap)
+//!
+//! By default, every pixel is stored in an [`AtomicU32`](std::sync::atomic::AtomicU32) which is accessed with
+//! relaxed ordering.
+//! This makes concurrent access from many connections well-defined while still compiling down to plain loads and
+//! stores on common architectures.
+//!
+//! The original backend which shares a plain `Vec<Color>` between threads without any synchronization is kept behind
+//! the `unsafe-storage` feature so that the throughput of both can be compared.
+//! Using it concurrently is technically undefined behavior.
+//!
+//! Independent of the feature, pixels can also be kept in a [memory-mapped file](super::mapped).
+//!
+//! All backends expose the same interface.
+//! Indices are not validated beyond what slice indexing does, so callers must resolve coordinates beforehand.
+
+use crate::pixmap::mapped::MappedBuffer;
+use crate::pixmap::Color;
+use std::ops::Range;
+
+#[cfg(not(feature = "unsafe-storage"))]
+use atomic::PixelBuffer as MemoryBuffer;
+#[cfg(feature = "unsafe-storage")]
+use unsynchronized::PixelBuffer as MemoryBuffer;
+
+/// Pixel storage of one of the available backends
+#[derive(Debug)]
+pub(super) enum PixelBuffer {
+    /// Pixels which are kept in the memory of this process
+    Memory(MemoryBuffer),
+    /// Pixels which are kept in a file that is mapped into memory
+    Mapped(MappedBuffer),
+}
+
+impl PixelBuffer {
+    /// Create an in-memory buffer of `len` pixels which all have the default color
+    pub fn new(len: usize) -> Self {
+        Self::Memory(MemoryBuffer::new(len))
+    }
+
+    /// Create an in-memory buffer which holds the given colors
+    pub fn from_colors(colors: Vec<Color>) -> Self {
+        Self::Memory(MemoryBuffer::from_colors(colors))
+    }
+
+    /// Whether the pixels are kept in a file which cannot change its size
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Self::Mapped(_))
+    }
+
+    /// The number of pixels in this buffer
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(buffer) => buffer.len(),
+            Self::Mapped(buffer) => buffer.len(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn load(&self, i: usize) -> Color {
+        match self {
+            Self::Memory(buffer) => buffer.load(i),
+            Self::Mapped(buffer) => buffer.load(i),
+        }
+    }
+
+    #[inline(always)]
+    pub fn store(&self, i: usize, color: Color) {
+        match self {
+            Self::Memory(buffer) => buffer.store(i, color),
+            Self::Mapped(buffer) => buffer.store(i, color),
+        }
+    }
+
+    /// Replace the color of a pixel only if it still has the `current` color
+    ///
+    /// Returns whether the pixel was replaced.
+    #[inline(always)]
+    pub fn compare_exchange(&self, i: usize, current: Color, new: Color) -> bool {
+        match self {
+            Self::Memory(buffer) => buffer.compare_exchange(i, current, new),
+            Self::Mapped(buffer) => buffer.compare_exchange(i, current, new),
+        }
+    }
+
+    /// Set all pixels in the given range to one color
+    #[inline(always)]
+    pub fn fill(&self, range: Range<usize>, color: Color) {
+        match self {
+            Self::Memory(buffer) => buffer.fill(range, color),
+            Self::Mapped(buffer) => buffer.fill(range, color),
+        }
+    }
+
+    /// Append the colors of all pixels in the given range to `dst`
+    #[inline(always)]
+    pub fn extend_into(&self, range: Range<usize>, dst: &mut Vec<Color>) {
+        match self {
+            Self::Memory(buffer) => buffer.extend_into(range, dst),
+            Self::Mapped(buffer) => buffer.extend_into(range, dst),
+        }
+    }
+}
+
+#[cfg(not(feature = "unsafe-storage"))]
+mod atomic {
+    use crate::pixmap::Color;
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Pixel storage in which every pixel is an atomic value
+    #[derive(Debug)]
+    pub(in crate::pixmap) struct PixelBuffer {
+        data: Box<[AtomicU32]>,
+    }
+
+    impl PixelBuffer {
+        /// Create a buffer of `len` pixels which all have the default color
+        pub fn new(len: usize) -> Self {
+            let value = u32::from(Color::default());
+            Self {
+                data: (0..len).map(|_| AtomicU32::new(value)).collect(),
+            }
+        }
+
+        /// Create a buffer which holds the given colors
+        pub fn from_colors(colors: Vec<Color>) -> Self {
+            Self {
+                data: colors.into_iter().map(|c| AtomicU32::new(c.into())).collect(),
+            }
+        }
+
+        /// The number of pixels in this buffer
+        pub fn len(&self) -> usize {
+            self.data.len()
+        }
+
+        #[inline(always)]
+        pub fn load(&self, i: usize) -> Color {
+            Color::from(self.data[i].load(Ordering::Relaxed))
+        }
+
+        #[inline(always)]
+        pub fn store(&self, i: usize, color: Color) {
+            self.data[i].store(color.into(), Ordering::Relaxed)
+        }
+
+        /// Replace the color of a pixel only if it still has the `current` color
+        #[inline(always)]
+        pub fn compare_exchange(&self, i: usize, current: Color, new: Color) -> bool {
+            self.data[i]
+                .compare_exchange(current.into(), new.into(), Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        /// Set all pixels in the given range to one color
+        #[inline(always)]
+        pub fn fill(&self, range: Range<usize>, color: Color) {
+            let value = u32::from(color);
+            for pixel in &self.data[range] {
+                pixel.store(value, Ordering::Relaxed);
+            }
+        }
+
+        /// Append the colors of all pixels in the given range to `dst`
+        #[inline(always)]
+        pub fn extend_into(&self, range: Range<usize>, dst: &mut Vec<Color>) {
+            dst.extend(
+                self.data[range]
+                    .iter()
+                    .map(|pixel| Color::from(pixel.load(Ordering::Relaxed))),
+            );
+        }
+    }
+}
+
+#[cfg(feature = "unsafe-storage")]
+mod unsynchronized {
+    use crate::pixmap::Color;
+    use std::cell::SyncUnsafeCell;
+    use std::ops::Range;
+
+    /// Pixel storage which is shared between threads without any synchronization
+    #[derive(Debug)]
+    pub(in crate::pixmap) struct PixelBuffer {
+        data: SyncUnsafeCell<Vec<Color>>,
+    }
+
+    impl PixelBuffer {
+        /// Create a buffer of `len` pixels which all have the default color
+        pub fn new(len: usize) -> Self {
+            Self::from_colors(vec![Color::default(); len])
+        }
+
+        /// Create a buffer which holds the given colors
+        pub fn from_colors(colors: Vec<Color>) -> Self {
+            Self {
+                data: SyncUnsafeCell::new(colors),
+            }
+        }
+
+        /// The number of pixels in this buffer
+        pub fn len(&self) -> usize {
+            self.data().len()
+        }
+
+        #[inline(always)]
+        pub fn load(&self, i: usize) -> Color {
+            self.data()[i]
+        }
+
+        #[inline(always)]
+        pub fn store(&self, i: usize, color: Color) {
+            self.data()[i] = color;
+        }
+
+        /// Replace the color of a pixel only if it still has the `current` color
+        ///
+        /// Like every other access of this backend, the comparison is not atomic.
+        #[inline(always)]
+        pub fn compare_exchange(&self, i: usize, current: Color, new: Color) -> bool {
+            let pixel = &mut self.data()[i];
+            if *pixel != current {
+                return false;
+            }
+            *pixel = new;
+            true
+        }
+
+        /// Set all pixels in the given range to one color
+        #[inline(always)]
+        pub fn fill(&self, range: Range<usize>, color: Color) {
+            self.data()[range].fill(color);
+        }
+
+        /// Append the colors of all pixels in the given range to `dst`
+        #[inline(always)]
+        pub fn extend_into(&self, range: Range<usize>, dst: &mut Vec<Color>) {
+            dst.extend_from_slice(&self.data()[range]);
+        }
+
+        /// Get a handle to the raw data
+        ///
+        /// No memory safety rules are ensured for this data.
+        /// The handed out mutable reference is not checked to be the only one and the underlying data may change at
+        /// any time.
+        /// While this is undefined behavior, it works as expected in practice and is what this backend is kept for.
+        #[allow(clippy::mut_from_ref)]
+        #[inline(always)]
+        fn data(&self) -> &mut [Color] {
+            unsafe { &mut *self.data.get() }
+        }
+    }
+}
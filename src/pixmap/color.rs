@@ -1,15 +1,165 @@
-use std::fmt::{Formatter, LowerHex, UpperHex};
+use crate::pixmap::css_colors::CSS_COLORS;
+use std::fmt::{Display, Formatter, LowerHex, UpperHex};
+use std::str::FromStr;
+use thiserror::Error;
 
 #[cfg(test)]
 use quickcheck::{Arbitrary, Gen};
 
-/// Color data represented as red, green, and blue channels each having a depth of 8 bits.
+/// Color data represented as red, green, blue and alpha channels each having a depth of 8 bits.
 ///
-/// The internal format is 0RGB stored as one u32.
+/// The internal format is TRGB stored as one u32 where T is the *transparency* of the color, i.e. the inverted
+/// alpha channel.
+/// This keeps the zero value of the most significant byte fully opaque so that plain RGB colors are stored exactly
+/// as they would be in a 0RGB format.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
 #[repr(C)]
 pub struct Color(u32);
 
+/// An error which indicates that a string could not be parsed into a [`Color`]
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+pub enum ParseColorError {
+    /// The string contains characters that are not hex digits
+    #[error("color contains invalid hex digits")]
+    InvalidDigit,
+    /// The string has a number of hex digits that does not correspond to any supported notation
+    #[error("color has an invalid length")]
+    InvalidLength,
+    /// The string is neither hex encoded nor a known color name
+    #[error("unknown color name")]
+    UnknownName,
+}
+
+impl Color {
+    /// Construct a color from its red, green, blue and alpha channels
+    pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self(u32::from_be_bytes([!a, r, g, b]))
+    }
+
+    /// The alpha channel of this color where `0xFF` is fully opaque and `0x00` fully transparent
+    pub const fn alpha(&self) -> u8 {
+        !self.0.to_be_bytes()[0]
+    }
+
+    /// Whether this color is fully opaque and would completely replace any color it is drawn over
+    pub const fn is_opaque(&self) -> bool {
+        self.alpha() == u8::MAX
+    }
+
+    /// Return the same color but with its alpha channel replaced by the given value
+    pub const fn with_alpha(self, alpha: u8) -> Self {
+        Self((self.0 & 0x00FFFFFF) | ((!alpha as u32) << 24))
+    }
+
+    /// Composite this color over the given background color.
+    ///
+    /// The background is treated as fully opaque so the result is always opaque as well.
+    pub fn blend_over(self, background: Color) -> Color {
+        let alpha = self.alpha() as u32;
+        let fg = self.0.to_be_bytes();
+        let bg = background.0.to_be_bytes();
+        let blend = |fg: u8, bg: u8| ((fg as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+        Self(u32::from_be_bytes([
+            0,
+            blend(fg[1], bg[1]),
+            blend(fg[2], bg[2]),
+            blend(fg[3], bg[3]),
+        ]))
+    }
+
+    /// Move this color toward `target` so that only the fraction `remaining` of the distance between them is left.
+    ///
+    /// Channels are rounded down after adding `dither`, which should be a random value in `[0, 1)`, so that small
+    /// steps still happen with the right probability instead of always being rounded away.
+    /// The result is always opaque.
+    pub fn fade_toward(self, target: Color, remaining: f32, dither: f32) -> Color {
+        let from = self.0.to_be_bytes();
+        let to = target.0.to_be_bytes();
+        let fade = |from: u8, to: u8| {
+            let value = to as f32 + (from as f32 - to as f32) * remaining;
+            (value + dither).floor().clamp(0.0, 255.0) as u8
+        };
+        Self(u32::from_be_bytes([
+            0,
+            fade(from[1], to[1]),
+            fade(from[2], to[2]),
+            fade(from[3], to[3]),
+        ]))
+    }
+
+    /// Parse a hex encoded color in any of the notations supported by the pixelflut protocol.
+    ///
+    /// The color may optionally be prefixed with `#` and is interpreted depending on its number of digits:
+    ///
+    /// - `ab` is a grayscale value that is used for all three channels
+    /// - `rgb` is a short form in which each digit is repeated (`f80` is `ff8800`)
+    /// - `rrggbb` is a normal rgb color
+    /// - `rrggbbaa` is an rgb color with an additional alpha channel
+    ///
+    /// Colors with 1, 4 or 5 digits are interpreted as a plain hex number in the same way as `rrggbb` would be.
+    /// This is done for compatibility with clients that do not pad their colors with leading zeros.
+    pub fn from_hex_str(s: &str) -> Result<Self, ParseColorError> {
+        Self::from_hex_bytes(s.as_bytes())
+    }
+
+    /// Parse a hex encoded color directly from bytes.
+    ///
+    /// This supports the same notations as [`from_hex_str()`](Self::from_hex_str) but does not require the input to
+    /// be valid UTF-8 which makes it suitable for parsing network input.
+    pub fn from_hex_bytes(s: &[u8]) -> Result<Self, ParseColorError> {
+        let digits = s.strip_prefix(b"#").unwrap_or(s);
+
+        // accumulate the digits while validating them; overflows are irrelevant because long inputs are rejected
+        let mut value: u32 = 0;
+        for &digit in digits {
+            let nibble = match digit {
+                b'0'..=b'9' => digit - b'0',
+                b'a'..=b'f' => digit - b'a' + 10,
+                b'A'..=b'F' => digit - b'A' + 10,
+                _ => return Err(ParseColorError::InvalidDigit),
+            };
+            value = value.wrapping_shl(4) | nibble as u32;
+        }
+
+        match digits.len() {
+            2 => {
+                let v = value as u8;
+                Ok(Self::from((v, v, v)))
+            }
+            3 => {
+                let expand = |nibble: u32| ((nibble & 0xF) * 0x11) as u8;
+                Ok(Self::from((
+                    expand(value >> 8),
+                    expand(value >> 4),
+                    expand(value),
+                )))
+            }
+            1 | 4..=6 => Ok(Self::from(value)),
+            8 => Ok(Self::from(value.to_be_bytes())),
+            _ => Err(ParseColorError::InvalidLength),
+        }
+    }
+}
+
+/// Parses a color from any hex notation supported by [`Color::from_hex_str()`] or from a
+/// [CSS color name](https://developer.mozilla.org/en-US/docs/Web/CSS/named-color).
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::from_hex_str(s) {
+            Err(ParseColorError::InvalidDigit) => {
+                let name = s.to_ascii_lowercase();
+                CSS_COLORS
+                    .binary_search_by_key(&name.as_str(), |(name, _)| name)
+                    .map(|i| CSS_COLORS[i].1)
+                    .map_err(|_| ParseColorError::UnknownName)
+            }
+            result => result,
+        }
+    }
+}
+
 impl From<[u8; 3]> for Color {
     fn from(data: [u8; 3]) -> Self {
         Self(u32::from_be_bytes([0, data[0], data[1], data[2]]))
@@ -23,6 +173,19 @@ impl From<Color> for [u8; 3] {
     }
 }
 
+impl From<[u8; 4]> for Color {
+    fn from(data: [u8; 4]) -> Self {
+        Self::from_rgba(data[0], data[1], data[2], data[3])
+    }
+}
+
+impl From<Color> for [u8; 4] {
+    fn from(value: Color) -> Self {
+        let channels = value.0.to_be_bytes();
+        [channels[1], channels[2], channels[3], !channels[0]]
+    }
+}
+
 impl From<(u8, u8, u8)> for Color {
     fn from(data: (u8, u8, u8)) -> Self {
         Self(u32::from_be_bytes([0, data.0, data.1, data.2]))
@@ -36,6 +199,19 @@ impl From<Color> for (u8, u8, u8) {
     }
 }
 
+impl From<(u8, u8, u8, u8)> for Color {
+    fn from(data: (u8, u8, u8, u8)) -> Self {
+        Self::from_rgba(data.0, data.1, data.2, data.3)
+    }
+}
+
+impl From<Color> for (u8, u8, u8, u8) {
+    fn from(value: Color) -> Self {
+        let channels: [u8; 4] = value.into();
+        (channels[0], channels[1], channels[2], channels[3])
+    }
+}
+
 impl From<u32> for Color {
     fn from(src: u32) -> Self {
         Self(src)
@@ -55,30 +231,39 @@ impl From<Color> for Vec<u8> {
     }
 }
 
-impl ToString for Color {
-    fn to_string(&self) -> String {
-        let channels: [u8; 3] = (*self).into();
-        format!("#{:02X}{:02X}{:02X}", channels[0], channels[1], channels[2])
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("#{:X}", self))
     }
 }
 
+/// Formats the color as `RRGGBB` or, if it is not fully opaque, as `RRGGBBAA`
 impl UpperHex for Color {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let channels: [u8; 3] = (*self).into();
+        let channels: [u8; 4] = (*self).into();
         f.write_fmt(format_args!(
             "{:02X}{:02X}{:02X}",
             channels[0], channels[1], channels[2]
-        ))
+        ))?;
+        if !self.is_opaque() {
+            f.write_fmt(format_args!("{:02X}", channels[3]))?;
+        }
+        Ok(())
     }
 }
 
+/// Formats the color as `rrggbb` or, if it is not fully opaque, as `rrggbbaa`
 impl LowerHex for Color {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let channels: [u8; 3] = (*self).into();
+        let channels: [u8; 4] = (*self).into();
         f.write_fmt(format_args!(
             "{:02x}{:02x}{:02x}",
             channels[0], channels[1], channels[2]
-        ))
+        ))?;
+        if !self.is_opaque() {
+            f.write_fmt(format_args!("{:02x}", channels[3]))?;
+        }
+        Ok(())
     }
 }
 
@@ -96,6 +281,26 @@ quickcheck! {
         let c_dec: Color = Color::from(c_enc);
         c_dec == color
     }
+
+    fn test_rgba_conversion_inversion(color: Color) -> bool {
+        let c_enc: [u8; 4] = color.into();
+        let c_dec: Color = Color::from(c_enc);
+        c_dec == color
+    }
+
+    fn test_blend_result_is_opaque(fg: Color, bg: Color) -> bool {
+        fg.blend_over(bg).is_opaque()
+    }
+
+    fn test_fade_stays_between_colors(from: Color, to: Color, remaining: u8, dither: u8) -> bool {
+        let faded: [u8; 3] = from.fade_toward(to, remaining as f32 / 255.0, dither as f32 / 256.0).into();
+        let (from, to): ([u8; 3], [u8; 3]) = (from.into(), to.into());
+        (0..3).all(|i| (from[i].min(to[i])..=from[i].max(to[i])).contains(&faded[i]))
+    }
+
+    fn test_hex_conversion_inversion(color: Color) -> bool {
+        Color::from_hex_str(&format!("{:X}", color)) == Ok(color)
+    }
 }
 
 #[cfg(test)]
@@ -108,4 +313,49 @@ fn test_conversion() {
     run_test((0xAA, 0xBB, 0xCC), Color(0x00AABBCC));
     run_test([0xAA, 0xBB, 0xCC], Color(0x00AABBCC));
     run_test(0x00AABBCC, Color(0x00AABBCC));
+    run_test((0xAA, 0xBB, 0xCC, 0xFF), Color(0x00AABBCC));
+    run_test([0xAA, 0xBB, 0xCC, 0x00], Color(0xFFAABBCC));
+}
+
+#[cfg(test)]
+#[test]
+fn test_parsing() {
+    fn run_test(src: &str, expected: Color) {
+        let result = Color::from_str(src);
+        assert_eq!(result, Ok(expected), "{:?} != Ok({:06X})", result, expected);
+    }
+    run_test("AABBCC", Color::from((0xAA, 0xBB, 0xCC)));
+    run_test("#aabbcc", Color::from((0xAA, 0xBB, 0xCC)));
+    run_test("AABBCC80", Color::from_rgba(0xAA, 0xBB, 0xCC, 0x80));
+    run_test("f80", Color::from((0xFF, 0x88, 0x00)));
+    run_test("#7f", Color::from((0x7F, 0x7F, 0x7F)));
+    run_test("c097", Color::from((0x00, 0xC0, 0x97)));
+    run_test("RebeccaPurple", Color::from((0x66, 0x33, 0x99)));
+    run_test("transparent", Color::from_rgba(0, 0, 0, 0));
+    assert_eq!(Color::from_str("#1234567"), Err(ParseColorError::InvalidLength));
+    assert_eq!(Color::from_str("notacolor"), Err(ParseColorError::UnknownName));
+    assert_eq!(Color::from_hex_str("red"), Err(ParseColorError::InvalidDigit));
+}
+
+#[cfg(test)]
+#[test]
+fn test_css_colors_are_sorted() {
+    assert!(CSS_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+}
+
+#[cfg(test)]
+#[test]
+fn test_blending() {
+    let bg = Color::from((0x00, 0x00, 0x00));
+    let fg = Color::from_rgba(0xFF, 0x80, 0x00, 0xFF);
+    assert_eq!(fg.blend_over(bg), Color::from((0xFF, 0x80, 0x00)));
+    assert_eq!(fg.with_alpha(0x00).blend_over(bg), bg);
+    assert_eq!(
+        fg.with_alpha(0x80).blend_over(bg),
+        Color::from((0x80, 0x40, 0x00))
+    );
+
+    assert_eq!(fg.fade_toward(bg, 0.5, 0.0), Color::from((0x7F, 0x40, 0x00)));
+    assert_eq!(fg.fade_toward(bg, 0.0, 0.0), bg);
+    assert_eq!(fg.fade_toward(bg, 1.0, 0.0), fg);
 }
@@ -0,0 +1,156 @@
+//! The named colors defined by the CSS Color Module Level 4 specification
+
+use crate::pixmap::Color;
+
+/// All named CSS colors, sorted by name so that they can be searched using binary search
+pub(super) static CSS_COLORS: &[(&str, Color)] = &[
+    ("aliceblue", Color::from_rgba(0xF0, 0xF8, 0xFF, 0xFF)),
+    ("antiquewhite", Color::from_rgba(0xFA, 0xEB, 0xD7, 0xFF)),
+    ("aqua", Color::from_rgba(0x00, 0xFF, 0xFF, 0xFF)),
+    ("aquamarine", Color::from_rgba(0x7F, 0xFF, 0xD4, 0xFF)),
+    ("azure", Color::from_rgba(0xF0, 0xFF, 0xFF, 0xFF)),
+    ("beige", Color::from_rgba(0xF5, 0xF5, 0xDC, 0xFF)),
+    ("bisque", Color::from_rgba(0xFF, 0xE4, 0xC4, 0xFF)),
+    ("black", Color::from_rgba(0x00, 0x00, 0x00, 0xFF)),
+    ("blanchedalmond", Color::from_rgba(0xFF, 0xEB, 0xCD, 0xFF)),
+    ("blue", Color::from_rgba(0x00, 0x00, 0xFF, 0xFF)),
+    ("blueviolet", Color::from_rgba(0x8A, 0x2B, 0xE2, 0xFF)),
+    ("brown", Color::from_rgba(0xA5, 0x2A, 0x2A, 0xFF)),
+    ("burlywood", Color::from_rgba(0xDE, 0xB8, 0x87, 0xFF)),
+    ("cadetblue", Color::from_rgba(0x5F, 0x9E, 0xA0, 0xFF)),
+    ("chartreuse", Color::from_rgba(0x7F, 0xFF, 0x00, 0xFF)),
+    ("chocolate", Color::from_rgba(0xD2, 0x69, 0x1E, 0xFF)),
+    ("coral", Color::from_rgba(0xFF, 0x7F, 0x50, 0xFF)),
+    ("cornflowerblue", Color::from_rgba(0x64, 0x95, 0xED, 0xFF)),
+    ("cornsilk", Color::from_rgba(0xFF, 0xF8, 0xDC, 0xFF)),
+    ("crimson", Color::from_rgba(0xDC, 0x14, 0x3C, 0xFF)),
+    ("cyan", Color::from_rgba(0x00, 0xFF, 0xFF, 0xFF)),
+    ("darkblue", Color::from_rgba(0x00, 0x00, 0x8B, 0xFF)),
+    ("darkcyan", Color::from_rgba(0x00, 0x8B, 0x8B, 0xFF)),
+    ("darkgoldenrod", Color::from_rgba(0xB8, 0x86, 0x0B, 0xFF)),
+    ("darkgray", Color::from_rgba(0xA9, 0xA9, 0xA9, 0xFF)),
+    ("darkgreen", Color::from_rgba(0x00, 0x64, 0x00, 0xFF)),
+    ("darkgrey", Color::from_rgba(0xA9, 0xA9, 0xA9, 0xFF)),
+    ("darkkhaki", Color::from_rgba(0xBD, 0xB7, 0x6B, 0xFF)),
+    ("darkmagenta", Color::from_rgba(0x8B, 0x00, 0x8B, 0xFF)),
+    ("darkolivegreen", Color::from_rgba(0x55, 0x6B, 0x2F, 0xFF)),
+    ("darkorange", Color::from_rgba(0xFF, 0x8C, 0x00, 0xFF)),
+    ("darkorchid", Color::from_rgba(0x99, 0x32, 0xCC, 0xFF)),
+    ("darkred", Color::from_rgba(0x8B, 0x00, 0x00, 0xFF)),
+    ("darksalmon", Color::from_rgba(0xE9, 0x96, 0x7A, 0xFF)),
+    ("darkseagreen", Color::from_rgba(0x8F, 0xBC, 0x8F, 0xFF)),
+    ("darkslateblue", Color::from_rgba(0x48, 0x3D, 0x8B, 0xFF)),
+    ("darkslategray", Color::from_rgba(0x2F, 0x4F, 0x4F, 0xFF)),
+    ("darkslategrey", Color::from_rgba(0x2F, 0x4F, 0x4F, 0xFF)),
+    ("darkturquoise", Color::from_rgba(0x00, 0xCE, 0xD1, 0xFF)),
+    ("darkviolet", Color::from_rgba(0x94, 0x00, 0xD3, 0xFF)),
+    ("deeppink", Color::from_rgba(0xFF, 0x14, 0x93, 0xFF)),
+    ("deepskyblue", Color::from_rgba(0x00, 0xBF, 0xFF, 0xFF)),
+    ("dimgray", Color::from_rgba(0x69, 0x69, 0x69, 0xFF)),
+    ("dimgrey", Color::from_rgba(0x69, 0x69, 0x69, 0xFF)),
+    ("dodgerblue", Color::from_rgba(0x1E, 0x90, 0xFF, 0xFF)),
+    ("firebrick", Color::from_rgba(0xB2, 0x22, 0x22, 0xFF)),
+    ("floralwhite", Color::from_rgba(0xFF, 0xFA, 0xF0, 0xFF)),
+    ("forestgreen", Color::from_rgba(0x22, 0x8B, 0x22, 0xFF)),
+    ("fuchsia", Color::from_rgba(0xFF, 0x00, 0xFF, 0xFF)),
+    ("gainsboro", Color::from_rgba(0xDC, 0xDC, 0xDC, 0xFF)),
+    ("ghostwhite", Color::from_rgba(0xF8, 0xF8, 0xFF, 0xFF)),
+    ("gold", Color::from_rgba(0xFF, 0xD7, 0x00, 0xFF)),
+    ("goldenrod", Color::from_rgba(0xDA, 0xA5, 0x20, 0xFF)),
+    ("gray", Color::from_rgba(0x80, 0x80, 0x80, 0xFF)),
+    ("green", Color::from_rgba(0x00, 0x80, 0x00, 0xFF)),
+    ("greenyellow", Color::from_rgba(0xAD, 0xFF, 0x2F, 0xFF)),
+    ("grey", Color::from_rgba(0x80, 0x80, 0x80, 0xFF)),
+    ("honeydew", Color::from_rgba(0xF0, 0xFF, 0xF0, 0xFF)),
+    ("hotpink", Color::from_rgba(0xFF, 0x69, 0xB4, 0xFF)),
+    ("indianred", Color::from_rgba(0xCD, 0x5C, 0x5C, 0xFF)),
+    ("indigo", Color::from_rgba(0x4B, 0x00, 0x82, 0xFF)),
+    ("ivory", Color::from_rgba(0xFF, 0xFF, 0xF0, 0xFF)),
+    ("khaki", Color::from_rgba(0xF0, 0xE6, 0x8C, 0xFF)),
+    ("lavender", Color::from_rgba(0xE6, 0xE6, 0xFA, 0xFF)),
+    ("lavenderblush", Color::from_rgba(0xFF, 0xF0, 0xF5, 0xFF)),
+    ("lawngreen", Color::from_rgba(0x7C, 0xFC, 0x00, 0xFF)),
+    ("lemonchiffon", Color::from_rgba(0xFF, 0xFA, 0xCD, 0xFF)),
+    ("lightblue", Color::from_rgba(0xAD, 0xD8, 0xE6, 0xFF)),
+    ("lightcoral", Color::from_rgba(0xF0, 0x80, 0x80, 0xFF)),
+    ("lightcyan", Color::from_rgba(0xE0, 0xFF, 0xFF, 0xFF)),
+    ("lightgoldenrodyellow", Color::from_rgba(0xFA, 0xFA, 0xD2, 0xFF)),
+    ("lightgray", Color::from_rgba(0xD3, 0xD3, 0xD3, 0xFF)),
+    ("lightgreen", Color::from_rgba(0x90, 0xEE, 0x90, 0xFF)),
+    ("lightgrey", Color::from_rgba(0xD3, 0xD3, 0xD3, 0xFF)),
+    ("lightpink", Color::from_rgba(0xFF, 0xB6, 0xC1, 0xFF)),
+    ("lightsalmon", Color::from_rgba(0xFF, 0xA0, 0x7A, 0xFF)),
+    ("lightseagreen", Color::from_rgba(0x20, 0xB2, 0xAA, 0xFF)),
+    ("lightskyblue", Color::from_rgba(0x87, 0xCE, 0xFA, 0xFF)),
+    ("lightslategray", Color::from_rgba(0x77, 0x88, 0x99, 0xFF)),
+    ("lightslategrey", Color::from_rgba(0x77, 0x88, 0x99, 0xFF)),
+    ("lightsteelblue", Color::from_rgba(0xB0, 0xC4, 0xDE, 0xFF)),
+    ("lightyellow", Color::from_rgba(0xFF, 0xFF, 0xE0, 0xFF)),
+    ("lime", Color::from_rgba(0x00, 0xFF, 0x00, 0xFF)),
+    ("limegreen", Color::from_rgba(0x32, 0xCD, 0x32, 0xFF)),
+    ("linen", Color::from_rgba(0xFA, 0xF0, 0xE6, 0xFF)),
+    ("magenta", Color::from_rgba(0xFF, 0x00, 0xFF, 0xFF)),
+    ("maroon", Color::from_rgba(0x80, 0x00, 0x00, 0xFF)),
+    ("mediumaquamarine", Color::from_rgba(0x66, 0xCD, 0xAA, 0xFF)),
+    ("mediumblue", Color::from_rgba(0x00, 0x00, 0xCD, 0xFF)),
+    ("mediumorchid", Color::from_rgba(0xBA, 0x55, 0xD3, 0xFF)),
+    ("mediumpurple", Color::from_rgba(0x93, 0x70, 0xDB, 0xFF)),
+    ("mediumseagreen", Color::from_rgba(0x3C, 0xB3, 0x71, 0xFF)),
+    ("mediumslateblue", Color::from_rgba(0x7B, 0x68, 0xEE, 0xFF)),
+    ("mediumspringgreen", Color::from_rgba(0x00, 0xFA, 0x9A, 0xFF)),
+    ("mediumturquoise", Color::from_rgba(0x48, 0xD1, 0xCC, 0xFF)),
+    ("mediumvioletred", Color::from_rgba(0xC7, 0x15, 0x85, 0xFF)),
+    ("midnightblue", Color::from_rgba(0x19, 0x19, 0x70, 0xFF)),
+    ("mintcream", Color::from_rgba(0xF5, 0xFF, 0xFA, 0xFF)),
+    ("mistyrose", Color::from_rgba(0xFF, 0xE4, 0xE1, 0xFF)),
+    ("moccasin", Color::from_rgba(0xFF, 0xE4, 0xB5, 0xFF)),
+    ("navajowhite", Color::from_rgba(0xFF, 0xDE, 0xAD, 0xFF)),
+    ("navy", Color::from_rgba(0x00, 0x00, 0x80, 0xFF)),
+    ("oldlace", Color::from_rgba(0xFD, 0xF5, 0xE6, 0xFF)),
+    ("olive", Color::from_rgba(0x80, 0x80, 0x00, 0xFF)),
+    ("olivedrab", Color::from_rgba(0x6B, 0x8E, 0x23, 0xFF)),
+    ("orange", Color::from_rgba(0xFF, 0xA5, 0x00, 0xFF)),
+    ("orangered", Color::from_rgba(0xFF, 0x45, 0x00, 0xFF)),
+    ("orchid", Color::from_rgba(0xDA, 0x70, 0xD6, 0xFF)),
+    ("palegoldenrod", Color::from_rgba(0xEE, 0xE8, 0xAA, 0xFF)),
+    ("palegreen", Color::from_rgba(0x98, 0xFB, 0x98, 0xFF)),
+    ("paleturquoise", Color::from_rgba(0xAF, 0xEE, 0xEE, 0xFF)),
+    ("palevioletred", Color::from_rgba(0xDB, 0x70, 0x93, 0xFF)),
+    ("papayawhip", Color::from_rgba(0xFF, 0xEF, 0xD5, 0xFF)),
+    ("peachpuff", Color::from_rgba(0xFF, 0xDA, 0xB9, 0xFF)),
+    ("peru", Color::from_rgba(0xCD, 0x85, 0x3F, 0xFF)),
+    ("pink", Color::from_rgba(0xFF, 0xC0, 0xCB, 0xFF)),
+    ("plum", Color::from_rgba(0xDD, 0xA0, 0xDD, 0xFF)),
+    ("powderblue", Color::from_rgba(0xB0, 0xE0, 0xE6, 0xFF)),
+    ("purple", Color::from_rgba(0x80, 0x00, 0x80, 0xFF)),
+    ("rebeccapurple", Color::from_rgba(0x66, 0x33, 0x99, 0xFF)),
+    ("red", Color::from_rgba(0xFF, 0x00, 0x00, 0xFF)),
+    ("rosybrown", Color::from_rgba(0xBC, 0x8F, 0x8F, 0xFF)),
+    ("royalblue", Color::from_rgba(0x41, 0x69, 0xE1, 0xFF)),
+    ("saddlebrown", Color::from_rgba(0x8B, 0x45, 0x13, 0xFF)),
+    ("salmon", Color::from_rgba(0xFA, 0x80, 0x72, 0xFF)),
+    ("sandybrown", Color::from_rgba(0xF4, 0xA4, 0x60, 0xFF)),
+    ("seagreen", Color::from_rgba(0x2E, 0x8B, 0x57, 0xFF)),
+    ("seashell", Color::from_rgba(0xFF, 0xF5, 0xEE, 0xFF)),
+    ("sienna", Color::from_rgba(0xA0, 0x52, 0x2D, 0xFF)),
+    ("silver", Color::from_rgba(0xC0, 0xC0, 0xC0, 0xFF)),
+    ("skyblue", Color::from_rgba(0x87, 0xCE, 0xEB, 0xFF)),
+    ("slateblue", Color::from_rgba(0x6A, 0x5A, 0xCD, 0xFF)),
+    ("slategray", Color::from_rgba(0x70, 0x80, 0x90, 0xFF)),
+    ("slategrey", Color::from_rgba(0x70, 0x80, 0x90, 0xFF)),
+    ("snow", Color::from_rgba(0xFF, 0xFA, 0xFA, 0xFF)),
+    ("springgreen", Color::from_rgba(0x00, 0xFF, 0x7F, 0xFF)),
+    ("steelblue", Color::from_rgba(0x46, 0x82, 0xB4, 0xFF)),
+    ("tan", Color::from_rgba(0xD2, 0xB4, 0x8C, 0xFF)),
+    ("teal", Color::from_rgba(0x00, 0x80, 0x80, 0xFF)),
+    ("thistle", Color::from_rgba(0xD8, 0xBF, 0xD8, 0xFF)),
+    ("tomato", Color::from_rgba(0xFF, 0x63, 0x47, 0xFF)),
+    ("transparent", Color::from_rgba(0x00, 0x00, 0x00, 0x00)),
+    ("turquoise", Color::from_rgba(0x40, 0xE0, 0xD0, 0xFF)),
+    ("violet", Color::from_rgba(0xEE, 0x82, 0xEE, 0xFF)),
+    ("wheat", Color::from_rgba(0xF5, 0xDE, 0xB3, 0xFF)),
+    ("white", Color::from_rgba(0xFF, 0xFF, 0xFF, 0xFF)),
+    ("whitesmoke", Color::from_rgba(0xF5, 0xF5, 0xF5, 0xFF)),
+    ("yellow", Color::from_rgba(0xFF, 0xFF, 0x00, 0xFF)),
+    ("yellowgreen", Color::from_rgba(0x9A, 0xCD, 0x32, 0xFF)),
+];
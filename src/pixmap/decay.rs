@@ -0,0 +1,64 @@
+//! A background task which lets the content of a [`Pixmap`](super::Pixmap) fade away over time
+//!
+//! This keeps long running canvases from filling up with stale content because everything that is not redrawn
+//! eventually disappears.
+
+use crate::pixmap::{Color, SharedPixmap};
+use crate::DaemonResult;
+use std::time::Duration;
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Interval;
+
+/// Configuration options for [`Decay`]
+#[derive(Debug)]
+pub struct DecayOptions {
+    /// The time after which the distance of a pixel to the background color is halved
+    pub half_life: Duration,
+
+    /// The color toward which pixels fade
+    pub background: Color,
+
+    /// The interval in which pixels are faded
+    ///
+    /// Shorter intervals make the fading look smoother but cost more CPU time.
+    pub interval: Interval,
+}
+
+/// A task which periodically fades all pixels of a pixmap toward a background color
+///
+/// Pixels are faded according to the time since they were last written if the pixmap has
+/// [attribution](super::Pixmap::with_attribution) enabled.
+#[derive(Debug)]
+pub struct Decay {
+    options: DecayOptions,
+    pixmap: SharedPixmap,
+}
+
+impl Decay {
+    /// Create a new task which lets the given pixmap decay
+    pub fn new(options: DecayOptions, pixmap: SharedPixmap) -> Self {
+        Self { options, pixmap }
+    }
+
+    /// Start fading the pixmap in the background
+    pub fn start(self, join_set: &mut JoinSet<DaemonResult>) -> anyhow::Result<AbortHandle> {
+        let handle = join_set
+            .build_task()
+            .name("decay")
+            .spawn(async move { self.run().await })?;
+        Ok(handle)
+    }
+
+    async fn run(mut self) -> anyhow::Result<!> {
+        let mut last_tick = self.options.interval.tick().await;
+        loop {
+            let tick = self.options.interval.tick().await;
+            let pixmap = self.pixmap.clone();
+            let (background, half_life, elapsed) =
+                (self.options.background, self.options.half_life, tick - last_tick);
+            // fading touches every pixel so it should not block other tasks in the meantime
+            tokio::task::spawn_blocking(move || pixmap.decay(background, half_life, elapsed)).await?;
+            last_tick = tick;
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Tracking of which regions of a [`Pixmap`](super::Pixmap) changed
+//!
+//! The pixmap is divided into square tiles which are stamped with a generation number whenever one of their pixels
+//! is written.
+//! Consumers keep a [`DirtyCursor`] which remembers the generation up to which they have already looked so that
+//! any number of them can track changes independently of each other.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A rectangular region of a pixmap
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Tile {
+    /// The x coordinate of the top left corner
+    pub x: usize,
+    /// The y coordinate of the top left corner
+    pub y: usize,
+    /// How many pixels the region spans horizontally
+    pub width: usize,
+    /// How many pixels the region spans vertically
+    pub height: usize,
+}
+
+/// The position of one consumer in the history of changes to a pixmap
+///
+/// A new cursor has not seen anything yet, so the first query with it reports the whole pixmap as changed.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct DirtyCursor {
+    seen: u64,
+}
+
+/// Generation stamps for all tiles of a pixmap
+#[derive(Debug)]
+pub(super) struct DirtyTiles {
+    tile_size: usize,
+    width: usize,
+    height: usize,
+    tiles_x: usize,
+    /// The generation which is currently being written
+    ///
+    /// It starts at 1 so that new cursors which have seen generation 0 report all tiles.
+    generation: AtomicU64,
+    stamps: Box<[AtomicU64]>,
+}
+
+impl DirtyTiles {
+    pub fn new(width: usize, height: usize, tile_size: usize) -> Self {
+        assert!(tile_size > 0, "tiles must be at least 1 pixel large");
+        let tiles_x = width.div_ceil(tile_size);
+        let tiles_y = height.div_ceil(tile_size);
+        Self {
+            tile_size,
+            width,
+            height,
+            tiles_x,
+            generation: AtomicU64::new(1),
+            stamps: (0..tiles_x * tiles_y).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Create tracking for a pixmap of a new size in which all tiles are dirty for every existing cursor
+    pub fn resized(&self, width: usize, height: usize) -> Self {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let resized = Self::new(width, height, self.tile_size);
+        resized.generation.store(generation, Ordering::SeqCst);
+        for stamp in resized.stamps.iter() {
+            stamp.store(generation, Ordering::Relaxed);
+        }
+        resized
+    }
+
+    /// Mark all tiles which overlap the given pixels as changed
+    ///
+    /// This must be called *after* the pixel data has been written so that a consumer which looks at the pixmap in
+    /// the meantime either already sees the new data or reports the tile the next time.
+    #[inline(always)]
+    pub fn mark(&self, columns: Range<usize>, rows: Range<usize>) {
+        if columns.is_empty() || rows.is_empty() {
+            return;
+        }
+        self.stamp(columns, rows, self.generation.load(Ordering::SeqCst));
+    }
+
+    /// Stamp all tiles which overlap the given pixels with `generation` or any later one that started meanwhile
+    #[inline(always)]
+    fn stamp(&self, columns: Range<usize>, rows: Range<usize>, mut generation: u64) {
+        let tile_columns = columns.start / self.tile_size..=(columns.end - 1) / self.tile_size;
+        loop {
+            for tile_row in rows.start / self.tile_size..=(rows.end - 1) / self.tile_size {
+                for stamp in &self.stamps[tile_row * self.tiles_x..][tile_columns.clone()] {
+                    // checking first keeps the cache line shared between writers while the tile is already marked
+                    if stamp.load(Ordering::Relaxed) < generation {
+                        stamp.fetch_max(generation, Ordering::SeqCst);
+                    }
+                }
+            }
+
+            // a consumer may have started a new generation and scanned the tiles before the stamps landed, in which
+            // case it has already moved its cursor past them and they need to be stamped again
+            let current = self.generation.load(Ordering::SeqCst);
+            if current == generation {
+                break;
+            }
+            generation = current;
+        }
+    }
+
+    /// Collect all tiles which changed since the cursor was last used and advance it
+    pub fn take(&self, cursor: &mut DirtyCursor) -> Vec<Tile> {
+        let current = self.generation.fetch_add(1, Ordering::SeqCst);
+        let tiles = self
+            .stamps
+            .iter()
+            .enumerate()
+            .filter(|(_, stamp)| stamp.load(Ordering::SeqCst) >= cursor.seen)
+            .map(|(i, _)| self.tile(i))
+            .collect();
+        // everything written from now on is stamped with a later generation, writes which were stamped with an older
+        // one after the scan are stamped again by `mark()`
+        cursor.seen = current + 1;
+        tiles
+    }
+
+    fn tile(&self, i: usize) -> Tile {
+        let x = (i % self.tiles_x) * self.tile_size;
+        let y = (i / self.tiles_x) * self.tile_size;
+        Tile {
+            x,
+            y,
+            width: self.tile_size.min(self.width - x),
+            height: self.tile_size.min(self.height - y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_stamps_of_an_old_generation_are_not_lost() {
+        let tiles = DirtyTiles::new(4, 4, 2);
+        let mut cursor = DirtyCursor::default();
+        tiles.take(&mut cursor);
+
+        // a writer loads the generation, then a consumer takes before the stamp lands
+        let generation = tiles.generation.load(Ordering::SeqCst);
+        assert!(tiles.take(&mut cursor).is_empty());
+        tiles.stamp(2..3, 0..1, generation);
+
+        assert_eq!(
+            tiles.take(&mut cursor),
+            vec![Tile {
+                x: 2,
+                y: 0,
+                width: 2,
+                height: 2
+            }]
+        );
+        assert!(tiles.take(&mut cursor).is_empty());
+    }
+
+    #[test]
+    fn test_no_tile_is_lost_to_concurrent_takes() {
+        const TILES: usize = 64;
+        for _ in 0..200 {
+            let tiles = Arc::new(DirtyTiles::new(TILES, 1, 1));
+            let mut cursor = DirtyCursor::default();
+            tiles.take(&mut cursor);
+
+            // every tile is marked exactly once while the consumer keeps taking
+            let done = Arc::new(AtomicBool::new(false));
+            let writer = std::thread::spawn({
+                let tiles = tiles.clone();
+                let done = done.clone();
+                move || {
+                    for x in 0..TILES {
+                        tiles.mark(x..x + 1, 0..1);
+                    }
+                    done.store(true, Ordering::SeqCst);
+                }
+            });
+            let mut reported = HashSet::new();
+            while !done.load(Ordering::SeqCst) {
+                reported.extend(tiles.take(&mut cursor).into_iter().map(|tile| tile.x));
+            }
+            writer.join().unwrap();
+            reported.extend(tiles.take(&mut cursor).into_iter().map(|tile| tile.x));
+
+            assert_eq!(reported.len(), TILES);
+            assert!(tiles.take(&mut cursor).is_empty());
+        }
+    }
+}
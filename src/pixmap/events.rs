@@ -0,0 +1,130 @@
+//! Broadcasting of changes that are made to a [`Pixmap`]
+//!
+//! Writers collect their changes with a [`ChangeRecorder`] and publish them in batches so that the cost of
+//! broadcasting is not paid for every single pixel.
+//! Subscribers receive these batches through a bounded channel.
+//! A subscriber that does not keep up loses the oldest batches and is told so via [`SubscriptionError::Lagged`],
+//! after which it should resynchronize from a full copy of the pixmap.
+
+use crate::pixmap::{Color, Pixmap};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// How many changes a [`ChangeRecorder`] collects before it publishes them on its own
+const MAX_BATCH_LEN: usize = 1024;
+
+/// A single change that was made to a pixmap
+///
+/// Coordinates are the ones that were written to, before the pixmaps
+/// [`CoordinatePolicy`](super::CoordinatePolicy) is applied.
+/// Colors which are not opaque have been composited over the existing pixels.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PixelChange {
+    /// A single pixel was written
+    Pixel {
+        /// The x coordinate of the pixel
+        x: usize,
+        /// The y coordinate of the pixel
+        y: usize,
+        /// The color that was written
+        color: Color,
+    },
+    /// A rectangle was filled with one color
+    Rect {
+        /// The x coordinate of the rectangles top left corner
+        x: usize,
+        /// The y coordinate of the rectangles top left corner
+        y: usize,
+        /// The width of the rectangle
+        width: usize,
+        /// The height of the rectangle
+        height: usize,
+        /// The color with which the rectangle was filled
+        color: Color,
+    },
+    /// The pixmap was resized, preserving the region which lies inside both the old and the new size
+    Resize {
+        /// The new width of the pixmap
+        width: usize,
+        /// The new height of the pixmap
+        height: usize,
+    },
+}
+
+/// Changes which were published together, in the order in which they were made
+pub type ChangeBatch = Arc<[PixelChange]>;
+
+/// An error which indicates that a [`ChangeSubscriber`] cannot receive the next batch of changes
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+pub enum SubscriptionError {
+    /// The subscriber did not keep up and the given number of batches was dropped
+    ///
+    /// Receiving again continues with the oldest batch that is still available.
+    #[error("subscriber fell behind and missed {0} batches of changes")]
+    Lagged(u64),
+    /// The pixmap has been dropped so no more changes will be published
+    #[error("the pixmap no longer publishes changes")]
+    Closed,
+}
+
+/// A handle through which batches of changes to a pixmap are received
+#[derive(Debug)]
+pub struct ChangeSubscriber {
+    receiver: broadcast::Receiver<ChangeBatch>,
+}
+
+impl ChangeSubscriber {
+    pub(super) fn new(receiver: broadcast::Receiver<ChangeBatch>) -> Self {
+        Self { receiver }
+    }
+
+    /// Wait for the next batch of changes
+    pub async fn recv(&mut self) -> Result<ChangeBatch, SubscriptionError> {
+        self.receiver.recv().await.map_err(|e| match e {
+            broadcast::error::RecvError::Lagged(n) => SubscriptionError::Lagged(n),
+            broadcast::error::RecvError::Closed => SubscriptionError::Closed,
+        })
+    }
+
+    /// Get the next batch of changes if one is already available
+    pub fn try_recv(&mut self) -> Result<Option<ChangeBatch>, SubscriptionError> {
+        match self.receiver.try_recv() {
+            Ok(batch) => Ok(Some(batch)),
+            Err(broadcast::error::TryRecvError::Empty) => Ok(None),
+            Err(broadcast::error::TryRecvError::Lagged(n)) => Err(SubscriptionError::Lagged(n)),
+            Err(broadcast::error::TryRecvError::Closed) => Err(SubscriptionError::Closed),
+        }
+    }
+}
+
+/// A buffer in which one writer collects its changes until they are published as one batch
+///
+/// Changes are only collected while somebody is subscribed to the pixmap.
+/// Pending changes are published once enough of them are collected or when [`flush()`](Self::flush) is called,
+/// which writers should do whenever they are done with a chunk of work.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ChangeRecorder {
+    pending: Vec<PixelChange>,
+}
+
+impl ChangeRecorder {
+    /// Record a change that was made to `pixmap`
+    #[inline(always)]
+    pub fn record(&mut self, pixmap: &Pixmap, change: PixelChange) {
+        if !pixmap.has_change_subscribers() {
+            return;
+        }
+        self.pending.push(change);
+        if self.pending.len() >= MAX_BATCH_LEN {
+            self.flush(pixmap);
+        }
+    }
+
+    /// Publish all pending changes to the subscribers of `pixmap`
+    pub fn flush(&mut self, pixmap: &Pixmap) {
+        if !self.pending.is_empty() {
+            pixmap.publish_changes(self.pending.drain(..).collect());
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! A bounded record of recent changes to a [`Pixmap`](super::Pixmap) which allows undoing them
+//!
+//! For every written pixel, the color it had before is remembered together with who wrote it and when.
+//! Once the history is full, the oldest changes are forgotten.
+//! Changes are first collected by the [`PixmapBatch`](super::PixmapBatch) which makes them and only added to the
+//! history when the batch ends, so that writers do not contend for the history on every pixel.
+//! Writes which happen concurrently to the same pixel may therefore be recorded in a different order than they were
+//! applied, so undoing them restores the pixel on a best-effort basis.
+
+use crate::pixmap::buffer::PixelBuffer;
+use crate::pixmap::{Color, WriterId};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The change of one pixel
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Entry {
+    index: usize,
+    previous: Color,
+    writer: WriterId,
+    time: SystemTime,
+}
+
+/// The most recent changes to the pixels of one canvas
+#[derive(Debug)]
+pub(super) struct History {
+    capacity: usize,
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Create an empty history with the same capacity
+    pub fn emptied(&self) -> Self {
+        Self::new(self.capacity)
+    }
+
+    /// Remember the current colors of all pixels in the given range in `pending` before `writer` overwrites them
+    /// at `time`
+    ///
+    /// The changes become part of the history once they are [committed](Self::commit).
+    #[inline(always)]
+    pub fn record(
+        &self,
+        pending: &mut Vec<Entry>,
+        range: Range<usize>,
+        writer: WriterId,
+        time: SystemTime,
+        data: &PixelBuffer,
+    ) {
+        pending.extend(range.map(|index| Entry {
+            index,
+            previous: data.load(index),
+            writer,
+            time,
+        }));
+    }
+
+    /// Add all `pending` changes to the history, forgetting the oldest ones if it is full
+    pub fn commit(&self, pending: &mut Vec<Entry>) {
+        if pending.is_empty() {
+            return;
+        }
+        let skipped = pending.len().saturating_sub(self.capacity);
+        let mut entries = self.entries.lock().unwrap();
+        let overflow = (entries.len() + pending.len() - skipped).saturating_sub(self.capacity);
+        entries.drain(..overflow);
+        entries.extend(pending.drain(..).skip(skipped));
+    }
+
+    /// Remove all changes which were made since `since` by `writer` (or by anybody if it is `None`) from the
+    /// history.
+    ///
+    /// Returns the pixels that need to be restored together with their previous color, in the order in which they
+    /// should be restored.
+    /// Changes which were overwritten later on by somebody else are kept because undoing them would destroy the
+    /// newer content.
+    pub fn take(&self, since: SystemTime, writer: Option<WriterId>) -> Vec<(usize, Color)> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut restore = Vec::new();
+        let mut undone = HashSet::new();
+        let mut overwritten = HashSet::new();
+        for (position, entry) in entries.iter().enumerate().rev() {
+            // batches are committed when they end, so older changes may follow newer ones
+            if entry.time < since {
+                continue;
+            }
+            if writer.is_some_and(|writer| writer != entry.writer) {
+                overwritten.insert(entry.index);
+            } else if !overwritten.contains(&entry.index) {
+                restore.push((entry.index, entry.previous));
+                undone.insert(position);
+            }
+        }
+
+        let mut position = 0;
+        entries.retain(|_| {
+            position += 1;
+            !undone.contains(&(position - 1))
+        });
+        restore
+    }
+}
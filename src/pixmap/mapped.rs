@@ -0,0 +1,215 @@
+//! Pixel storage which lives in a memory-mapped file
+//!
+//! The file uses the same format as the snapshots of the
+//! [`FileSink`](crate::sinks::pixmap_file::FileSink): the magic bytes `PIXELFLUT`, the width and height as big-endian
+//! 64-bit integers and then three bytes of RGB data per pixel, row by row.
+//! Because every write lands directly in the shared mapping, the file always holds the current canvas, survives a
+//! crash of the server and can be opened read-only by other processes which want to render it.
+//!
+//! Each color channel is accessed atomically on its own, so concurrent writes to the same pixel may mix the
+//! channels of both colors.
+//! Transparency is not stored, all loaded pixels are opaque.
+
+use crate::pixmap::storage::InvalidSizeError;
+use crate::pixmap::Color;
+use memmap2::{MmapMut, MmapOptions};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use thiserror::Error;
+
+/// The magic bytes at the start of every pixmap file
+pub(crate) const FILE_MAGIC: &[u8] = b"PIXELFLUT";
+/// The length of the header which consists of the magic bytes, width and height
+const HEADER_LEN: usize = FILE_MAGIC.len() + size_of::<u64>() * 2;
+
+/// An error which occurs when a file cannot be used to back a [`Pixmap`](super::Pixmap)
+#[derive(Debug, Error)]
+pub enum MapFileError {
+    /// The requested size is not valid for any pixmap
+    #[error(transparent)]
+    InvalidSize(#[from] InvalidSizeError),
+    /// The file could not be opened, resized or mapped
+    #[error("could not map pixmap file: {0}")]
+    Io(#[from] io::Error),
+    /// The file exists but does not start with the `PIXELFLUT` magic
+    #[error("file does not contain pixmap data")]
+    InvalidMagic,
+    /// The file holds a pixmap of a different size
+    #[error("file holds a pixmap of size {}x{} instead of {}x{}", .found.0, .found.1, .expected.0, .expected.1)]
+    SizeMismatch {
+        /// The size which was requested
+        expected: (usize, usize),
+        /// The size which is stored in the header
+        found: (usize, usize),
+    },
+    /// The file is not as long as its header says
+    #[error("file is {found} bytes long but its header requires {expected} bytes")]
+    InvalidLength {
+        /// The length which is required by the header
+        expected: u64,
+        /// The actual length of the file
+        found: u64,
+    },
+}
+
+/// Pixel storage in which every pixel is three atomic bytes of a memory-mapped file
+#[derive(Debug)]
+pub(super) struct MappedBuffer {
+    /// Keeps the file mapped for as long as the buffer exists
+    _map: MmapMut,
+    /// The start of the pixel data inside the mapping
+    data: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the pixel data is only ever accessed through atomics and the mapping which `data` points into is owned by
+// the buffer, so it can be shared with and sent to other threads just like `MmapMut` itself.
+unsafe impl Send for MappedBuffer {}
+unsafe impl Sync for MappedBuffer {}
+
+impl MappedBuffer {
+    /// Map the file at `path` as a buffer of `width` by `height` pixels
+    ///
+    /// A missing or empty file is initialized with a header and black pixels.
+    /// So is a file whose header is still zeroed because the server crashed while initializing it.
+    /// Otherwise, its header and length are validated and its pixel data is kept.
+    pub fn open(path: &Path, width: usize, height: usize) -> Result<Self, MapFileError> {
+        let len = width * height;
+        let file_len = (len as u64)
+            .checked_mul(3)
+            .and_then(|data_len| data_len.checked_add(HEADER_LEN as u64))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "pixmap is too large for a file"))?;
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let found_len = file.metadata()?.len();
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        (&file).take(HEADER_LEN as u64).read_to_end(&mut header)?;
+        let is_new = found_len <= file_len && header.iter().all(|&byte| byte == 0);
+        if is_new {
+            file.set_len(file_len)?;
+        }
+
+        // SAFETY: the mapping is only ever accessed through atomics, so concurrent modification by other processes
+        // is well-defined. Truncating the file while it is mapped still crashes this process, which is the same
+        // contract every user of shared file mappings has to accept.
+        let mut map = unsafe { MmapOptions::new().map_mut(&file)? };
+        if is_new {
+            map[..FILE_MAGIC.len()].copy_from_slice(FILE_MAGIC);
+            map[FILE_MAGIC.len()..HEADER_LEN - size_of::<u64>()]
+                .copy_from_slice(&(width as u64).to_be_bytes());
+            map[HEADER_LEN - size_of::<u64>()..HEADER_LEN].copy_from_slice(&(height as u64).to_be_bytes());
+            map.flush_range(0, HEADER_LEN)?;
+        } else {
+            validate_header(&map[..HEADER_LEN.min(map.len())], (width, height))?;
+            if found_len != file_len {
+                return Err(MapFileError::InvalidLength {
+                    expected: file_len,
+                    found: found_len,
+                });
+            }
+        }
+
+        let data = map.as_mut_ptr().wrapping_add(HEADER_LEN);
+        Ok(Self { _map: map, data, len })
+    }
+
+    /// The number of pixels in this buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn load(&self, i: usize) -> Color {
+        load_pixel(&self.data()[i * 3..i * 3 + 3])
+    }
+
+    #[inline(always)]
+    pub fn store(&self, i: usize, color: Color) {
+        store_pixel(&self.data()[i * 3..i * 3 + 3], <[u8; 3]>::from(color));
+    }
+
+    /// Replace the color of a pixel only if it still has the `current` color
+    ///
+    /// The channels are compared and replaced one after another, so this stops at the first channel which was
+    /// changed by somebody else.
+    #[inline(always)]
+    pub fn compare_exchange(&self, i: usize, current: Color, new: Color) -> bool {
+        let pixel = &self.data()[i * 3..i * 3 + 3];
+        let (current, new) = (<[u8; 3]>::from(current), <[u8; 3]>::from(new));
+        (0..3).all(|c| {
+            pixel[c]
+                .compare_exchange(current[c], new[c], Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        })
+    }
+
+    /// Set all pixels in the given range to one color
+    #[inline(always)]
+    pub fn fill(&self, range: Range<usize>, color: Color) {
+        let channels = <[u8; 3]>::from(color);
+        for pixel in self.data()[range.start * 3..range.end * 3].chunks_exact(3) {
+            store_pixel(pixel, channels);
+        }
+    }
+
+    /// Append the colors of all pixels in the given range to `dst`
+    #[inline(always)]
+    pub fn extend_into(&self, range: Range<usize>, dst: &mut Vec<Color>) {
+        dst.extend(
+            self.data()[range.start * 3..range.end * 3]
+                .chunks_exact(3)
+                .map(load_pixel),
+        );
+    }
+
+    /// The pixel data behind the header
+    #[inline(always)]
+    fn data(&self) -> &[AtomicU8] {
+        // SAFETY: `data` was derived from a mutable pointer to the writable mapping which is at least
+        // `HEADER_LEN + len * 3` bytes long and stays alive as long as `self`.
+        // `AtomicU8` has the same layout as `u8` and allows shared mutation of the pixel data.
+        unsafe { std::slice::from_raw_parts(self.data.cast::<AtomicU8>(), self.len * 3) }
+    }
+}
+
+#[inline(always)]
+fn load_pixel(pixel: &[AtomicU8]) -> Color {
+    Color::from([
+        pixel[0].load(Ordering::Relaxed),
+        pixel[1].load(Ordering::Relaxed),
+        pixel[2].load(Ordering::Relaxed),
+    ])
+}
+
+#[inline(always)]
+fn store_pixel(pixel: &[AtomicU8], channels: [u8; 3]) {
+    for (channel, value) in pixel.iter().zip(channels) {
+        channel.store(value, Ordering::Relaxed);
+    }
+}
+
+/// Verify that a mapped file starts with a header which describes a pixmap of the `expected` size
+fn validate_header(map: &[u8], expected: (usize, usize)) -> Result<(), MapFileError> {
+    if map.len() < HEADER_LEN || &map[..FILE_MAGIC.len()] != FILE_MAGIC {
+        return Err(MapFileError::InvalidMagic);
+    }
+    let read_u64 =
+        |offset: usize| u64::from_be_bytes(map[offset..offset + size_of::<u64>()].try_into().unwrap());
+    let found = (
+        read_u64(FILE_MAGIC.len()) as usize,
+        read_u64(FILE_MAGIC.len() + size_of::<u64>()) as usize,
+    );
+    if found != expected {
+        return Err(MapFileError::SizeMismatch { expected, found });
+    }
+    Ok(())
+}
@@ -6,10 +6,33 @@ use std::sync::Arc;
 
 pub use color::*;
 
+mod activity;
+mod attribution;
+mod buffer;
 mod color;
+mod css_colors;
+mod decay;
+mod dirty;
+mod events;
+mod history;
+mod mapped;
+mod protection;
+mod scale;
 mod storage;
 
-pub use storage::{InvalidCoordinatesError, Pixmap};
+pub use activity::ClientGuard;
+pub use attribution::{LastWrite, TooManyWritersError, WriterId};
+pub use decay::{Decay, DecayOptions};
+pub use dirty::{DirtyCursor, Tile};
+pub use events::{ChangeBatch, ChangeRecorder, ChangeSubscriber, PixelChange, SubscriptionError};
+pub use mapped::MapFileError;
+pub(crate) use mapped::FILE_MAGIC;
+pub use protection::ProtectionMask;
+pub use scale::{ScaleFilter, Scaler, UnknownScaleFilterError};
+pub use storage::{
+    CoordinatePolicy, InvalidCoordinatesError, InvalidSizeError, Pixmap, PixmapBatch,
+    UnknownCoordinatePolicyError,
+};
 
 /// A [`Pixmap`] which can be used throughout multiple threads
 ///
@@ -0,0 +1,103 @@
+//! Regions of a [`Pixmap`](super::Pixmap) which clients are not allowed to write
+//!
+//! Protection is only enforced for client requests so that the operator can still draw into protected regions,
+//! e.g. to place a logo there.
+//! It is defined in pixmap coordinates, so regions which end up outside of the pixmap after a resize have no effect
+//! until it grows again.
+
+use crate::pixmap::Tile;
+use std::ops::Range;
+
+/// A mask which marks individual pixels as protected
+///
+/// The mask is aligned with the top left corner of the pixmap.
+/// Pixels outside of it are not protected.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProtectionMask {
+    width: usize,
+    height: usize,
+    protected: Vec<bool>,
+}
+
+impl ProtectionMask {
+    /// Create a mask of `width` by `height` pixels where `protected` states row by row whether each pixel is
+    /// protected
+    ///
+    /// # Panics
+    ///
+    /// Panics if `protected` does not contain exactly `width * height` values.
+    pub fn new(width: usize, height: usize, protected: Vec<bool>) -> Self {
+        assert_eq!(
+            protected.len(),
+            width * height,
+            "mask data does not fit the mask size"
+        );
+        Self {
+            width,
+            height,
+            protected,
+        }
+    }
+
+    /// The number of pixels which are protected by this mask
+    pub fn protected_pixels(&self) -> usize {
+        self.protected.iter().filter(|&&protected| protected).count()
+    }
+}
+
+/// All protected regions of one pixmap
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(super) struct Protection {
+    pub rects: Vec<Tile>,
+    pub mask: Option<ProtectionMask>,
+}
+
+impl Protection {
+    /// Whether nothing is protected at all
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty() && self.mask.is_none()
+    }
+
+    /// Mark which pixels of `row` are protected in `columns`, which holds one value per column of the pixmap
+    ///
+    /// This is cheaper than checking every pixel with [`overlaps()`](Self::overlaps) when whole rows are processed.
+    pub fn mark_row(&self, row: usize, columns: &mut [bool]) {
+        columns.fill(false);
+        for rect in &self.rects {
+            if rect.y <= row && row < rect.y.saturating_add(rect.height) {
+                let end = rect.x.saturating_add(rect.width).min(columns.len());
+                columns[rect.x.min(end)..end].fill(true);
+            }
+        }
+        if let Some(mask) = self.mask.as_ref().filter(|mask| row < mask.height) {
+            let width = mask.width.min(columns.len());
+            let protected = &mask.protected[row * mask.width..row * mask.width + width];
+            for (column, &protected) in columns.iter_mut().zip(protected) {
+                *column |= protected;
+            }
+        }
+    }
+
+    /// Whether any pixel of the given region is protected
+    pub fn overlaps(&self, columns: Range<usize>, rows: Range<usize>) -> bool {
+        let rect_overlaps = |rect: &Tile| {
+            columns.start < rect.x.saturating_add(rect.width)
+                && rect.x < columns.end
+                && rows.start < rect.y.saturating_add(rect.height)
+                && rect.y < rows.end
+        };
+        if self.rects.iter().any(rect_overlaps) {
+            return true;
+        }
+        let Some(mask) = &self.mask else {
+            return false;
+        };
+        let columns = columns.start.min(mask.width)..columns.end.min(mask.width);
+        (rows.start.min(mask.height)..rows.end.min(mask.height)).any(|row| {
+            mask.protected[row * mask.width + columns.start..row * mask.width + columns.end]
+                .iter()
+                .any(|&protected| protected)
+        })
+    }
+}
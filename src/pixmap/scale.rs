@@ -0,0 +1,330 @@
+//! Resampling of pixel data to a different size
+//!
+//! A [`Scaler`] precomputes which source pixels contribute to every output row and column so that sinks which
+//! repeatedly scale frames of the same size only pay for that once.
+//! Sinks use the cheap interpolating filters while one-off scaling of images, e.g. before they are drawn onto a
+//! canvas, can afford to average all source pixels.
+
+use crate::pixmap::Color;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// How pixels are sampled when scaling pixel data
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ScaleFilter {
+    /// Use the color of the source pixel which is closest to the sampled position
+    ///
+    /// This is fast and keeps edges sharp but makes downscaled content flicker when it changes.
+    #[default]
+    Nearest,
+    /// Interpolate linearly between the four source pixels around the sampled position
+    Bilinear,
+    /// Average all source pixels which an output pixel covers, weighted by how much of them it covers
+    ///
+    /// This is slower than the other filters but does not alias when content is scaled down a lot.
+    /// When scaling up, it behaves like [`Nearest`](Self::Nearest) except that pixels are blended at their borders.
+    Area,
+}
+
+/// An error which indicates that a string does not name a [`ScaleFilter`]
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+#[error("unknown scale filter, expected nearest, bilinear or area")]
+pub struct UnknownScaleFilterError;
+
+impl FromStr for ScaleFilter {
+    type Err = UnknownScaleFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            "area" => Ok(Self::Area),
+            _ => Err(UnknownScaleFilterError),
+        }
+    }
+}
+
+/// The source pixels which contribute to one output row or column
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Sample {
+    low: usize,
+    high: usize,
+    /// How much `high` contributes, in 256ths
+    weight: u16,
+}
+
+/// A run of source pixels which are averaged into one output row or column
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Span {
+    start: usize,
+    /// How much each source pixel starting at `start` contributes, in [`SPAN_WEIGHT_ONE`]ths
+    weights: Vec<u64>,
+}
+
+/// The weight with which a source pixel contributes if it is the only one in a [`Span`]
+const SPAN_WEIGHT_ONE: u64 = 1 << 16;
+
+/// How the source pixels of each output row and column are determined
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Kernel {
+    /// Interpolate between at most two source pixels per row and column
+    Interpolate { columns: Vec<Sample>, rows: Vec<Sample> },
+    /// Average a run of source pixels per row and column
+    Average { columns: Vec<Span>, rows: Vec<Span> },
+}
+
+/// Scales pixel data of one size to another
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Scaler {
+    src_width: usize,
+    src_height: usize,
+    kernel: Kernel,
+}
+
+impl Scaler {
+    /// Create a scaler from `src_width` by `src_height` pixels to `dst_width` by `dst_height` pixels
+    pub fn new(
+        src_width: usize,
+        src_height: usize,
+        dst_width: usize,
+        dst_height: usize,
+        filter: ScaleFilter,
+    ) -> Self {
+        let kernel = match filter {
+            ScaleFilter::Area => Kernel::Average {
+                columns: spans(src_width, dst_width),
+                rows: spans(src_height, dst_height),
+            },
+            ScaleFilter::Nearest | ScaleFilter::Bilinear => Kernel::Interpolate {
+                columns: samples(src_width, dst_width, filter),
+                rows: samples(src_height, dst_height, filter),
+            },
+        };
+        Self {
+            src_width,
+            src_height,
+            kernel,
+        }
+    }
+
+    /// Whether scaling changes the size at all
+    ///
+    /// If it does not, the data can be used as is.
+    pub fn is_identity(&self) -> bool {
+        (self.src_width, self.src_height) == self.output_size()
+    }
+
+    /// The size of the scaled data
+    pub fn output_size(&self) -> (usize, usize) {
+        match &self.kernel {
+            Kernel::Interpolate { columns, rows } => (columns.len(), rows.len()),
+            Kernel::Average { columns, rows } => (columns.len(), rows.len()),
+        }
+    }
+
+    /// Scale `src` which must be row-major data of the source size and write the result into `dst`
+    ///
+    /// `dst` is replaced completely.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `src` does not contain exactly as many pixels as the source size requires.
+    pub fn scale<T: From<Color>>(&self, src: &[Color], dst: &mut Vec<T>) {
+        assert_eq!(
+            src.len(),
+            self.src_width * self.src_height,
+            "source data does not fit the scaler"
+        );
+
+        dst.clear();
+        let (width, height) = self.output_size();
+        dst.reserve(width * height);
+        match &self.kernel {
+            Kernel::Interpolate { columns, rows } => {
+                for row in rows {
+                    let low_row = &src[row.low * self.src_width..(row.low + 1) * self.src_width];
+                    let high_row = &src[row.high * self.src_width..(row.high + 1) * self.src_width];
+                    for column in columns {
+                        let top = lerp(low_row[column.low], low_row[column.high], column.weight);
+                        let bottom = lerp(high_row[column.low], high_row[column.high], column.weight);
+                        dst.push(T::from(lerp(top, bottom, row.weight)));
+                    }
+                }
+            }
+            Kernel::Average { columns, rows } => {
+                for row in rows {
+                    for column in columns {
+                        dst.push(T::from(self.average(src, row, column)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Average the source pixels in the rectangle which is spanned by one output row and column
+    fn average(&self, src: &[Color], row: &Span, column: &Span) -> Color {
+        let mut sums = [0u64; 4];
+        for (y, row_weight) in (row.start..).zip(&row.weights) {
+            let src_row = &src[y * self.src_width..(y + 1) * self.src_width];
+            for (&color, column_weight) in src_row[column.start..].iter().zip(&column.weights) {
+                let weight = row_weight * column_weight;
+                for (sum, channel) in sums.iter_mut().zip(<[u8; 4]>::from(color)) {
+                    *sum += channel as u64 * weight;
+                }
+            }
+        }
+        let total = SPAN_WEIGHT_ONE * SPAN_WEIGHT_ONE;
+        Color::from(sums.map(|sum| ((sum + total / 2) / total) as u8))
+    }
+}
+
+/// Calculate which of `src_len` source pixels are sampled for each of `dst_len` output pixels
+///
+/// The [`Area`](ScaleFilter::Area) filter does not sample but averages [`spans()`] instead.
+fn samples(src_len: usize, dst_len: usize, filter: ScaleFilter) -> Vec<Sample> {
+    (0..dst_len)
+        .map(|i| match filter {
+            ScaleFilter::Nearest => {
+                let low = i * src_len / dst_len;
+                Sample {
+                    low,
+                    high: low,
+                    weight: 0,
+                }
+            }
+            ScaleFilter::Bilinear => {
+                // sample at the center of the output pixel, measured in 256ths of a source pixel
+                let center = ((2 * i + 1) * src_len * 256 / (2 * dst_len)).saturating_sub(128);
+                let low = (center / 256).min(src_len - 1);
+                Sample {
+                    low,
+                    high: (low + 1).min(src_len - 1),
+                    weight: if low + 1 < src_len {
+                        (center % 256) as u16
+                    } else {
+                        0
+                    },
+                }
+            }
+            ScaleFilter::Area => unreachable!("area scaling averages spans instead of sampling"),
+        })
+        .collect()
+}
+
+/// Calculate which of `src_len` source pixels each of `dst_len` output pixels covers and by how much
+fn spans(src_len: usize, dst_len: usize) -> Vec<Span> {
+    // output pixel i covers i * src_len..(i + 1) * src_len and source pixel j covers j * dst_len..(j + 1) * dst_len
+    let (src_len, dst_len) = (src_len as u64, dst_len as u64);
+    (0..dst_len)
+        .map(|i| {
+            let (begin, end) = (i * src_len, (i + 1) * src_len);
+            let start = begin / dst_len;
+            let mut weights = (start..end.div_ceil(dst_len))
+                .map(|j| {
+                    let overlap = end.min((j + 1) * dst_len) - begin.max(j * dst_len);
+                    overlap * SPAN_WEIGHT_ONE / src_len
+                })
+                .collect::<Vec<_>>();
+            // rounding must not change the brightness, so the first pixel receives what is left over
+            let rest = SPAN_WEIGHT_ONE - weights.iter().sum::<u64>();
+            weights[0] += rest;
+            Span {
+                start: start as usize,
+                weights,
+            }
+        })
+        .collect()
+}
+
+/// Interpolate between two colors where `weight` is the contribution of `b` in 256ths
+#[inline(always)]
+fn lerp(a: Color, b: Color, weight: u16) -> Color {
+    if weight == 0 {
+        return a;
+    }
+    let (a, b) = (<[u8; 4]>::from(a), <[u8; 4]>::from(b));
+    let weight = weight as u32;
+    let channel = |i: usize| ((a[i] as u32 * (256 - weight) + b[i] as u32 * weight + 128) / 256) as u8;
+    Color::from([channel(0), channel(1), channel(2), channel(3)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    quickcheck! {
+        fn test_same_size_is_identity(width: u8, height: u8, filter: u8) -> TestResult {
+            let (width, height) = (width as usize, height as usize);
+            if width == 0 || height == 0 {
+                return TestResult::discard();
+            }
+            let filter = [ScaleFilter::Nearest, ScaleFilter::Bilinear, ScaleFilter::Area][filter as usize % 3];
+            let scaler = Scaler::new(width, height, width, height, filter);
+            let src = (0..width * height).map(|i| Color::from(i as u32)).collect::<Vec<_>>();
+            let mut dst = Vec::<Color>::new();
+            scaler.scale(&src, &mut dst);
+            TestResult::from_bool(scaler.is_identity() && dst == src)
+        }
+
+        fn test_scaled_colors_stay_in_range(width: u8, height: u8, filter: u8) -> TestResult {
+            let (width, height) = (width as usize, height as usize);
+            if width == 0 || height == 0 {
+                return TestResult::discard();
+            }
+            let filter = [ScaleFilter::Nearest, ScaleFilter::Bilinear, ScaleFilter::Area][filter as usize % 3];
+            let scaler = Scaler::new(4, 2, width, height, filter);
+            let src = [0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80].map(|v| Color::from((v, v, v)));
+            let mut dst = Vec::<Color>::new();
+            scaler.scale(&src, &mut dst);
+            TestResult::from_bool(
+                dst.len() == width * height
+                    && dst.iter().all(|&c| (0x10..=0x80).contains(&<[u8; 3]>::from(c)[0]) && c.is_opaque()),
+            )
+        }
+    }
+
+    #[test]
+    fn test_scale_filters() {
+        let black = Color::from((0, 0, 0));
+        let white = Color::from((0xFF, 0xFF, 0xFF));
+        let mut dst = Vec::<Color>::new();
+
+        Scaler::new(2, 1, 4, 1, ScaleFilter::Nearest).scale(&[black, white], &mut dst);
+        assert_eq!(dst, vec![black, black, white, white]);
+
+        Scaler::new(2, 1, 4, 1, ScaleFilter::Bilinear).scale(&[black, white], &mut dst);
+        assert_eq!(dst[0], black);
+        assert_eq!(dst[3], white);
+        assert!(<[u8; 3]>::from(dst[1])[0] < <[u8; 3]>::from(dst[2])[0]);
+
+        Scaler::new(4, 2, 2, 1, ScaleFilter::Bilinear).scale(
+            &[black, white, black, white, white, black, white, black],
+            &mut dst,
+        );
+        assert_eq!(dst, vec![Color::from((0x80, 0x80, 0x80)); 2]);
+
+        // averaging considers every source pixel, so a fine pattern becomes gray instead of aliasing
+        let checkerboard = (0..8 * 8)
+            .map(|i| if (i + i / 8) % 2 == 0 { black } else { white })
+            .collect::<Vec<_>>();
+        Scaler::new(8, 8, 2, 2, ScaleFilter::Area).scale(&checkerboard, &mut dst);
+        assert_eq!(dst, vec![Color::from((0x80, 0x80, 0x80)); 4]);
+        Scaler::new(8, 8, 2, 2, ScaleFilter::Nearest).scale(&checkerboard, &mut dst);
+        assert_eq!(dst, vec![black; 4]);
+
+        // output pixels which cover source pixels partially weigh them by the covered area
+        Scaler::new(3, 1, 2, 1, ScaleFilter::Area).scale(&[black, white, white], &mut dst);
+        assert_eq!(dst, vec![Color::from((0x55, 0x55, 0x55)), white]);
+        Scaler::new(2, 1, 4, 1, ScaleFilter::Area).scale(&[black, white], &mut dst);
+        assert_eq!(dst, vec![black, black, white, white]);
+    }
+
+    #[test]
+    fn test_parse_scale_filter() {
+        assert_eq!("nearest".parse(), Ok(ScaleFilter::Nearest));
+        assert_eq!("Bilinear".parse(), Ok(ScaleFilter::Bilinear));
+        assert_eq!("AREA".parse(), Ok(ScaleFilter::Area));
+        assert_eq!("cubic".parse::<ScaleFilter>(), Err(UnknownScaleFilterError));
+    }
+}
@@ -1,13 +1,122 @@
+use crate::pixmap::activity::{Activity, ClientGuard};
+use crate::pixmap::attribution::{LastWrite, TooManyWritersError, WriteStamps, WriterId, WriterRegistry};
+use crate::pixmap::buffer::PixelBuffer;
+use crate::pixmap::dirty::{DirtyCursor, DirtyTiles, Tile};
+use crate::pixmap::events::{ChangeBatch, ChangeSubscriber, PixelChange};
+use crate::pixmap::history::{self, History};
+use crate::pixmap::mapped::{MapFileError, MappedBuffer};
+use crate::pixmap::protection::{Protection, ProtectionMask};
+use crate::pixmap::scale::{ScaleFilter, Scaler};
 use crate::pixmap::Color;
-use std::cell::SyncUnsafeCell;
+use arc_swap::{ArcSwap, Guard};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
+use tokio::sync::{broadcast, watch};
 
 /// A fast pixel storage implementation
+///
+/// Pixmaps can be accessed concurrently from many threads.
+/// Individual pixels are always read and written as a whole but no ordering is guaranteed between accesses to
+/// different pixels, so readers may observe a mix of old and new data while a rectangle is being filled.
+///
+/// The size of a pixmap can change at runtime, so it should be queried again instead of being cached for long.
 #[derive(Debug)]
 pub struct Pixmap {
-    data: SyncUnsafeCell<Vec<Color>>,
+    /// The pixel data which is replaced as a whole when the pixmap is resized
+    canvas: ArcSwap<Canvas>,
+    /// Held while resizing so that concurrent resizes do not overwrite each other
+    resize_lock: Mutex<()>,
+    size: watch::Sender<(usize, usize)>,
+    changes: Option<broadcast::Sender<ChangeBatch>>,
+    writers: Option<WriterRegistry>,
+    /// Regions which clients may not write
+    protection: ArcSwap<Protection>,
+    activity: Activity,
+}
+
+/// A number of accesses to a [`Pixmap`] which are made in quick succession
+///
+/// A batch keeps using the canvas and protected regions which the pixmap had when the batch was started.
+/// Writes which are made through a batch while the pixmap is resized are lost, so batches should not be kept
+/// around for long.
+#[derive(Debug)]
+pub struct PixmapBatch {
+    canvas: Guard<Arc<Canvas>>,
+    protection: Guard<Arc<Protection>>,
+    author: Author,
+}
+
+/// Who makes a number of writes and when, together with the history of these writes
+///
+/// The time is only read once for all writes of a [`PixmapBatch`] because reading the clock for every pixel would
+/// slow down writes considerably.
+/// For the same reason, the history is collected here and only added to the canvas once all writes are made.
+#[derive(Debug)]
+struct Author {
+    writer: WriterId,
+    time: SystemTime,
+    history: Vec<history::Entry>,
+}
+
+/// The pixel data of a [`Pixmap`] at one size
+#[derive(Debug)]
+struct Canvas {
+    data: PixelBuffer,
     width: usize,
     height: usize,
+    policy: CoordinatePolicy,
+    dirty: Option<DirtyTiles>,
+    writes: Option<WriteStamps>,
+    history: Option<History>,
+}
+
+/// The part of a rectangle which lands on one contiguous region of a [`Canvas`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Region {
+    columns: Range<usize>,
+    rows: Range<usize>,
+    /// The position of the region relative to the top left corner of the rectangle
+    offset: (usize, usize),
+}
+
+/// How a [`Pixmap`] treats coordinates that lie outside of it
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum CoordinatePolicy {
+    /// Accessing coordinates outside of the pixmap results in an error
+    #[default]
+    Reject,
+    /// Writes to coordinates outside of the pixmap are silently discarded and rectangles are cut off at the edges
+    ///
+    /// Reads of coordinates outside of the pixmap still result in an error because there is nothing to read.
+    Clip,
+    /// Coordinates wrap around at the edges so that the pixmap behaves like the surface of a torus
+    Wrap,
+}
+
+/// An error which indicates that a string does not name a [`CoordinatePolicy`]
+#[derive(Debug, Error, Copy, Clone, Eq, PartialEq)]
+#[error("unknown coordinate policy, expected one of 'reject', 'clip' or 'wrap'")]
+pub struct UnknownCoordinatePolicyError;
+
+impl FromStr for CoordinatePolicy {
+    type Err = UnknownCoordinatePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("reject") {
+            Ok(CoordinatePolicy::Reject)
+        } else if s.eq_ignore_ascii_case("clip") {
+            Ok(CoordinatePolicy::Clip)
+        } else if s.eq_ignore_ascii_case("wrap") {
+            Ok(CoordinatePolicy::Wrap)
+        } else {
+            Err(UnknownCoordinatePolicyError)
+        }
+    }
 }
 
 /// An error which indicates that invalid coordinates could not be accessed
@@ -29,74 +138,1217 @@ pub struct InvalidSizeError {
 impl Pixmap {
     /// Create a new Pixmap with the specified dimensions
     pub fn new(width: usize, height: usize) -> Result<Self, InvalidSizeError> {
-        if width == 0 || height == 0 {
+        validate_size(width, height)?;
+        Ok(Self::from_canvas(Canvas {
+            data: PixelBuffer::new(width * height),
+            width,
+            height,
+            policy: CoordinatePolicy::default(),
+            dirty: None,
+            writes: None,
+            history: None,
+        }))
+    }
+
+    /// Create a new Pixmap with the specified dimensions that holds the given pixel data
+    ///
+    /// The data is expected to contain the colors of all pixels row by row, starting at the top left.
+    pub fn from_color_data(width: usize, height: usize, data: Vec<Color>) -> Result<Self, InvalidSizeError> {
+        validate_size(width, height)?;
+        if data.len() != width * height {
             return Err(InvalidSizeError {
                 size: (width, height),
-                details: "Width and Height must both be greater than 0",
+                details: "The amount of pixel data does not match the size",
             });
         }
 
-        Ok(Self {
-            data: SyncUnsafeCell::new(vec![Color::default(); width * height]),
+        Ok(Self::from_canvas(Canvas {
+            data: PixelBuffer::from_colors(data),
             width,
             height,
+            policy: CoordinatePolicy::default(),
+            dirty: None,
+            writes: None,
+            history: None,
+        }))
+    }
+
+    /// Create a new Pixmap with the specified dimensions whose pixel data is kept in the file at `path`
+    ///
+    /// The file is mapped into memory so that every write lands in it directly.
+    /// It therefore always holds the current canvas without being rewritten periodically, survives a crash of this
+    /// process and can be opened read-only by other processes which want to render it.
+    /// The file uses the same format as the snapshots of the [`FileSink`](crate::sinks::pixmap_file::FileSink).
+    ///
+    /// A missing or empty file is initialized as a black canvas.
+    /// Otherwise, the file must hold a pixmap of exactly the given size whose pixels are then kept.
+    ///
+    /// Only the RGB channels of colors are stored and the pixmap cannot be [resized](Self::resize).
+    pub fn map_file(path: &Path, width: usize, height: usize) -> Result<Self, MapFileError> {
+        validate_size(width, height)?;
+        Ok(Self::from_canvas(Canvas {
+            data: PixelBuffer::Mapped(MappedBuffer::open(path, width, height)?),
+            width,
+            height,
+            policy: CoordinatePolicy::default(),
+            dirty: None,
+            writes: None,
+            history: None,
+        }))
+    }
+
+    fn from_canvas(canvas: Canvas) -> Self {
+        Self {
+            size: watch::Sender::new((canvas.width, canvas.height)),
+            canvas: ArcSwap::from_pointee(canvas),
+            resize_lock: Mutex::new(()),
+            changes: None,
+            writers: None,
+            protection: ArcSwap::default(),
+            activity: Activity::default(),
+        }
+    }
+
+    /// Modify the canvas while the pixmap is being configured and is therefore not shared yet
+    fn map_canvas(self, f: impl FnOnce(Canvas) -> Canvas) -> Self {
+        let canvas = Arc::into_inner(self.canvas.into_inner())
+            .expect("the canvas of a pixmap that is being configured should not be shared");
+        Self {
+            canvas: ArcSwap::from_pointee(f(canvas)),
+            ..self
+        }
+    }
+
+    /// Configure how coordinates outside of this pixmap are treated
+    pub fn with_coordinate_policy(self, policy: CoordinatePolicy) -> Self {
+        self.map_canvas(|canvas| Canvas { policy, ..canvas })
+    }
+
+    /// Track which tiles of `tile_size` by `tile_size` pixels change so that consumers can skip unchanged regions
+    ///
+    /// Without tracking, every query reports the whole pixmap as changed.
+    ///
+    /// # Panics
+    /// Panics if `tile_size` is 0.
+    pub fn with_dirty_tracking(self, tile_size: usize) -> Self {
+        self.map_canvas(|canvas| Canvas {
+            dirty: Some(DirtyTiles::new(canvas.width, canvas.height, tile_size)),
+            ..canvas
         })
     }
 
+    /// Allow subscribing to changes of this pixmap
+    ///
+    /// Up to `capacity` batches of changes are buffered for subscribers which have not received them yet.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0.
+    pub fn with_change_events(self, capacity: usize) -> Self {
+        Self {
+            changes: Some(broadcast::Sender::new(capacity)),
+            ..self
+        }
+    }
+
+    /// Remember who last wrote each pixel and when
+    ///
+    /// Writes are attributed to the writer which is passed to methods like [`set_pixel_as()`](Self::set_pixel_as)
+    /// while the other write methods attribute them to [`WriterId::UNKNOWN`].
+    pub fn with_attribution(self) -> Self {
+        Self {
+            writers: Some(WriterRegistry::default()),
+            ..self.map_canvas(|canvas| Canvas {
+                writes: Some(WriteStamps::new(canvas.data.len())),
+                ..canvas
+            })
+        }
+    }
+
+    /// Remember the previous colors of the last `capacity` written pixels so that writes can be undone
+    ///
+    /// Undoing the writes of a specific writer requires [attribution](Self::with_attribution) to be enabled as well.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0.
+    pub fn with_history(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "history must be able to hold at least one change");
+        self.map_canvas(|canvas| Canvas {
+            history: Some(History::new(capacity)),
+            ..canvas
+        })
+    }
+
+    /// Whether this pixmap remembers who last wrote each pixel
+    pub fn has_attribution(&self) -> bool {
+        self.writers.is_some()
+    }
+
+    /// Get the id under which writes with the given identity (e.g. the address of a client) are attributed
+    ///
+    /// Registering the same identity again returns the same id.
+    /// If attribution is not enabled, [`WriterId::UNKNOWN`] is returned without registering anything.
+    /// Once the number of writers reaches its limit, new identities are rejected with an error.
+    pub fn register_writer(&self, identity: &str) -> Result<WriterId, TooManyWritersError> {
+        match &self.writers {
+            Some(writers) => writers.register(identity),
+            None => Ok(WriterId::UNKNOWN),
+        }
+    }
+
+    /// Look up the id of a writer which was [registered](Self::register_writer) with the given identity
+    pub fn find_writer(&self, identity: &str) -> Option<WriterId> {
+        self.writers.as_ref()?.find(identity)
+    }
+
+    /// Look up the identity with which a writer was [registered](Self::register_writer)
+    pub fn writer_identity(&self, writer: WriterId) -> Option<String> {
+        self.writers.as_ref()?.identity(writer)
+    }
+
+    /// Subscribe to all changes which are published from now on
+    ///
+    /// Returns `None` if change events were not enabled with [`with_change_events()`](Self::with_change_events).
+    pub fn subscribe_changes(&self) -> Option<ChangeSubscriber> {
+        self.changes
+            .as_ref()
+            .map(|sender| ChangeSubscriber::new(sender.subscribe()))
+    }
+
+    /// Whether anybody is currently subscribed to changes of this pixmap
+    ///
+    /// Writers can use this to avoid collecting changes that nobody receives.
+    #[inline(always)]
+    pub fn has_change_subscribers(&self) -> bool {
+        self.changes
+            .as_ref()
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    /// Send a batch of changes to all current subscribers
+    ///
+    /// Changes are not recorded automatically when the pixmap is written to.
+    /// Instead, writers are expected to collect them with a [`ChangeRecorder`](super::ChangeRecorder).
+    pub fn publish_changes(&self, batch: ChangeBatch) {
+        if let Some(sender) = &self.changes {
+            // sending only fails if there are no subscribers, in which case nobody is interested
+            let _ = sender.send(batch);
+        }
+    }
+
+    /// Get the policy with which this pixmap treats coordinates outside of it
+    pub fn coordinate_policy(&self) -> CoordinatePolicy {
+        self.canvas.load().policy
+    }
+
+    /// Count a client as connected until the returned guard is dropped
+    ///
+    /// Servers call this for every connection so that the number of clients can be displayed.
+    pub fn client_connected(&self) -> ClientGuard {
+        self.activity.client_connected()
+    }
+
+    /// The number of clients which are currently connected
+    pub fn connected_clients(&self) -> usize {
+        self.activity.connected_clients()
+    }
+
+    /// Add to the number of pixels which clients wrote
+    ///
+    /// Servers report this after handling requests because the pixmap does not distinguish between writes from
+    /// clients and other writes.
+    pub fn count_written_pixels(&self, pixels: u64) {
+        self.activity.count_written_pixels(pixels)
+    }
+
+    /// The total number of pixels which clients wrote since the pixmap was created
+    pub fn written_pixels(&self) -> u64 {
+        self.activity.written_pixels()
+    }
+
     /// Get the size of this pixmap as `(width, height)` tuple
     pub fn get_size(&self) -> (usize, usize) {
-        (self.width, self.height)
+        let canvas = self.canvas.load();
+        (canvas.width, canvas.height)
+    }
+
+    /// Get notified whenever the pixmap is [resized](Self::resize)
+    ///
+    /// The receiver always holds the current size as `(width, height)` tuple.
+    pub fn watch_size(&self) -> watch::Receiver<(usize, usize)> {
+        self.size.subscribe()
+    }
+
+    /// Change the size of the pixmap
+    ///
+    /// The pixels of the region which lies inside both the old and the new size are preserved while new pixels
+    /// have the default color.
+    /// Writes which happen concurrently to the resize may be lost.
+    /// Subscribers of changes are told about the new size and all tiles are reported as dirty afterwards.
+    /// The [history](Self::with_history) is cleared because its changes refer to the old size.
+    ///
+    /// Pixmaps which are [backed by a file](Self::map_file) cannot be resized.
+    pub fn resize(&self, width: usize, height: usize) -> Result<(), InvalidSizeError> {
+        validate_size(width, height)?;
+        let _guard = self.resize_lock.lock().unwrap();
+        let canvas = self.canvas.load_full();
+        if canvas.data.is_mapped() {
+            return Err(InvalidSizeError {
+                size: (width, height),
+                details: "The pixmap is backed by a file which cannot change its size",
+            });
+        }
+        let canvas = canvas.resized(width, height);
+        self.canvas.store(Arc::new(canvas));
+        self.size.send_replace((width, height));
+        self.publish_changes(Arc::new([PixelChange::Resize { width, height }]));
+        Ok(())
+    }
+
+    /// Start a batch of accesses whose writes are attributed to `writer`
+    ///
+    /// Servers use this for all requests which they received together because the batch only looks up the
+    /// current canvas and protection once instead of on every access.
+    pub fn batch(&self, writer: WriterId) -> PixmapBatch {
+        let canvas = self.canvas.load();
+        PixmapBatch {
+            author: canvas.author(writer),
+            canvas,
+            protection: self.protection.load(),
+        }
     }
 
     /// Get the color value of the pixel at position (x,y)
     pub fn get_pixel(&self, x: usize, y: usize) -> Result<Color, InvalidCoordinatesError> {
-        let i = y.saturating_mul(self.width).saturating_add(x);
-        match unsafe { self.get_color_data() }.get(i) {
-            None => Err(InvalidCoordinatesError {
-                target: (x, y),
-                pixmap_size: self.get_size(),
-            }),
-            Some(color) => Ok(*color),
-        }
+        self.canvas.load().get_pixel(x, y)
+    }
+
+    /// Make writes to the current canvas on behalf of `writer`
+    fn write_as<T>(&self, writer: WriterId, write: impl FnOnce(&Canvas, &mut Author) -> T) -> T {
+        let canvas = self.canvas.load();
+        let mut author = canvas.author(writer);
+        let result = write(&canvas, &mut author);
+        canvas.commit(&mut author);
+        result
     }
 
     /// Set the pixel value at position (x,y) to the specified color
     pub fn set_pixel(&self, x: usize, y: usize, color: Color) -> Result<(), InvalidCoordinatesError> {
-        let i = y.saturating_mul(self.width).saturating_add(x);
-        match unsafe { self.get_color_data() }.get_mut(i) {
-            None => Err(InvalidCoordinatesError {
-                target: (x, y),
-                pixmap_size: self.get_size(),
-            }),
-            Some(stored_color) => {
-                *stored_color = color;
-                Ok(())
+        self.set_pixel_as(x, y, color, WriterId::UNKNOWN)
+    }
+
+    /// Like [`set_pixel()`](Self::set_pixel) but attribute the write to `writer`
+    pub fn set_pixel_as(
+        &self,
+        x: usize,
+        y: usize,
+        color: Color,
+        writer: WriterId,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.write_as(writer, |canvas, author| canvas.set_pixel(x, y, color, author))
+    }
+
+    /// Composite the given color over the current value of the pixel at position (x,y)
+    ///
+    /// The resulting pixel is always opaque.
+    /// If the color is already opaque, this is equivalent to [`set_pixel()`](Self::set_pixel).
+    pub fn blend_pixel(&self, x: usize, y: usize, color: Color) -> Result<(), InvalidCoordinatesError> {
+        self.blend_pixel_as(x, y, color, WriterId::UNKNOWN)
+    }
+
+    /// Like [`blend_pixel()`](Self::blend_pixel) but attribute the write to `writer`
+    pub fn blend_pixel_as(
+        &self,
+        x: usize,
+        y: usize,
+        color: Color,
+        writer: WriterId,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.write_as(writer, |canvas, author| canvas.blend_pixel(x, y, color, author))
+    }
+
+    /// Set all pixels of the rectangle which starts at position (x,y) and spans `width` by `height` pixels to the
+    /// specified color.
+    ///
+    /// If the color is not opaque, it is composited over every pixel of the rectangle like
+    /// [`blend_pixel()`](Self::blend_pixel) does.
+    /// How rectangles that do not lie completely inside the pixmap are treated depends on the coordinate policy.
+    /// With [`CoordinatePolicy::Reject`], nothing is changed and an error is returned.
+    pub fn fill_rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.fill_rect_as(x, y, width, height, color, WriterId::UNKNOWN)
+    }
+
+    /// Like [`fill_rect()`](Self::fill_rect) but attribute the write to `writer`
+    pub fn fill_rect_as(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+        writer: WriterId,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.write_as(writer, |canvas, author| {
+            canvas.fill_rect(x, y, width, height, color, author)
+        })
+    }
+
+    /// Draw a rectangle of `width` by `height` pixels whose colors are given row by row onto the pixmap with its
+    /// top left corner at position (x,y).
+    ///
+    /// Colors which are not opaque are composited over the current pixels.
+    /// Rectangles that do not lie completely inside the pixmap are treated like in
+    /// [`fill_rect()`](Self::fill_rect), so only the part which lands on the pixmap is drawn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `colors` does not contain exactly `width * height` colors.
+    pub fn blit(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        colors: &[Color],
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.blit_as(x, y, width, height, colors, WriterId::UNKNOWN)
+    }
+
+    /// Like [`blit()`](Self::blit) but attribute the write to `writer`
+    pub fn blit_as(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        colors: &[Color],
+        writer: WriterId,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.write_as(writer, |canvas, author| {
+            canvas.blit(x, y, width, height, colors, author)
+        })
+    }
+
+    /// Like [`blit()`](Self::blit) but take the pixels from a buffer with three bytes of RGB data per pixel
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not contain exactly `width * height` pixels.
+    pub fn blit_rgb(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        data: &[u8],
+    ) -> Result<(), InvalidCoordinatesError> {
+        let colors = data
+            .chunks_exact(3)
+            .map(|rgb| Color::from([rgb[0], rgb[1], rgb[2]]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            colors.len() * 3,
+            data.len(),
+            "data does not consist of whole pixels"
+        );
+        self.blit(x, y, width, height, &colors)
+    }
+
+    /// Like [`blit()`](Self::blit) but take the pixels from a buffer with four bytes of RGBA data per pixel
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not contain exactly `width * height` pixels.
+    pub fn blit_rgba(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        data: &[u8],
+    ) -> Result<(), InvalidCoordinatesError> {
+        let colors = data
+            .chunks_exact(4)
+            .map(|rgba| Color::from([rgba[0], rgba[1], rgba[2], rgba[3]]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            colors.len() * 4,
+            data.len(),
+            "data does not consist of whole pixels"
+        );
+        self.blit(x, y, width, height, &colors)
+    }
+
+    /// Draw the whole content of `source` onto this pixmap with its top left corner at position (x,y)
+    ///
+    /// This behaves like [`blit()`](Self::blit) with the color data of `source`.
+    /// Use [`copy_rect()`](Self::copy_rect) and [`blit()`](Self::blit) to only copy part of a pixmap.
+    pub fn copy_from(&self, source: &Pixmap, x: usize, y: usize) -> Result<(), InvalidCoordinatesError> {
+        let source = source.canvas.load();
+        self.blit(x, y, source.width, source.height, &source.color_data())
+    }
+
+    /// Get the color data of the pixmap scaled to `width` by `height` pixels, row by row starting at the top left
+    ///
+    /// Use a [`Scaler`] directly to repeatedly scale data of the same size more efficiently.
+    pub fn scaled(&self, width: usize, height: usize, filter: ScaleFilter) -> Vec<Color> {
+        let canvas = self.canvas.load();
+        let mut result = Vec::with_capacity(width * height);
+        Scaler::new(canvas.width, canvas.height, width, height, filter)
+            .scale(&canvas.color_data(), &mut result);
+        result
+    }
+
+    /// Protect the rectangle which starts at position (x,y) and spans `width` by `height` pixels from clients
+    ///
+    /// The pixmap itself does not enforce protection, servers check [`is_protected()`](Self::is_protected) before
+    /// they carry out client requests.
+    /// Protected pixels do not [decay](Self::decay) either.
+    pub fn protect_rect(&self, x: usize, y: usize, width: usize, height: usize) {
+        self.protection.rcu(|protection| {
+            let mut protection = Protection::clone(protection);
+            protection.rects.push(Tile { x, y, width, height });
+            protection
+        });
+    }
+
+    /// Protect the pixels which are marked in `mask` from clients in addition to all protected rectangles
+    ///
+    /// This replaces any previously set mask.
+    pub fn set_protection_mask(&self, mask: Option<ProtectionMask>) {
+        self.protection.rcu(|protection| Protection {
+            mask: mask.clone(),
+            ..Protection::clone(protection)
+        });
+    }
+
+    /// Remove all protected rectangles and the protection mask
+    pub fn clear_protection(&self) {
+        self.protection.store(Arc::default());
+    }
+
+    /// Whether any pixel of the rectangle which starts at position (x,y) and spans `width` by `height` pixels is
+    /// protected from clients
+    ///
+    /// Coordinates are resolved according to the coordinate policy.
+    /// Rectangles which are rejected by it are not considered to be protected because writing them fails anyway.
+    pub fn is_protected(&self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        self.batch(WriterId::UNKNOWN).is_protected(x, y, width, height)
+    }
+
+    /// Undo all writes which were made since `since`
+    ///
+    /// Only writes which are still part of the [history](Self::with_history) can be undone.
+    /// Restored pixels are published to change subscribers but not attributed to anybody.
+    /// Returns the number of changes which were undone.
+    pub fn rewind(&self, since: SystemTime) -> usize {
+        self.undo(since, None)
+    }
+
+    /// Undo all writes which `writer` made since `since`
+    ///
+    /// Pixels which were written by somebody else afterwards are left alone.
+    /// Apart from that, this behaves like [`rewind()`](Self::rewind).
+    pub fn undo_writes(&self, writer: WriterId, since: SystemTime) -> usize {
+        self.undo(since, Some(writer))
+    }
+
+    fn undo(&self, since: SystemTime, writer: Option<WriterId>) -> usize {
+        let changes = self.canvas.load().undo(since, writer);
+        let restored = changes.len();
+        if !changes.is_empty() && self.has_change_subscribers() {
+            self.publish_changes(changes.into());
+        }
+        restored
+    }
+
+    /// Get who last wrote the pixel at position (x,y) and when
+    ///
+    /// `None` is returned if attribution is not enabled or if the pixel was not written since it was enabled.
+    pub fn last_write(&self, x: usize, y: usize) -> Result<Option<LastWrite>, InvalidCoordinatesError> {
+        self.canvas.load().last_write(x, y)
+    }
+
+    /// Color every pixel according to its last writer, row by row starting at the top left
+    ///
+    /// Pixels which were not written since attribution was enabled have the default color.
+    /// Returns `None` if attribution is not enabled.
+    pub fn attribution_heatmap(&self) -> Option<Vec<Color>> {
+        let canvas = self.canvas.load();
+        let writes = canvas.writes.as_ref()?;
+        Some(
+            writes
+                .all()
+                .map(|write| write.map_or(Color::default(), |write| write.writer.heatmap_color()))
+                .collect(),
+        )
+    }
+
+    /// Count how many pixels each writer was the last one to write
+    ///
+    /// Returns `None` if attribution is not enabled.
+    pub fn pixels_per_writer(&self) -> Option<HashMap<WriterId, usize>> {
+        let canvas = self.canvas.load();
+        let mut counts = HashMap::new();
+        for write in canvas.writes.as_ref()?.all().flatten() {
+            *counts.entry(write.writer).or_default() += 1;
+        }
+        Some(counts)
+    }
+
+    /// Copy the colors of all pixels in the rectangle which starts at position (x,y) and spans `width` by `height`
+    /// pixels.
+    ///
+    /// The colors are returned row by row, starting at the top left corner of the rectangle.
+    /// The rectangle must lie completely inside the pixmap, otherwise an error is returned.
+    /// With [`CoordinatePolicy::Wrap`], it may instead cross the edges as long as it is not larger than the pixmap.
+    pub fn copy_rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Color>, InvalidCoordinatesError> {
+        self.canvas.load().copy_rect(x, y, width, height)
+    }
+
+    /// Fade all pixels toward `background` as if `elapsed` time had passed
+    ///
+    /// The distance of every pixel to the background halves once per `half_life`.
+    /// If attribution is enabled, pixels which were written less than `elapsed` ago only fade by their age so that
+    /// fresh writes are not dimmed immediately.
+    /// Fading counts as a change for dirty tracking but is neither attributed to anybody nor published to change
+    /// subscribers.
+    /// Protected pixels do not fade because clients cannot redraw them.
+    /// Pixels which are written while they are being faded keep the written color, except in pixmaps which are
+    /// [backed by a file](Self::map_file) where each color channel is replaced on its own, so such a pixel may
+    /// mix the channels of both colors.
+    pub fn decay(&self, background: Color, half_life: Duration, elapsed: Duration) {
+        self.canvas
+            .load()
+            .decay(background, half_life, elapsed, &self.protection.load())
+    }
+
+    /// Copy the colors of all pixels, row by row starting at the top left
+    pub fn color_data(&self) -> Vec<Color> {
+        self.canvas.load().color_data()
+    }
+
+    /// Replace the content of `dst` with the colors of all pixels, row by row starting at the top left
+    ///
+    /// This allows sinks which periodically read the whole pixmap to reuse their buffer.
+    pub fn copy_color_data(&self, dst: &mut Vec<Color>) {
+        self.canvas.load().copy_color_data(dst)
+    }
+
+    /// Collect the tiles which changed since `cursor` was last used and advance it
+    ///
+    /// If dirty tracking is not enabled, a single tile which covers the whole pixmap is returned every time.
+    pub fn dirty_tiles(&self, cursor: &mut DirtyCursor) -> Vec<Tile> {
+        self.canvas.load().dirty_tiles(cursor)
+    }
+
+    /// Bring a copy of the pixel data that was made with [`color_data()`](Self::color_data) or a previous call to
+    /// this method up to date by re-copying only the tiles which changed since `cursor` was last used.
+    ///
+    /// If `dst` does not have the size of this pixmap, all pixels are copied.
+    /// Returns whether any pixel data might have changed.
+    pub fn update_color_data<T: From<Color>>(&self, cursor: &mut DirtyCursor, dst: &mut Vec<T>) -> bool {
+        self.canvas.load().update_color_data(cursor, dst)
+    }
+
+    /// Like [`update_color_data()`](Self::update_color_data) but for data in the format of
+    /// [`rgb_data()`](Self::rgb_data)
+    pub fn update_rgb_data(&self, cursor: &mut DirtyCursor, dst: &mut Vec<u8>) -> bool {
+        self.canvas.load().update_rgb_data(cursor, dst)
+    }
+
+    /// Copy the colors of all pixels as 3 bytes of red, green and blue data each, row by row starting at the top left
+    pub fn rgb_data(&self) -> Vec<u8> {
+        self.canvas.load().rgb_data()
+    }
+}
+
+impl PixmapBatch {
+    /// Get the color value of the pixel at position (x,y)
+    #[inline(always)]
+    pub fn get_pixel(&self, x: usize, y: usize) -> Result<Color, InvalidCoordinatesError> {
+        self.canvas.get_pixel(x, y)
+    }
+
+    /// Like [`Pixmap::set_pixel()`]
+    #[inline(always)]
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), InvalidCoordinatesError> {
+        self.canvas.set_pixel(x, y, color, &mut self.author)
+    }
+
+    /// Like [`Pixmap::blend_pixel()`]
+    #[inline(always)]
+    pub fn blend_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), InvalidCoordinatesError> {
+        self.canvas.blend_pixel(x, y, color, &mut self.author)
+    }
+
+    /// Like [`Pixmap::fill_rect()`]
+    pub fn fill_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+    ) -> Result<(), InvalidCoordinatesError> {
+        self.canvas
+            .fill_rect(x, y, width, height, color, &mut self.author)
+    }
+
+    /// Like [`Pixmap::copy_rect()`]
+    pub fn copy_rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Color>, InvalidCoordinatesError> {
+        self.canvas.copy_rect(x, y, width, height)
+    }
+
+    /// Like [`Pixmap::is_protected()`]
+    #[inline(always)]
+    pub fn is_protected(&self, x: usize, y: usize, width: usize, height: usize) -> bool {
+        if self.protection.is_empty() {
+            return false;
+        }
+        self.canvas
+            .rect_regions(x, y, width, height)
+            .is_ok_and(|regions| {
+                regions
+                    .into_iter()
+                    .any(|region| self.protection.overlaps(region.columns, region.rows))
+            })
+    }
+
+    /// Like [`Pixmap::last_write()`]
+    pub fn last_write(&self, x: usize, y: usize) -> Result<Option<LastWrite>, InvalidCoordinatesError> {
+        self.canvas.last_write(x, y)
+    }
+}
+
+impl Drop for PixmapBatch {
+    fn drop(&mut self) {
+        self.canvas.commit(&mut self.author);
+    }
+}
+
+impl Canvas {
+    /// Create a copy of this canvas with a different size in which the overlapping region is preserved
+    fn resized(&self, width: usize, height: usize) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        let columns = width.min(self.width);
+        for row in 0..height {
+            if row < self.height {
+                self.data
+                    .extend_into(row * self.width..row * self.width + columns, &mut data);
             }
+            data.resize((row + 1) * width, Color::default());
+        }
+        Self {
+            data: PixelBuffer::from_colors(data),
+            width,
+            height,
+            policy: self.policy,
+            dirty: self.dirty.as_ref().map(|dirty| dirty.resized(width, height)),
+            writes: self
+                .writes
+                .as_ref()
+                .map(|writes| writes.resized(self.width, width, height)),
+            history: self.history.as_ref().map(History::emptied),
         }
     }
 
-    /// Get a (usable) handle to the raw data that is contained in the pixmap
+    /// Describe writes which `writer` makes right now
     ///
-    /// # Safety
-    /// No memory safety rules are ensured for this data.
-    /// The handed out mutable reference is not checked to be the only one and the underlying data may change at any time.
-    /// It is completely unsafe and undefined behavior to work with the returned data.
+    /// The clock is only read if the time of writes is remembered at all.
+    #[inline(always)]
+    fn author(&self, writer: WriterId) -> Author {
+        let time = match self.writes.is_some() || self.history.is_some() {
+            true => SystemTime::now(),
+            false => SystemTime::UNIX_EPOCH,
+        };
+        Author {
+            writer,
+            time,
+            history: Vec::new(),
+        }
+    }
+
+    /// Add the history of all writes which `author` made to the history of this canvas
+    fn commit(&self, author: &mut Author) {
+        if let Some(history) = &self.history {
+            history.commit(&mut author.history);
+        }
+    }
+
+    fn get_pixel(&self, x: usize, y: usize) -> Result<Color, InvalidCoordinatesError> {
+        match self.pixel_index(x, y)? {
+            Some(i) => Ok(self.data.load(i)),
+            None => Err(self.invalid_coordinates(x, y)),
+        }
+    }
+
+    fn set_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        color: Color,
+        author: &mut Author,
+    ) -> Result<(), InvalidCoordinatesError> {
+        if let Some(i) = self.pixel_index(x, y)? {
+            self.record(i..i + 1, author);
+            self.data.store(i, color);
+            self.mark_pixel(i, author);
+        }
+        Ok(())
+    }
+
+    fn blend_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        color: Color,
+        author: &mut Author,
+    ) -> Result<(), InvalidCoordinatesError> {
+        if let Some(i) = self.pixel_index(x, y)? {
+            self.record(i..i + 1, author);
+            self.data.store(i, color.blend_over(self.data.load(i)));
+            self.mark_pixel(i, author);
+        }
+        Ok(())
+    }
+
+    fn last_write(&self, x: usize, y: usize) -> Result<Option<LastWrite>, InvalidCoordinatesError> {
+        match self.pixel_index(x, y)? {
+            Some(i) => Ok(self.writes.as_ref().and_then(|writes| writes.get(i))),
+            None => Err(self.invalid_coordinates(x, y)),
+        }
+    }
+
+    /// Resolve coordinates to the index of the addressed pixel according to the coordinate policy
+    ///
+    /// `None` is returned if the coordinates lie outside of the pixmap and are clipped.
+    #[inline(always)]
+    fn pixel_index(&self, x: usize, y: usize) -> Result<Option<usize>, InvalidCoordinatesError> {
+        if x < self.width && y < self.height {
+            return Ok(Some(y * self.width + x));
+        }
+        match self.policy {
+            CoordinatePolicy::Reject => Err(self.invalid_coordinates(x, y)),
+            CoordinatePolicy::Clip => Ok(None),
+            CoordinatePolicy::Wrap => Ok(Some((y % self.height) * self.width + x % self.width)),
+        }
+    }
+
+    /// Remember the current colors of the pixels with the given indices before they are overwritten
+    #[inline(always)]
+    fn record(&self, range: Range<usize>, author: &mut Author) {
+        if let Some(history) = &self.history {
+            history.record(&mut author.history, range, author.writer, author.time, &self.data);
+        }
+    }
+
+    /// Record that the pixel with the given index changed
+    #[inline(always)]
+    fn mark_pixel(&self, i: usize, author: &Author) {
+        if let Some(writes) = &self.writes {
+            writes.stamp(i..i + 1, writes.encode(author.writer, author.time));
+        }
+        if let Some(dirty) = &self.dirty {
+            let (x, y) = (i % self.width, i / self.width);
+            dirty.mark(x..x + 1, y..y + 1);
+        }
+    }
+
+    fn invalid_coordinates(&self, x: usize, y: usize) -> InvalidCoordinatesError {
+        InvalidCoordinatesError {
+            target: (x, y),
+            pixmap_size: (self.width, self.height),
+        }
+    }
+
+    fn fill_rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: Color,
+        author: &mut Author,
+    ) -> Result<(), InvalidCoordinatesError> {
+        for region in self.rect_regions(x, y, width, height)? {
+            self.write_rows(region.columns, region.rows, author, |row_data, _| {
+                if color.is_opaque() {
+                    self.data.fill(row_data, color);
+                } else {
+                    for i in row_data {
+                        self.data.store(i, color.blend_over(self.data.load(i)));
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn blit(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        colors: &[Color],
+        author: &mut Author,
+    ) -> Result<(), InvalidCoordinatesError> {
+        assert_eq!(colors.len(), width * height, "colors do not fit the rectangle");
+        for region in self.rect_regions(x, y, width, height)? {
+            let (offset_x, offset_y) = region.offset;
+            self.write_rows(region.columns, region.rows, author, |row_data, n| {
+                let src_start = (offset_y + n) * width + offset_x;
+                for (i, &color) in row_data.zip(&colors[src_start..]) {
+                    if color.is_opaque() {
+                        self.data.store(i, color);
+                    } else {
+                        self.data.store(i, color.blend_over(self.data.load(i)));
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Resolve a rectangle to the regions of the pixmap which it covers according to the coordinate policy
     ///
-    /// # Reasoning
-    /// While it is undefined behavior to use the returned handle, it works as expected and it is _very_ fast to store
-    /// and work with pixel data in this way.
-    /// Since pixelflut is designed to be primarily fast and does not intend to offer a consistent view of the pixel
-    /// data this has show to be fine.
-    #[allow(clippy::mut_from_ref)]
-    pub(crate) unsafe fn get_color_data(&self) -> &mut [Color] {
-        &mut *self.data.get()
+    /// With [`CoordinatePolicy::Reject`], an error is returned unless the rectangle lies completely inside the
+    /// pixmap.
+    fn rect_regions(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Region>, InvalidCoordinatesError> {
+        match self.policy {
+            CoordinatePolicy::Reject => {
+                let (x_end, y_end) = self.rect_end(x, y, width, height)?;
+                Ok(vec![Region {
+                    columns: x..x_end,
+                    rows: y..y_end,
+                    offset: (0, 0),
+                }])
+            }
+            CoordinatePolicy::Clip => {
+                let x_end = x.saturating_add(width).min(self.width);
+                let y_end = y.saturating_add(height).min(self.height);
+                if x < x_end && y < y_end {
+                    Ok(vec![Region {
+                        columns: x..x_end,
+                        rows: y..y_end,
+                        offset: (0, 0),
+                    }])
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            CoordinatePolicy::Wrap => {
+                let mut regions = Vec::with_capacity(4);
+                let mut offset_y = 0;
+                for rows in wrapped_ranges(y, height, self.height) {
+                    let mut offset_x = 0;
+                    for columns in wrapped_ranges(x, width, self.width) {
+                        offset_x += columns.len();
+                        regions.push(Region {
+                            offset: (offset_x - columns.len(), offset_y),
+                            columns,
+                            rows: rows.clone(),
+                        });
+                    }
+                    offset_y += rows.len();
+                }
+                Ok(regions)
+            }
+        }
+    }
+
+    /// Write to a rectangle which is already known to lie completely inside the pixmap
+    ///
+    /// `write` is called with the pixel indices of every row and the number of that row inside the rectangle.
+    /// Recording history, attribution and dirty tracking are taken care of.
+    #[inline(always)]
+    fn write_rows(
+        &self,
+        columns: Range<usize>,
+        rows: Range<usize>,
+        author: &mut Author,
+        mut write: impl FnMut(Range<usize>, usize),
+    ) {
+        let stamp = self
+            .writes
+            .as_ref()
+            .map(|writes| (writes, writes.encode(author.writer, author.time)));
+        for (n, row) in rows.clone().enumerate() {
+            let row_data = row * self.width + columns.start..row * self.width + columns.end;
+            self.record(row_data.clone(), author);
+            write(row_data.clone(), n);
+            if let Some((writes, stamp)) = stamp {
+                writes.stamp(row_data, stamp);
+            }
+        }
+        if let Some(dirty) = &self.dirty {
+            dirty.mark(columns, rows);
+        }
+    }
+
+    fn copy_rect(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Color>, InvalidCoordinatesError> {
+        match self.policy {
+            CoordinatePolicy::Reject | CoordinatePolicy::Clip => {
+                let (x_end, y_end) = self.rect_end(x, y, width, height)?;
+                let mut result = Vec::with_capacity(width * height);
+                for row in y..y_end {
+                    self.data
+                        .extend_into(row * self.width + x..row * self.width + x_end, &mut result);
+                }
+                Ok(result)
+            }
+            CoordinatePolicy::Wrap => {
+                if width > self.width || height > self.height {
+                    return Err(self.invalid_coordinates(x, y));
+                }
+                let mut result = Vec::with_capacity(width * height);
+                for rows in wrapped_ranges(y, height, self.height) {
+                    for row in rows {
+                        for columns in wrapped_ranges(x, width, self.width) {
+                            self.data.extend_into(
+                                row * self.width + columns.start..row * self.width + columns.end,
+                                &mut result,
+                            );
+                        }
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    /// Calculate the exclusive end coordinates of a rectangle and validate that it lies inside the pixmap
+    #[inline(always)]
+    fn rect_end(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(usize, usize), InvalidCoordinatesError> {
+        let x_end = x.checked_add(width).filter(|&x_end| x_end <= self.width);
+        let y_end = y.checked_add(height).filter(|&y_end| y_end <= self.height);
+        match (x_end, y_end) {
+            (Some(x_end), Some(y_end)) => Ok((x_end, y_end)),
+            _ => Err(self.invalid_coordinates(x, y)),
+        }
+    }
+
+    /// Restore the pixels which were changed since `since` and return the changes which this makes
+    fn undo(&self, since: SystemTime, writer: Option<WriterId>) -> Vec<PixelChange> {
+        let Some(history) = &self.history else {
+            return Vec::new();
+        };
+        history
+            .take(since, writer)
+            .into_iter()
+            .map(|(i, color)| {
+                self.data.store(i, color);
+                let (x, y) = (i % self.width, i / self.width);
+                if let Some(dirty) = &self.dirty {
+                    dirty.mark(x..x + 1, y..y + 1);
+                }
+                PixelChange::Pixel { x, y, color }
+            })
+            .collect()
+    }
+
+    fn decay(&self, background: Color, half_life: Duration, elapsed: Duration, protection: &Protection) {
+        let now = SystemTime::now();
+        let remaining = |time: Duration| 0.5f32.powf(time.as_secs_f32() / half_life.as_secs_f32());
+        let remaining_elapsed = remaining(elapsed);
+        let seed = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        let mut protected = vec![false; self.width];
+        for row in 0..self.height {
+            if !protection.is_empty() {
+                protection.mark_row(row, &mut protected);
+            }
+            let mut changed: Option<Range<usize>> = None;
+            for (column, &protected) in protected.iter().enumerate() {
+                let i = row * self.width + column;
+                let color = self.data.load(i);
+                if <[u8; 3]>::from(color) == <[u8; 3]>::from(background) || protected {
+                    continue;
+                }
+                let age = self
+                    .writes
+                    .as_ref()
+                    .and_then(|writes| writes.get(i))
+                    .map(|write| now.duration_since(write.time).unwrap_or_default());
+                let remaining = match age {
+                    Some(age) if age < elapsed => remaining(age),
+                    _ => remaining_elapsed,
+                };
+                let faded = color.fade_toward(background, remaining, dither(i as u64 ^ seed));
+                // pixels which a client wrote in the meantime keep their new color
+                if faded != color && self.data.compare_exchange(i, color, faded) {
+                    changed = Some(changed.map_or(column..column + 1, |range| range.start..column + 1));
+                }
+            }
+            if let (Some(dirty), Some(columns)) = (&self.dirty, changed) {
+                dirty.mark(columns, row..row + 1);
+            }
+        }
+    }
+
+    fn color_data(&self) -> Vec<Color> {
+        let mut result = Vec::with_capacity(self.data.len());
+        self.copy_color_data(&mut result);
+        result
+    }
+
+    fn copy_color_data(&self, dst: &mut Vec<Color>) {
+        dst.clear();
+        self.data.extend_into(0..self.data.len(), dst);
+    }
+
+    fn dirty_tiles(&self, cursor: &mut DirtyCursor) -> Vec<Tile> {
+        match &self.dirty {
+            Some(dirty) => dirty.take(cursor),
+            None => vec![Tile {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            }],
+        }
+    }
+
+    fn update_color_data<T: From<Color>>(&self, cursor: &mut DirtyCursor, dst: &mut Vec<T>) -> bool {
+        let resized = dst.len() != self.data.len();
+        if resized {
+            dst.clear();
+            dst.extend((0..self.data.len()).map(|_| T::from(Color::default())));
+        }
+        self.update_pixels(cursor, resized, |i, color| dst[i] = T::from(color))
+    }
+
+    fn update_rgb_data(&self, cursor: &mut DirtyCursor, dst: &mut Vec<u8>) -> bool {
+        let resized = dst.len() != self.data.len() * 3;
+        if resized {
+            dst.clear();
+            dst.resize(self.data.len() * 3, 0);
+        }
+        self.update_pixels(cursor, resized, |i, color| {
+            dst[i * 3..i * 3 + 3].copy_from_slice(&<[u8; 3]>::from(color))
+        })
+    }
+
+    /// Pass the index and color of every pixel in a dirty tile to `write` or of all pixels if `everything` is set
+    fn update_pixels(
+        &self,
+        cursor: &mut DirtyCursor,
+        everything: bool,
+        mut write: impl FnMut(usize, Color),
+    ) -> bool {
+        let tiles = self.dirty_tiles(cursor);
+        if everything {
+            for i in 0..self.data.len() {
+                write(i, self.data.load(i));
+            }
+            return true;
+        }
+        for tile in &tiles {
+            for row in tile.y..tile.y + tile.height {
+                let start = row * self.width + tile.x;
+                for i in start..start + tile.width {
+                    write(i, self.data.load(i));
+                }
+            }
+        }
+        !tiles.is_empty()
     }
+
+    fn rgb_data(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.data.len() * 3);
+        for i in 0..self.data.len() {
+            result.extend_from_slice(&<[u8; 3]>::from(self.data.load(i)));
+        }
+        result
+    }
+}
+
+/// Validate that a pixmap can be constructed with the given size
+fn validate_size(width: usize, height: usize) -> Result<(), InvalidSizeError> {
+    if width == 0 || height == 0 {
+        return Err(InvalidSizeError {
+            size: (width, height),
+            details: "Width and Height must both be greater than 0",
+        });
+    }
+    if width.checked_mul(height).is_none() {
+        return Err(InvalidSizeError {
+            size: (width, height),
+            details: "The number of pixels does not fit into memory",
+        });
+    }
+    Ok(())
+}
+
+/// Derive a pseudo-random number in `[0, 1)` from `value`
+///
+/// This is used to dither rounding so that slowly fading pixels do not get stuck one step away from their target.
+fn dither(value: u64) -> f32 {
+    // splitmix64 finalizer
+    let mut z = value.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Split a span of `len` coordinates that starts at `start` and wraps around at `size` into at most two ranges
+///
+/// Spans that are longer than `size` are shortened to `size` because they would otherwise cover coordinates twice.
+fn wrapped_ranges(start: usize, len: usize, size: usize) -> impl Iterator<Item = Range<usize>> {
+    let start = start % size;
+    let len = len.min(size);
+    let first = start..usize::min(start + len, size);
+    let second = 0..(start + len).saturating_sub(size);
+    [first, second].into_iter().filter(|range| !range.is_empty())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use ::test::Bencher;
     use quickcheck::{quickcheck, TestResult};
+    use std::hint::black_box;
 
     quickcheck! {
         fn test_set_and_get_pixel(x: usize, y: usize) -> TestResult {
@@ -110,5 +1362,750 @@ mod test {
                 }
             }
         }
+
+        fn test_blend_pixel(x: usize, y: usize, alpha: u8) -> TestResult {
+            let background = Color::from((0x00, 0x40, 0xFF));
+            let color = Color::from_rgba(0xFF, 0x40, 0x00, alpha);
+            let pixmap = Pixmap::new(80, 60).unwrap();
+            if pixmap.set_pixel(x, y, background).is_err() {
+                return TestResult::discard();
+            }
+            pixmap.blend_pixel(x, y, color).unwrap();
+            let got_color = pixmap.get_pixel(x, y).unwrap();
+            TestResult::from_bool(got_color == color.blend_over(background) && got_color.is_opaque())
+        }
+
+        fn test_fill_rect(x: u8, y: u8, width: u8, height: u8) -> TestResult {
+            let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+            let color = Color::from((0xAB, 0xAB, 0xAB));
+            let pixmap = Pixmap::new(80, 60).unwrap();
+            match pixmap.fill_rect(x, y, width, height, color) {
+                Err(_) => TestResult::from_bool(x + width > 80 || y + height > 60),
+                Ok(_) => {
+                    let (pixmap_width, pixmap_height) = pixmap.get_size();
+                    for (ix, iy) in (0..pixmap_width).flat_map(|ix| (0..pixmap_height).map(move |iy| (ix, iy))) {
+                        let inside = (x..x + width).contains(&ix) && (y..y + height).contains(&iy);
+                        if (pixmap.get_pixel(ix, iy).unwrap() == color) != inside {
+                            return TestResult::failed();
+                        }
+                    }
+                    TestResult::passed()
+                }
+            }
+        }
+
+        fn test_wrap_pixel(x: usize, y: usize) -> bool {
+            let color = Color::from((0xAB, 0xAB, 0xAB));
+            let pixmap = Pixmap::new(80, 60).unwrap().with_coordinate_policy(CoordinatePolicy::Wrap);
+            pixmap.set_pixel(x, y, color).unwrap();
+            pixmap.get_pixel(x % 80, y % 60).unwrap() == color && pixmap.get_pixel(x, y).unwrap() == color
+        }
+
+        fn test_copy_rect(x: u8, y: u8, width: u8, height: u8) -> TestResult {
+            let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+            let pixmap = Pixmap::new(80, 60).unwrap();
+            for (ix, iy) in (0..80).flat_map(|ix| (0..60).map(move |iy| (ix, iy))) {
+                pixmap.set_pixel(ix, iy, Color::from((ix as u8, iy as u8, 0))).unwrap();
+            }
+
+            match pixmap.copy_rect(x, y, width, height) {
+                Err(_) => TestResult::from_bool(x + width > 80 || y + height > 60),
+                Ok(colors) => {
+                    let expected = (y..y + height)
+                        .flat_map(|iy| (x..x + width).map(move |ix| Color::from((ix as u8, iy as u8, 0))))
+                        .collect::<Vec<_>>();
+                    TestResult::from_bool(colors == expected)
+                }
+            }
+        }
+
+        fn test_blit(x: u8, y: u8, width: u8, height: u8) -> TestResult {
+            let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+            let colors = (0..width * height).map(|i| Color::from((i as u8, 0xFF, 0))).collect::<Vec<_>>();
+            let pixmap = Pixmap::new(80, 60).unwrap();
+            match pixmap.blit(x, y, width, height, &colors) {
+                Err(_) => TestResult::from_bool(x + width > 80 || y + height > 60),
+                Ok(_) => TestResult::from_bool(pixmap.copy_rect(x, y, width, height).unwrap() == colors),
+            }
+        }
+    }
+
+    /// Fill an 80x60 pixmap so that every pixel encodes its own coordinates
+    fn coordinate_pixmap(policy: CoordinatePolicy) -> Pixmap {
+        let pixmap = Pixmap::new(80, 60).unwrap().with_coordinate_policy(policy);
+        for (ix, iy) in (0..80).flat_map(|ix| (0..60).map(move |iy| (ix, iy))) {
+            pixmap
+                .set_pixel(ix, iy, Color::from((ix as u8, iy as u8, 0)))
+                .unwrap();
+        }
+        pixmap
+    }
+
+    #[test]
+    fn test_reject_policy() {
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        let pixmap = Pixmap::new(80, 60).unwrap();
+
+        // x coordinates beyond the width must not spill over into the next row
+        assert!(pixmap.set_pixel(80, 0, color).is_err());
+        assert!(pixmap.blend_pixel(81, 0, color).is_err());
+        assert!(pixmap.get_pixel(80, 0).is_err());
+        assert_eq!(pixmap.get_pixel(0, 1).unwrap(), Color::default());
+        assert_eq!(pixmap.get_pixel(1, 1).unwrap(), Color::default());
+
+        assert!(pixmap.set_pixel(0, 60, color).is_err());
+        assert!(pixmap.set_pixel(usize::MAX, usize::MAX, color).is_err());
+        assert!(pixmap.fill_rect(70, 0, 20, 1, color).is_err());
+        assert!(pixmap.copy_rect(70, 0, 20, 1).is_err());
+    }
+
+    #[test]
+    fn test_clip_policy() {
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_coordinate_policy(CoordinatePolicy::Clip);
+
+        // writes outside of the pixmap are discarded
+        assert!(pixmap.set_pixel(80, 0, color).is_ok());
+        assert!(pixmap.blend_pixel(0, 60, color).is_ok());
+        assert!(pixmap.color_data().iter().all(|c| *c == Color::default()));
+
+        // reads outside of the pixmap are still errors
+        assert!(pixmap.get_pixel(80, 0).is_err());
+        assert!(pixmap.copy_rect(70, 50, 20, 20).is_err());
+
+        // rectangles are cut off at the edges
+        pixmap.fill_rect(70, 50, 20, 20, color).unwrap();
+        for (ix, iy) in (0..80).flat_map(|ix| (0..60).map(move |iy| (ix, iy))) {
+            let inside = ix >= 70 && iy >= 50;
+            assert_eq!(pixmap.get_pixel(ix, iy).unwrap() == color, inside, "{ix}x{iy}");
+        }
+        assert!(pixmap.fill_rect(100, 100, usize::MAX, usize::MAX, color).is_ok());
+    }
+
+    #[test]
+    fn test_wrap_policy() {
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Wrap);
+        assert_eq!(pixmap.get_pixel(81, 61).unwrap(), Color::from((1, 1, 0)));
+        assert_eq!(
+            pixmap.get_pixel(usize::MAX, 0).unwrap(),
+            pixmap.get_pixel(usize::MAX % 80, 0).unwrap()
+        );
+
+        // rectangles may cross the edges when reading
+        assert_eq!(
+            pixmap.copy_rect(79, 59, 2, 2).unwrap(),
+            vec![
+                Color::from((79, 59, 0)),
+                Color::from((0, 59, 0)),
+                Color::from((79, 0, 0)),
+                Color::from((0, 0, 0)),
+            ]
+        );
+        assert!(pixmap.copy_rect(0, 0, 81, 1).is_err());
+
+        // and when writing
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        pixmap.fill_rect(78, 58, 4, 4, color).unwrap();
+        for (ix, iy) in (0..80).flat_map(|ix| (0..60).map(move |iy| (ix, iy))) {
+            let inside = !(2..78).contains(&ix) && !(2..58).contains(&iy);
+            assert_eq!(pixmap.get_pixel(ix, iy).unwrap() == color, inside, "{ix}x{iy}");
+        }
+
+        // rectangles larger than the pixmap cover it exactly once
+        let color = Color::from_rgba(0xFF, 0x00, 0x00, 0x80);
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Wrap);
+        pixmap.fill_rect(40, 30, usize::MAX, 1000, color).unwrap();
+        assert_eq!(
+            pixmap.get_pixel(5, 7).unwrap(),
+            color.blend_over(Color::from((5, 7, 0)))
+        );
+    }
+
+    #[test]
+    fn test_blit_policies() {
+        let colors = (0..6).map(|i| Color::from((i, 0, 0))).collect::<Vec<_>>();
+
+        // clipped rectangles only draw the part which lands on the pixmap
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_coordinate_policy(CoordinatePolicy::Clip);
+        pixmap.blit(78, 59, 3, 2, &colors).unwrap();
+        assert_eq!(pixmap.copy_rect(78, 59, 2, 1).unwrap(), colors[..2]);
+
+        // wrapped rectangles continue at the opposite edges
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_coordinate_policy(CoordinatePolicy::Wrap);
+        pixmap.blit(79, 59, 3, 2, &colors).unwrap();
+        assert_eq!(pixmap.get_pixel(79, 59).unwrap(), colors[0]);
+        assert_eq!(pixmap.get_pixel(1, 59).unwrap(), colors[2]);
+        assert_eq!(pixmap.get_pixel(79, 0).unwrap(), colors[3]);
+        assert_eq!(pixmap.get_pixel(1, 0).unwrap(), colors[5]);
+
+        // transparent pixels are composited over the current content
+        let pixmap = Pixmap::new(2, 1).unwrap();
+        pixmap
+            .fill_rect(0, 0, 2, 1, Color::from((0xFF, 0xFF, 0xFF)))
+            .unwrap();
+        pixmap
+            .blit_rgba(0, 0, 2, 1, &[0, 0, 0, 0xFF, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(pixmap.rgb_data(), vec![0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        pixmap.blit_rgb(1, 0, 1, 1, &[1, 2, 3]).unwrap();
+        assert_eq!(pixmap.get_pixel(1, 0).unwrap(), Color::from((1, 2, 3)));
+    }
+
+    #[test]
+    fn test_copy_and_scale() {
+        let source = coordinate_pixmap(CoordinatePolicy::Reject);
+        let pixmap = Pixmap::new(100, 100).unwrap();
+        pixmap.copy_from(&source, 10, 20).unwrap();
+        assert_eq!(pixmap.copy_rect(10, 20, 80, 60).unwrap(), source.color_data());
+        assert!(pixmap.copy_from(&source, 30, 0).is_err());
+
+        let scaled = source.scaled(40, 30, ScaleFilter::Nearest);
+        assert_eq!(scaled.len(), 40 * 30);
+        assert_eq!(scaled[7 * 40 + 31], source.get_pixel(62, 14).unwrap());
+        assert_eq!(source.scaled(80, 60, ScaleFilter::Bilinear), source.color_data());
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let pixmap = Pixmap::new(80, 60).unwrap();
+
+        // every thread draws its own set of rows while another one continuously takes snapshots
+        std::thread::scope(|s| {
+            for thread in 0..4u8 {
+                let pixmap = &pixmap;
+                s.spawn(move || {
+                    for iy in (usize::from(thread)..60).step_by(4) {
+                        pixmap
+                            .fill_rect(0, iy, 80, 1, Color::from((thread, iy as u8, 0)))
+                            .unwrap();
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..100 {
+                    assert_eq!(pixmap.rgb_data().len(), 80 * 60 * 3);
+                }
+            });
+        });
+
+        for (ix, iy) in (0..80).flat_map(|ix| (0..60).map(move |iy| (ix, iy))) {
+            let expected = Color::from(((iy % 4) as u8, iy as u8, 0));
+            assert_eq!(pixmap.get_pixel(ix, iy).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_color_data_roundtrip() {
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Reject);
+        let restored = Pixmap::from_color_data(80, 60, pixmap.color_data()).unwrap();
+        assert_eq!(pixmap.color_data(), restored.color_data());
+        assert_eq!(pixmap.rgb_data()[..6], [0, 0, 0, 1, 0, 0]);
+        assert!(Pixmap::from_color_data(80, 60, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_dirty_tiles() {
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        let pixmap = Pixmap::new(80, 60).unwrap().with_dirty_tracking(32);
+        let mut cursor = DirtyCursor::default();
+        let tile = |x, y, width, height| Tile { x, y, width, height };
+
+        // a new cursor sees everything, then nothing until something changes
+        assert_eq!(pixmap.dirty_tiles(&mut cursor).len(), 6);
+        assert!(pixmap.dirty_tiles(&mut cursor).is_empty());
+
+        pixmap.set_pixel(70, 50, color).unwrap();
+        pixmap.blend_pixel(0, 0, color.with_alpha(0x80)).unwrap();
+        assert_eq!(
+            pixmap.dirty_tiles(&mut cursor),
+            vec![tile(0, 0, 32, 32), tile(64, 32, 16, 28)]
+        );
+
+        // consumers are independent of each other
+        let mut other_cursor = cursor;
+        pixmap.fill_rect(30, 30, 4, 4, color).unwrap();
+        let expected = vec![
+            tile(0, 0, 32, 32),
+            tile(32, 0, 32, 32),
+            tile(0, 32, 32, 28),
+            tile(32, 32, 32, 28),
+        ];
+        assert_eq!(pixmap.dirty_tiles(&mut cursor), expected);
+        assert_eq!(pixmap.dirty_tiles(&mut other_cursor), expected);
+        assert!(pixmap.dirty_tiles(&mut cursor).is_empty());
+
+        // failed writes change nothing
+        assert!(pixmap.set_pixel(80, 0, color).is_err());
+        assert!(pixmap.dirty_tiles(&mut cursor).is_empty());
+    }
+
+    #[test]
+    fn test_update_data() {
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Wrap).with_dirty_tracking(16);
+        let mut cursor = DirtyCursor::default();
+        let mut colors = Vec::<Color>::new();
+        let mut rgb = Vec::new();
+        let mut rgb_cursor = DirtyCursor::default();
+
+        assert!(pixmap.update_color_data(&mut cursor, &mut colors));
+        assert!(pixmap.update_rgb_data(&mut rgb_cursor, &mut rgb));
+        assert!(!pixmap.update_color_data(&mut cursor, &mut colors));
+        assert!(!pixmap.update_rgb_data(&mut rgb_cursor, &mut rgb));
+
+        pixmap.fill_rect(75, 55, 10, 10, color).unwrap();
+        assert!(pixmap.update_color_data(&mut cursor, &mut colors));
+        assert!(pixmap.update_rgb_data(&mut rgb_cursor, &mut rgb));
+        assert_eq!(colors, pixmap.color_data());
+        assert_eq!(rgb, pixmap.rgb_data());
+
+        // without tracking, everything is always copied
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Reject);
+        assert!(pixmap.update_color_data(&mut cursor, &mut colors));
+        assert!(pixmap.update_color_data(&mut cursor, &mut colors));
+        assert_eq!(colors, pixmap.color_data());
+    }
+
+    #[test]
+    fn test_resize() {
+        let pixmap = coordinate_pixmap(CoordinatePolicy::Wrap)
+            .with_dirty_tracking(16)
+            .with_change_events(4);
+        let mut cursor = DirtyCursor::default();
+        let mut size = pixmap.watch_size();
+        let mut subscriber = pixmap.subscribe_changes().unwrap();
+        pixmap.dirty_tiles(&mut cursor);
+
+        // the overlapping region is preserved while new pixels are empty
+        pixmap.resize(100, 40).unwrap();
+        assert_eq!(pixmap.get_size(), (100, 40));
+        assert_eq!(pixmap.coordinate_policy(), CoordinatePolicy::Wrap);
+        assert_eq!(pixmap.get_pixel(79, 39).unwrap(), Color::from((79, 39, 0)));
+        assert_eq!(pixmap.get_pixel(80, 39).unwrap(), Color::default());
+        assert_eq!(pixmap.get_pixel(100, 0).unwrap(), Color::from((0, 0, 0)));
+        assert_eq!(pixmap.color_data().len(), 100 * 40);
+
+        // everybody is told about the new size
+        assert!(size.has_changed().unwrap());
+        assert_eq!(*size.borrow_and_update(), (100, 40));
+        assert_eq!(pixmap.dirty_tiles(&mut cursor).len(), 7 * 3);
+        assert!(pixmap.dirty_tiles(&mut cursor).is_empty());
+        assert_eq!(
+            subscriber.try_recv().unwrap().unwrap()[..],
+            [PixelChange::Resize {
+                width: 100,
+                height: 40
+            }]
+        );
+
+        assert!(pixmap.resize(0, 40).is_err());
+        assert!(pixmap.resize(usize::MAX, 2).is_err());
+        assert_eq!(pixmap.get_size(), (100, 40));
+    }
+
+    #[test]
+    fn test_batch() {
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        let pixmap = Pixmap::new(80, 60).unwrap();
+        let mut batch = pixmap.batch(WriterId::UNKNOWN);
+        batch.set_pixel(1, 2, color).unwrap();
+        batch.fill_rect(10, 10, 2, 2, color).unwrap();
+        assert_eq!(pixmap.get_pixel(1, 2).unwrap(), color);
+        assert_eq!(batch.copy_rect(10, 10, 2, 2).unwrap(), vec![color; 4]);
+
+        // a batch keeps the size and protection which the pixmap had when it was started
+        pixmap.protect_rect(0, 0, 5, 5);
+        pixmap.resize(100, 100).unwrap();
+        assert!(batch.get_pixel(90, 90).is_err());
+        assert!(!batch.is_protected(1, 2, 1, 1));
+        let batch = pixmap.batch(WriterId::UNKNOWN);
+        assert_eq!(batch.get_pixel(90, 90).unwrap(), Color::default());
+        assert!(batch.is_protected(1, 2, 1, 1));
+    }
+
+    #[test]
+    fn test_attribution() {
+        let color = Color::from((0xAB, 0xAB, 0xAB));
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_coordinate_policy(CoordinatePolicy::Wrap)
+            .with_attribution();
+        let alice = pixmap.register_writer("alice").unwrap();
+        let bob = pixmap.register_writer("bob").unwrap();
+        assert_eq!(pixmap.register_writer("alice"), Ok(alice));
+        assert_ne!(alice, bob);
+        assert_eq!(pixmap.writer_identity(bob).as_deref(), Some("bob"));
+        assert_eq!(pixmap.writer_identity(WriterId::UNKNOWN), None);
+        assert_eq!(pixmap.find_writer("bob"), Some(bob));
+        assert_eq!(pixmap.find_writer("carol"), None);
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        assert_eq!(pixmap.last_write(0, 0).unwrap(), None);
+        pixmap.set_pixel_as(0, 0, color, alice).unwrap();
+        pixmap.fill_rect_as(79, 59, 2, 2, color, bob).unwrap();
+        pixmap.blend_pixel(5, 5, color).unwrap();
+        let write = pixmap.last_write(0, 0).unwrap().unwrap();
+        assert_eq!(write.writer, bob);
+        assert!(write.time >= before);
+        assert_eq!(pixmap.last_write(0, 1).unwrap(), None);
+        assert_eq!(
+            pixmap.last_write(5, 5).unwrap().unwrap().writer,
+            WriterId::UNKNOWN
+        );
+
+        let counts = pixmap.pixels_per_writer().unwrap();
+        assert_eq!(counts.get(&alice), None);
+        assert_eq!(counts[&bob], 4);
+        assert_eq!(counts[&WriterId::UNKNOWN], 1);
+        let heatmap = pixmap.attribution_heatmap().unwrap();
+        assert_eq!(heatmap[0], bob.heatmap_color());
+        assert_eq!(heatmap[1], Color::default());
+        assert_ne!(alice.heatmap_color(), bob.heatmap_color());
+
+        // attribution survives resizing
+        pixmap.resize(40, 40).unwrap();
+        assert_eq!(pixmap.last_write(0, 0).unwrap().unwrap().writer, bob);
+        assert_eq!(pixmap.register_writer("bob"), Ok(bob));
+
+        // the number of writers is bounded but known writers keep their id
+        for i in 0..u16::MAX - 2 {
+            pixmap
+                .register_writer(&format!("10.0.{}.{}", i / 256, i % 256))
+                .unwrap();
+        }
+        assert_eq!(pixmap.register_writer("carol"), Err(TooManyWritersError));
+        assert_eq!(pixmap.register_writer("bob"), Ok(bob));
+
+        // without attribution, nothing is tracked
+        let pixmap = Pixmap::new(80, 60).unwrap();
+        assert_eq!(pixmap.register_writer("alice"), Ok(WriterId::UNKNOWN));
+        pixmap.set_pixel(0, 0, color).unwrap();
+        assert_eq!(pixmap.last_write(0, 0).unwrap(), None);
+        assert_eq!(pixmap.pixels_per_writer(), None);
+    }
+
+    #[test]
+    fn test_history() {
+        let (red, green, blue) = (
+            Color::from((0xFF, 0, 0)),
+            Color::from((0, 0xFF, 0)),
+            Color::from((0, 0, 0xFF)),
+        );
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_attribution()
+            .with_history(100)
+            .with_change_events(4);
+        let artist = pixmap.register_writer("artist").unwrap();
+        let vandal = pixmap.register_writer("vandal").unwrap();
+        let mut subscriber = pixmap.subscribe_changes().unwrap();
+
+        pixmap.fill_rect_as(0, 0, 4, 1, red, artist).unwrap();
+        let attack = SystemTime::now();
+        pixmap.fill_rect_as(0, 0, 3, 1, green, vandal).unwrap();
+        pixmap.set_pixel_as(0, 0, blue, vandal).unwrap();
+        pixmap.set_pixel_as(1, 0, blue, artist).unwrap();
+
+        // pixels which were painted over by somebody else afterwards are kept
+        assert_eq!(pixmap.undo_writes(vandal, attack), 3);
+        assert_eq!(pixmap.copy_rect(0, 0, 4, 1).unwrap(), vec![red, blue, red, red]);
+        assert_eq!(
+            subscriber.try_recv().unwrap().unwrap()[..],
+            [
+                PixelChange::Pixel {
+                    x: 0,
+                    y: 0,
+                    color: green
+                },
+                PixelChange::Pixel {
+                    x: 2,
+                    y: 0,
+                    color: red
+                },
+                PixelChange::Pixel {
+                    x: 0,
+                    y: 0,
+                    color: red
+                },
+            ]
+        );
+
+        // rewinding undoes everything that is left
+        assert_eq!(pixmap.rewind(attack), 2);
+        assert_eq!(pixmap.copy_rect(0, 0, 4, 1).unwrap(), vec![red; 4]);
+        assert_eq!(pixmap.rewind(SystemTime::UNIX_EPOCH), 4);
+        assert!(pixmap.color_data().iter().all(|c| *c == Color::default()));
+        assert_eq!(pixmap.rewind(SystemTime::UNIX_EPOCH), 0);
+
+        // only the most recent changes are kept
+        let pixmap = Pixmap::new(80, 60).unwrap().with_history(10);
+        pixmap.fill_rect(0, 0, 80, 1, red).unwrap();
+        assert_eq!(pixmap.rewind(SystemTime::UNIX_EPOCH), 10);
+        assert_eq!(pixmap.get_pixel(69, 0).unwrap(), red);
+        assert_eq!(pixmap.get_pixel(70, 0).unwrap(), Color::default());
+
+        // the changes of a batch become part of the history when it ends
+        pixmap.fill_rect(0, 1, 8, 1, green).unwrap();
+        let mut batch = pixmap.batch(WriterId::UNKNOWN);
+        batch.fill_rect(0, 2, 5, 1, blue).unwrap();
+        assert_eq!(pixmap.rewind(SystemTime::UNIX_EPOCH), 8);
+        pixmap.fill_rect(0, 1, 8, 1, green).unwrap();
+        drop(batch);
+        assert_eq!(pixmap.rewind(SystemTime::UNIX_EPOCH), 10);
+        assert_eq!(pixmap.copy_rect(0, 1, 3, 1).unwrap(), vec![green; 3]);
+        assert_eq!(pixmap.copy_rect(3, 1, 5, 1).unwrap(), vec![Color::default(); 5]);
+        assert_eq!(pixmap.copy_rect(0, 2, 5, 1).unwrap(), vec![Color::default(); 5]);
+    }
+
+    #[test]
+    fn test_protection() {
+        let pixmap = Pixmap::new(80, 60)
+            .unwrap()
+            .with_coordinate_policy(CoordinatePolicy::Wrap);
+        assert!(!pixmap.is_protected(0, 0, 80, 60));
+
+        pixmap.protect_rect(10, 10, 5, 5);
+        assert!(pixmap.is_protected(14, 14, 1, 1));
+        assert!(pixmap.is_protected(0, 0, 11, 11));
+        assert!(!pixmap.is_protected(15, 10, 10, 10));
+        // rectangles which wrap around the edges are checked where they actually land
+        assert!(pixmap.is_protected(90, 70, 1, 1));
+        assert!(pixmap.is_protected(78, 12, 15, 1));
+        assert!(!pixmap.is_protected(78, 12, 10, 1));
+
+        let mut mask = vec![false; 4 * 2];
+        mask[5] = true;
+        pixmap.set_protection_mask(Some(ProtectionMask::new(4, 2, mask)));
+        assert!(pixmap.is_protected(1, 1, 1, 1));
+        assert!(!pixmap.is_protected(0, 0, 1, 2));
+        assert!(pixmap.is_protected(14, 14, 1, 1));
+
+        // protected pixels do not fade
+        let background = Color::from((0, 0, 0));
+        pixmap
+            .fill_rect(0, 0, 80, 60, Color::from((0xFF, 0xFF, 0xFF)))
+            .unwrap();
+        pixmap.decay(background, Duration::from_secs(1), Duration::from_secs(100));
+        assert_eq!(pixmap.get_pixel(1, 1).unwrap(), Color::from((0xFF, 0xFF, 0xFF)));
+        assert_eq!(pixmap.get_pixel(12, 12).unwrap(), Color::from((0xFF, 0xFF, 0xFF)));
+        assert_eq!(pixmap.get_pixel(0, 0).unwrap(), background);
+
+        pixmap.clear_protection();
+        assert!(!pixmap.is_protected(0, 0, 80, 60));
+    }
+
+    #[test]
+    fn test_decay() {
+        let color = Color::from((0xFF, 0x80, 0x00));
+        let background = Color::from((0x00, 0x00, 0x40));
+        let half_life = Duration::from_secs(10);
+        let pixmap = Pixmap::new(80, 60).unwrap().with_dirty_tracking(16);
+        let mut cursor = DirtyCursor::default();
+        pixmap.fill_rect(0, 0, 80, 60, background).unwrap();
+        pixmap.fill_rect(0, 0, 16, 16, color).unwrap();
+        pixmap.dirty_tiles(&mut cursor);
+
+        // one half-life halves the distance to the background, give or take the dithering
+        pixmap.decay(background, half_life, half_life);
+        let [r, g, b]: [u8; 3] = pixmap.get_pixel(5, 5).unwrap().into();
+        assert!((0x7F..=0x80).contains(&r) && (0x3F..=0x40).contains(&g) && (0x20..=0x21).contains(&b));
+        assert_eq!(
+            pixmap.dirty_tiles(&mut cursor),
+            vec![Tile {
+                x: 0,
+                y: 0,
+                width: 16,
+                height: 16
+            }]
+        );
+
+        // eventually nothing is left
+        pixmap.decay(background, half_life, half_life * 100);
+        assert_eq!(pixmap.get_pixel(5, 5).unwrap(), background);
+        pixmap.dirty_tiles(&mut cursor);
+        pixmap.decay(background, half_life, half_life);
+        assert!(pixmap.dirty_tiles(&mut cursor).is_empty());
+
+        // fresh writes only fade by their age
+        let pixmap = Pixmap::new(80, 60).unwrap().with_attribution();
+        pixmap.set_pixel(0, 0, color).unwrap();
+        pixmap.decay(background, half_life, half_life * 100);
+        assert_ne!(pixmap.get_pixel(0, 0).unwrap(), background);
+        assert_eq!(pixmap.get_pixel(1, 0).unwrap(), background);
+        // writes are timed precisely enough to tell them apart at the usual fading interval
+        pixmap.set_pixel(2, 0, color).unwrap();
+        pixmap.decay(background, Duration::from_millis(100), Duration::from_millis(200));
+        let [r, _, _]: [u8; 3] = pixmap.get_pixel(2, 0).unwrap().into();
+        assert!(r >= 0xC0, "fresh write faded to {r:#X}");
+    }
+
+    #[test]
+    fn test_compare_exchange() {
+        let dir = tempfile::tempdir().unwrap();
+        let (black, red, blue) = (
+            Color::from((0, 0, 0)),
+            Color::from((0xFF, 0, 0)),
+            Color::from((0, 0, 0xFF)),
+        );
+        for buffer in [
+            PixelBuffer::new(4),
+            PixelBuffer::Mapped(MappedBuffer::open(&dir.path().join("canvas.pixmap"), 2, 2).unwrap()),
+        ] {
+            assert!(buffer.compare_exchange(1, black, red));
+            assert!(!buffer.compare_exchange(1, black, blue));
+            assert_eq!(buffer.load(1), red);
+            assert_eq!(buffer.load(0), black);
+        }
+    }
+
+    #[test]
+    fn test_map_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("canvas.pixmap");
+        let red = Color::from((0xFF, 0, 0));
+
+        // a new file starts out black and keeps everything that is written
+        {
+            let pixmap = Pixmap::map_file(&path, 8, 4).unwrap();
+            assert_eq!(pixmap.color_data(), vec![Color::from((0, 0, 0)); 8 * 4]);
+            pixmap.set_pixel(7, 3, red).unwrap();
+            pixmap.fill_rect(0, 0, 2, 2, red).unwrap();
+            assert_eq!(pixmap.get_pixel(7, 3).unwrap(), red);
+            assert!(pixmap.resize(16, 4).is_err());
+        }
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(len, 9 + 16 + 8 * 4 * 3);
+
+        // the content is still there when the file is mapped again
+        let pixmap = Pixmap::map_file(&path, 8, 4).unwrap();
+        assert_eq!(pixmap.get_pixel(7, 3).unwrap(), red);
+        assert_eq!(pixmap.get_pixel(1, 1).unwrap(), red);
+        assert_eq!(pixmap.get_pixel(2, 2).unwrap(), Color::from((0, 0, 0)));
+        drop(pixmap);
+
+        // files which do not fit are rejected
+        assert!(matches!(
+            Pixmap::map_file(&path, 4, 8),
+            Err(MapFileError::SizeMismatch {
+                expected: (4, 8),
+                found: (8, 4)
+            })
+        ));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        assert!(matches!(
+            Pixmap::map_file(&path, 8, 4),
+            Err(MapFileError::InvalidLength { .. })
+        ));
+        std::fs::write(&path, b"not a pixmap at all, but long enough").unwrap();
+        assert!(matches!(
+            Pixmap::map_file(&path, 8, 4),
+            Err(MapFileError::InvalidMagic)
+        ));
+
+        // a file whose header was never written because of a crash is initialized again
+        std::fs::write(&path, vec![0; len as usize]).unwrap();
+        let pixmap = Pixmap::map_file(&path, 8, 4).unwrap();
+        pixmap.set_pixel(7, 3, red).unwrap();
+        drop(pixmap);
+        let pixmap = Pixmap::map_file(&path, 8, 4).unwrap();
+        assert_eq!(pixmap.get_pixel(7, 3).unwrap(), red);
+        drop(pixmap);
+        assert!(matches!(
+            Pixmap::map_file(&path, 0, 4),
+            Err(MapFileError::InvalidSize(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_coordinate_policy() {
+        assert_eq!("reject".parse(), Ok(CoordinatePolicy::Reject));
+        assert_eq!("Clip".parse(), Ok(CoordinatePolicy::Clip));
+        assert_eq!("WRAP".parse(), Ok(CoordinatePolicy::Wrap));
+        assert_eq!(
+            "torus".parse::<CoordinatePolicy>(),
+            Err(UnknownCoordinatePolicyError)
+        );
+    }
+
+    #[bench]
+    fn bench_set_pixel(b: &mut Bencher) {
+        let pixmap = Pixmap::new(800, 600).unwrap();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        b.iter(|| {
+            for i in 0..1000 {
+                pixmap
+                    .set_pixel(black_box(i * 7 % 800), black_box(i * 13 % 600), color)
+                    .unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_get_pixel(b: &mut Bencher) {
+        let pixmap = Pixmap::new(800, 600).unwrap();
+        b.iter(|| {
+            for i in 0..1000 {
+                black_box(
+                    pixmap
+                        .get_pixel(black_box(i * 7 % 800), black_box(i * 13 % 600))
+                        .unwrap(),
+                );
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_batch_set_pixel(b: &mut Bencher) {
+        let pixmap = Pixmap::new(800, 600).unwrap();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        b.iter(|| {
+            let mut batch = pixmap.batch(WriterId::UNKNOWN);
+            for i in 0..1000 {
+                batch
+                    .set_pixel(black_box(i * 7 % 800), black_box(i * 13 % 600), color)
+                    .unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_batch_set_pixel_mapped(b: &mut Bencher) {
+        let dir = tempfile::tempdir().unwrap();
+        let pixmap = Pixmap::map_file(&dir.path().join("canvas.pixmap"), 800, 600).unwrap();
+        let color = Color::from((0xAB, 0xCD, 0xEF));
+        b.iter(|| {
+            let mut batch = pixmap.batch(WriterId::UNKNOWN);
+            for i in 0..1000 {
+                batch
+                    .set_pixel(black_box(i * 7 % 800), black_box(i * 13 % 600), color)
+                    .unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_batch_get_pixel(b: &mut Bencher) {
+        let pixmap = Pixmap::new(800, 600).unwrap();
+        b.iter(|| {
+            let batch = pixmap.batch(WriterId::UNKNOWN);
+            for i in 0..1000 {
+                black_box(
+                    batch
+                        .get_pixel(black_box(i * 7 % 800), black_box(i * 13 % 600))
+                        .unwrap(),
+                );
+            }
+        });
     }
 }
@@ -1,6 +1,7 @@
 //! A sink which pipes the canvas into ffmpeg for video encoding or streaming
 
-use crate::pixmap::SharedPixmap;
+use crate::pixmap::{DirtyCursor, SharedPixmap};
+use crate::sinks::overlay::SharedOverlay;
 use crate::DaemonResult;
 use anyhow::anyhow;
 use std::process::Stdio;
@@ -143,6 +144,9 @@ pub struct FfmpegSink {
     options: FfmpegOptions,
     pixmap: SharedPixmap,
     ffmpeg_proc: Option<Child>,
+    /// The size of the video that the running ffmpeg process expects
+    video_size: (usize, usize),
+    overlay: Option<SharedOverlay>,
 }
 
 impl FfmpegSink {
@@ -152,6 +156,16 @@ impl FfmpegSink {
             options,
             pixmap,
             ffmpeg_proc: None,
+            video_size: (0, 0),
+            overlay: None,
+        }
+    }
+
+    /// Draw the given overlay over every frame
+    pub fn with_overlay(self, overlay: SharedOverlay) -> Self {
+        Self {
+            overlay: Some(overlay),
+            ..self
         }
     }
 
@@ -172,6 +186,7 @@ impl FfmpegSink {
         }
 
         let (width, height) = self.pixmap.get_size();
+        self.video_size = (width, height);
 
         let mut cmd = Command::new("ffmpeg");
         cmd.stdin(Stdio::piped()).kill_on_drop(true).env_clear();
@@ -192,9 +207,9 @@ impl FfmpegSink {
             .arg("rgb24")
             // provide metadata since it is not included in the rawvideo format
             .arg("-video_size")
-            .arg(&format!("{}x{}", width, height))
+            .arg(format!("{}x{}", width, height))
             .arg("-framerate")
-            .arg(&self.options.framerate.to_string())
+            .arg(self.options.framerate.to_string())
             // tell ffmpeg that it should read input from stdin
             .arg("-i")
             .arg("/dev/stdin");
@@ -216,25 +231,48 @@ impl FfmpegSink {
         Ok(())
     }
 
-    /// Execute the main loop which periodically sinks data into ffmpeg
-    async fn run(self) -> anyhow::Result<!> {
-        let mut ffmpeg = self.ffmpeg_proc.ok_or(anyhow!("ffmpeg is not running"))?;
-        let Some(channel) = &mut ffmpeg.stdin else {
-            return Err(anyhow!("ffmpegs stdin is not attached"));
-        };
+    /// Stop the ffmpeg child process after letting it finish encoding the data it already received
+    async fn stop_ffmpeg(&mut self) -> anyhow::Result<()> {
+        if let Some(mut ffmpeg) = self.ffmpeg_proc.take() {
+            drop(ffmpeg.stdin.take());
+            ffmpeg.wait().await?;
+        }
+        Ok(())
+    }
 
+    /// Execute the main loop which periodically sinks data into ffmpeg
+    async fn run(mut self) -> anyhow::Result<!> {
+        let mut size = self.pixmap.watch_size();
         let mut interval =
             tokio::time::interval(Duration::from_secs_f64(1.0 / self.options.framerate as f64));
 
+        // ffmpeg expects a frame on every tick but only the changed parts of it need to be copied again
+        let mut cursor = DirtyCursor::default();
+        let mut data = Vec::new();
+        // the overlay is drawn onto a copy so that the pixmap data can still be updated incrementally
+        let mut frame = Vec::new();
         loop {
-            let data = unsafe {
-                self.pixmap
-                    .get_color_data()
-                    .iter()
-                    .flat_map(|c| Into::<[u8; 3]>::into(*c))
-                    .collect::<Vec<_>>()
-            };
-            channel.write_all(&data).await.expect("Could not write to ffmpeg");
+            // the video size of a running ffmpeg process cannot be changed so it is restarted instead
+            if *size.borrow_and_update() != self.video_size {
+                tracing::info!("pixmap was resized, restarting ffmpeg");
+                self.stop_ffmpeg().await?;
+                self.start_ffmpeg()?;
+            }
+
+            self.pixmap.update_rgb_data(&mut cursor, &mut data);
+            // a resize may not have been announced yet, in which case the data does not fit the video
+            if data.len() == self.video_size.0 * self.video_size.1 * 3 {
+                let Some(channel) = self.ffmpeg_proc.as_mut().and_then(|ffmpeg| ffmpeg.stdin.as_mut()) else {
+                    return Err(anyhow!("ffmpegs stdin is not attached"));
+                };
+                let frame = match &self.overlay {
+                    Some(overlay) => {
+                        overlay.composited_rgb(&data, &mut frame, self.video_size.0, self.video_size.1)
+                    }
+                    None => &data,
+                };
+                channel.write_all(frame).await.expect("Could not write to ffmpeg");
+            }
 
             interval.tick().await;
         }
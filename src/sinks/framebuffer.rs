@@ -1,6 +1,7 @@
 //! A sink implementation for drawing on a linux framebuffer
 
-use crate::pixmap::{Color, SharedPixmap};
+use crate::pixmap::{Color, DirtyCursor, ScaleFilter, Scaler, SharedPixmap};
+use crate::sinks::overlay::SharedOverlay;
 use crate::DaemonResult;
 use anyhow::Context;
 use framebuffer::{Bitfield, Framebuffer};
@@ -9,56 +10,6 @@ use std::time::Duration;
 use tokio::task::{AbortHandle, JoinSet};
 use tokio::time::{interval, Instant, MissedTickBehavior};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct Sampler {
-    /// A mapping of screen-pixel-index to pixmap-pixel-index
-    ///
-    /// If it is `None`, no mapping needs to be done because the screen and pixmap have the same sizes
-    mapping: Option<Vec<u32>>,
-}
-
-impl Sampler {
-    pub fn new(src_width: usize, src_height: usize, out_width: usize, out_height: usize) -> Self {
-        if src_width == out_width && src_height == out_height {
-            Self { mapping: None }
-        } else {
-            tracing::warn!("Framebuffer has size {}x{} while pixmap has size {}x{}. This requires an additional sampling step which slows down rendering", out_width, out_height, src_width, src_height);
-            Self {
-                mapping: Some(
-                    (0..out_width * out_height)
-                        .map(|i_screen_px| {
-                            let screen_x = i_screen_px % out_width;
-                            let screen_y = i_screen_px / out_width;
-                            let px_x = (screen_x * src_width) / out_width;
-                            let px_y = (screen_y * src_height) / out_height;
-                            (px_y * src_width + px_x) as u32
-                        })
-                        .collect(),
-                ),
-            }
-        }
-    }
-
-    pub fn needs_sampling(&self) -> bool {
-        self.mapping.is_some()
-    }
-
-    #[allow(unused)]
-    #[inline(always)]
-    pub fn get_mapping(&self, i_screen_px: usize) -> Option<usize> {
-        self.mapping.as_ref()?.get(i_screen_px).map(|x| *x as usize)
-    }
-
-    #[inline(always)]
-    pub unsafe fn get_mappin_unchecked(&self, i_screen_px: usize) -> usize {
-        *self
-            .mapping
-            .as_ref()
-            .unwrap_unchecked()
-            .get_unchecked(i_screen_px) as usize
-    }
-}
-
 /// Options for configuring a [`FramebufferSink`]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct FramebufferSinkOptions {
@@ -73,12 +24,25 @@ pub struct FramebufferSinkOptions {
 pub struct FramebufferSink {
     options: FramebufferSinkOptions,
     pixmap: SharedPixmap,
+    overlay: Option<SharedOverlay>,
 }
 
 impl FramebufferSink {
     /// Create a new `FramebufferSink`
     pub fn new(options: FramebufferSinkOptions, pixmap: SharedPixmap) -> Self {
-        Self { options, pixmap }
+        Self {
+            options,
+            pixmap,
+            overlay: None,
+        }
+    }
+
+    /// Draw the given overlay over every frame
+    pub fn with_overlay(self, overlay: SharedOverlay) -> Self {
+        Self {
+            overlay: Some(overlay),
+            ..self
+        }
     }
 
     /// Start a background task for rendering onto the framebuffer device
@@ -115,18 +79,22 @@ impl FramebufferSink {
         let mut interval = interval(Duration::from_secs_f64(1.0 / self.options.framerate as f64));
         interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
-        let (pixmap_width, pixmap_height) = self.pixmap.get_size();
+        let mut pixmap_size = self.pixmap.watch_size();
+        let (mut pixmap_width, mut pixmap_height) = *pixmap_size.borrow_and_update();
         let screen_width = fb.var_screen_info.xres as usize;
         let screen_height = fb.var_screen_info.yres as usize;
-        let sampler = Sampler::new(pixmap_width, pixmap_height, screen_width, screen_height);
+        let scaler = screen_scaler(pixmap_width, pixmap_height, screen_width, screen_height);
 
-        let fb_pixels = screen_width * screen_height;
         let encoder = Encoder {
             r: fb.var_screen_info.red.clone(),
             g: fb.var_screen_info.green.clone(),
             b: fb.var_screen_info.blue.clone(),
         };
-        let renderer = Renderer { sampler, encoder };
+        let mut renderer = Renderer {
+            scaler,
+            encoder,
+            scaled: Vec::new(),
+        };
 
         let bits_per_pixel = fb.var_screen_info.bits_per_pixel as usize;
         let render_once_fn = match bits_per_pixel {
@@ -138,46 +106,85 @@ impl FramebufferSink {
             ),
         };
 
+        let mut cursor = DirtyCursor::default();
+        let mut pixel_data = Vec::new();
+        let mut overlay_generation = None;
+        let mut frame = Vec::new();
         loop {
-            let t1 = Instant::now();
-            render_once_fn(
-                &&renderer,
-                unsafe { self.pixmap.get_color_data() },
-                &mut fb,
-                fb_pixels,
-            );
-            let t2 = Instant::now();
-            tracing::trace!("Render: {}ms", (t2 - t1).as_millis());
+            if pixmap_size.has_changed().unwrap_or(false) {
+                (pixmap_width, pixmap_height) = *pixmap_size.borrow_and_update();
+                renderer.scaler = screen_scaler(pixmap_width, pixmap_height, screen_width, screen_height);
+                cursor = DirtyCursor::default();
+            }
+
+            // the framebuffer keeps showing the last frame if neither the pixmap nor the overlay changed
+            let mut changed = self.pixmap.update_color_data(&mut cursor, &mut pixel_data);
+            let generation = self.overlay.as_ref().map(|overlay| overlay.generation());
+            changed |= generation != overlay_generation;
+            // a resize may not have been announced yet, in which case the data does not fit the scaler
+            if changed && pixel_data.len() == pixmap_width * pixmap_height {
+                overlay_generation = generation;
+                let t1 = Instant::now();
+                let data = match &self.overlay {
+                    Some(overlay) => overlay.composited(&pixel_data, &mut frame, pixmap_width, pixmap_height),
+                    None => &pixel_data,
+                };
+                render_once_fn(&mut renderer, data, &mut fb);
+                let t2 = Instant::now();
+                tracing::trace!("Render: {}ms", (t2 - t1).as_millis());
+            }
             interval.tick().await;
         }
     }
 }
 
+/// Create a scaler from the pixmap size to the screen size
+fn screen_scaler(
+    pixmap_width: usize,
+    pixmap_height: usize,
+    screen_width: usize,
+    screen_height: usize,
+) -> Scaler {
+    let scaler = Scaler::new(
+        pixmap_width,
+        pixmap_height,
+        screen_width,
+        screen_height,
+        ScaleFilter::Nearest,
+    );
+    if !scaler.is_identity() {
+        tracing::warn!("Framebuffer has size {}x{} while pixmap has size {}x{}. This requires an additional sampling step which slows down rendering", screen_width, screen_height, pixmap_width, pixmap_height);
+    }
+    scaler
+}
+
 /// A little helper struct that provides a generic render method.
 /// You can call render with any type T, as long as T: Copy and the
 /// Encoder can encode Pixels to T.
 #[derive(Debug, Clone)]
 pub struct Renderer {
     encoder: Encoder,
-    sampler: Sampler,
+    scaler: Scaler,
+    /// Scratch space for pixel data which has been scaled to the screen size
+    scaled: Vec<Color>,
 }
 
 impl Renderer {
-    fn render<T: Copy>(&self, pixel_data: &[Color], fb: &mut Framebuffer, fb_pixels: usize)
+    fn render<T: Copy>(&mut self, pixel_data: &[Color], fb: &mut Framebuffer)
     where
         Encoder: Encode<T>,
     {
-        // encode pixel data into framebuffer format
-        let encoded: Vec<T> = self.encoder.encode_vec(pixel_data);
-
-        // sample pixels to framebuffer size
-        let pixels = if self.sampler.needs_sampling() {
-            let sampled = sample_vec(&self.sampler, &encoded, fb_pixels);
-            sampled
+        // scale pixels to framebuffer size
+        let pixel_data = if self.scaler.is_identity() {
+            pixel_data
         } else {
-            encoded
+            self.scaler.scale(pixel_data, &mut self.scaled);
+            &self.scaled
         };
 
+        // encode pixel data into framebuffer format
+        let pixels: Vec<T> = self.encoder.encode_vec(pixel_data);
+
         // transmute and copy to framebuffer
         let pixel_bytes = unsafe {
             let (prefix, bytes, suffix) = pixels.align_to::<u8>();
@@ -185,28 +192,10 @@ impl Renderer {
             assert_eq!(suffix.len(), 0);
             bytes
         };
-        fb.write_frame(&pixel_bytes);
+        fb.write_frame(pixel_bytes);
     }
 }
 
-#[inline(always)]
-fn sample_iter<'e, 's: 'e, T: Copy>(
-    sampler: &'s Sampler,
-    encoded: &'e [T],
-    px: usize,
-) -> impl Iterator<Item = T> + 'e {
-    let iter = (0..px).map(|px| unsafe {
-        let sample_px = sampler.get_mappin_unchecked(px);
-        *encoded.get_unchecked(sample_px)
-    });
-    iter
-}
-
-#[inline(always)]
-fn sample_vec<T: Copy>(sampler: &Sampler, encoded: &[T], px: usize) -> Vec<T> {
-    sample_iter(sampler, encoded, px).collect()
-}
-
 /// A Pixel encoder.
 /// The r, g and b fields describe the pixel layout.
 /// Call encoding methods trough the Encode<Target> trait.
@@ -4,6 +4,7 @@
 
 pub mod ffmpeg;
 pub mod framebuffer;
+pub mod overlay;
 pub mod pixmap_file;
 #[cfg(feature = "windowing")]
 pub mod window;
@@ -0,0 +1,394 @@
+//! A layer which sinks draw over the canvas without it being part of the pixmap
+//!
+//! Clients can neither read nor overwrite the overlay because it is only composited onto the frames which sinks
+//! deliver.
+//! It consists of a static image, e.g. a logo, and an image of text which may change over time to show statistics
+//! about the server.
+
+use crate::pixmap::Color;
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+#[cfg(feature = "overlay-text")]
+use crate::pixmap::SharedPixmap;
+#[cfg(feature = "overlay-text")]
+use crate::DaemonResult;
+#[cfg(feature = "overlay-text")]
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+#[cfg(feature = "overlay-text")]
+use tokio::task::{AbortHandle, JoinSet};
+#[cfg(feature = "overlay-text")]
+use tokio::time::Interval;
+
+/// The font with which overlay text is rendered
+#[cfg(feature = "overlay-text")]
+pub const FONT_HERMIT_REGULAR: &[u8] = include_bytes!("../../resources/Hermit-Regular.otf");
+
+/// An overlay which is shared between all sinks of one canvas
+pub type SharedOverlay = Arc<Overlay>;
+
+/// A rectangular image which is drawn at a fixed position of the overlay
+///
+/// Transparent regions of the image let the canvas shine through.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OverlayImage {
+    /// The x coordinate of the top left corner
+    x: usize,
+    /// The y coordinate of the top left corner
+    y: usize,
+    /// How many pixels the image spans horizontally
+    width: usize,
+    /// How many pixels the image spans vertically
+    height: usize,
+    /// The colors of the image row by row starting at the top left corner
+    colors: Vec<Color>,
+}
+
+impl OverlayImage {
+    /// Create an image of `width` by `height` pixels whose top left corner is at `x`, `y`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `colors` does not contain exactly one color per pixel of the image.
+    pub fn new(x: usize, y: usize, width: usize, height: usize, colors: Vec<Color>) -> Self {
+        assert_eq!(
+            Some(colors.len()),
+            width.checked_mul(height),
+            "image data does not fit the image size"
+        );
+        Self {
+            x,
+            y,
+            width,
+            height,
+            colors,
+        }
+    }
+
+    /// Move the image so that its top left corner is at `x`, `y`
+    pub fn at(self, x: usize, y: usize) -> Self {
+        Self { x, y, ..self }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Layers {
+    /// Incremented whenever any of the layers changes
+    generation: u64,
+    image: Option<OverlayImage>,
+    text: Option<OverlayImage>,
+}
+
+/// The images which sinks draw over the canvas
+///
+/// Overlays are defined in canvas coordinates, so everything that lies outside of the canvas is cut off.
+#[derive(Debug, Default)]
+pub struct Overlay {
+    layers: ArcSwap<Layers>,
+}
+
+impl Overlay {
+    /// Create an overlay which does not draw anything yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or remove the static image of the overlay
+    pub fn set_image(&self, image: Option<OverlayImage>) {
+        self.layers.rcu(|layers| Layers {
+            generation: layers.generation + 1,
+            image: image.clone(),
+            text: layers.text.clone(),
+        });
+    }
+
+    /// Set or remove the image of rendered text which is drawn on top of the static image
+    pub fn set_text(&self, text: Option<OverlayImage>) {
+        self.layers.rcu(|layers| Layers {
+            generation: layers.generation + 1,
+            image: layers.image.clone(),
+            text: text.clone(),
+        });
+    }
+
+    /// A number which changes whenever the content of the overlay changes
+    ///
+    /// Sinks which only render when the canvas changed use it to also render when the overlay changed.
+    pub fn generation(&self) -> u64 {
+        self.layers.load().generation
+    }
+
+    /// Whether the overlay does not draw anything at all
+    pub fn is_empty(&self) -> bool {
+        let layers = self.layers.load();
+        layers.image.is_none() && layers.text.is_none()
+    }
+
+    /// Draw the overlay over a frame of `width` by `height` pixels
+    pub fn composite(&self, frame: &mut [Color], width: usize, height: usize) {
+        self.for_each_pixel(width, height, |i, color| frame[i] = color.blend_over(frame[i]));
+    }
+
+    /// Draw the overlay over a frame of `width` by `height` pixels which is given as three bytes of RGB data per
+    /// pixel
+    pub fn composite_rgb(&self, frame: &mut [u8], width: usize, height: usize) {
+        self.for_each_pixel(width, height, |i, color| {
+            let rgb = &mut frame[i * 3..i * 3 + 3];
+            let background = Color::from([rgb[0], rgb[1], rgb[2]]);
+            rgb.copy_from_slice(&<[u8; 3]>::from(color.blend_over(background)));
+        });
+    }
+
+    /// Get a frame with the overlay drawn over `data`
+    ///
+    /// The overlay is drawn onto a copy in `scratch` so that sinks can keep updating `data` incrementally.
+    /// If the overlay is empty, `data` is returned as is.
+    pub fn composited<'a>(
+        &self,
+        data: &'a [Color],
+        scratch: &'a mut Vec<Color>,
+        width: usize,
+        height: usize,
+    ) -> &'a [Color] {
+        if self.is_empty() {
+            return data;
+        }
+        scratch.clear();
+        scratch.extend_from_slice(data);
+        self.composite(scratch, width, height);
+        scratch
+    }
+
+    /// Like [`composited()`](Self::composited) but for frames with three bytes of RGB data per pixel
+    pub fn composited_rgb<'a>(
+        &self,
+        data: &'a [u8],
+        scratch: &'a mut Vec<u8>,
+        width: usize,
+        height: usize,
+    ) -> &'a [u8] {
+        if self.is_empty() {
+            return data;
+        }
+        scratch.clear();
+        scratch.extend_from_slice(data);
+        self.composite_rgb(scratch, width, height);
+        scratch
+    }
+
+    /// Pass the frame index and overlay color of every visible overlay pixel to `draw`, bottom layer first
+    fn for_each_pixel(&self, width: usize, height: usize, mut draw: impl FnMut(usize, Color)) {
+        let layers = self.layers.load();
+        for image in layers.image.iter().chain(&layers.text) {
+            let columns = image.x.min(width)..image.x.saturating_add(image.width).min(width);
+            let rows = image.y.min(height)..image.y.saturating_add(image.height).min(height);
+            if columns.is_empty() {
+                continue;
+            }
+            for row in rows {
+                let src_start = (row - image.y) * image.width + (columns.start - image.x);
+                let colors = &image.colors[src_start..src_start + columns.len()];
+                for (column, &color) in columns.clone().zip(colors) {
+                    if color.alpha() > 0 {
+                        draw(row * width + column, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Configuration options for [`OverlayText`]
+#[cfg(feature = "overlay-text")]
+#[derive(Debug)]
+pub struct OverlayTextOptions {
+    /// The text which is displayed
+    ///
+    /// The placeholders `{address}`, `{clients}` and `{pixels_per_sec}` are replaced with the address of the
+    /// server, the number of connected clients and the number of pixels which clients wrote per second.
+    pub template: String,
+
+    /// What the `{address}` placeholder is replaced with
+    pub address: String,
+
+    /// The position of the top left corner of the text
+    pub position: (usize, usize),
+
+    /// The height of the text in pixels
+    pub size: f32,
+
+    /// The color of the text
+    pub color: Color,
+
+    /// The color of the box behind the text which keeps it readable
+    pub background: Color,
+
+    /// The interval in which the text is updated
+    pub interval: Interval,
+}
+
+/// A task which periodically renders statistics about the server into the text layer of an overlay
+#[cfg(feature = "overlay-text")]
+#[derive(Debug)]
+pub struct OverlayText {
+    options: OverlayTextOptions,
+    pixmap: SharedPixmap,
+    overlay: SharedOverlay,
+}
+
+#[cfg(feature = "overlay-text")]
+impl OverlayText {
+    /// Create a new task which shows statistics about the given pixmap in the overlay
+    pub fn new(options: OverlayTextOptions, pixmap: SharedPixmap, overlay: SharedOverlay) -> Self {
+        Self {
+            options,
+            pixmap,
+            overlay,
+        }
+    }
+
+    /// Start updating the text in the background
+    pub fn start(self, join_set: &mut JoinSet<DaemonResult>) -> anyhow::Result<AbortHandle> {
+        let handle = join_set
+            .build_task()
+            .name("overlay_text")
+            .spawn(async move { self.run().await })?;
+        Ok(handle)
+    }
+
+    async fn run(mut self) -> anyhow::Result<!> {
+        let font = FontRef::try_from_slice(FONT_HERMIT_REGULAR)?;
+        let mut last_tick = self.options.interval.tick().await;
+        let mut last_written = self.pixmap.written_pixels();
+        let mut last_text = String::new();
+        loop {
+            let tick = self.options.interval.tick().await;
+            let written = self.pixmap.written_pixels();
+            let pixels_per_sec = (written - last_written) as f64 / (tick - last_tick).as_secs_f64();
+            (last_tick, last_written) = (tick, written);
+
+            let text = self
+                .options
+                .template
+                .replace("{address}", &self.options.address)
+                .replace("{clients}", &self.pixmap.connected_clients().to_string())
+                .replace("{pixels_per_sec}", &format!("{pixels_per_sec:.0}"));
+            if text != last_text {
+                let (x, y) = self.options.position;
+                let image = render_text(
+                    &font,
+                    &text,
+                    self.options.size,
+                    self.options.color,
+                    self.options.background,
+                );
+                self.overlay.set_text(Some(image.at(x, y)));
+                last_text = text;
+            }
+        }
+    }
+}
+
+/// Render a line of text onto a box of the background color
+///
+/// The returned image is positioned at the origin.
+#[cfg(feature = "overlay-text")]
+pub fn render_text(font: &impl Font, text: &str, size: f32, color: Color, background: Color) -> OverlayImage {
+    let font = font.as_scaled(PxScale::from(size));
+    let width = text
+        .chars()
+        .map(|c| font.h_advance(font.glyph_id(c)))
+        .sum::<f32>()
+        .ceil() as usize;
+    let height = font.height().ceil() as usize;
+    let mut colors = vec![background; width * height];
+
+    let mut caret = 0.0;
+    for c in text.chars() {
+        let mut glyph = font.scaled_glyph(c);
+        glyph.position = point(caret, font.ascent());
+        caret += font.h_advance(glyph.id);
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|glyph_x, glyph_y, coverage| {
+            let x = bounds.min.x as i64 + glyph_x as i64;
+            let y = bounds.min.y as i64 + glyph_y as i64;
+            if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                let i = y as usize * width + x as usize;
+                colors[i] = mix(colors[i], color, coverage);
+            }
+        });
+    }
+
+    OverlayImage::new(0, 0, width, height, colors)
+}
+
+/// Interpolate all channels, including alpha, between two colors where `amount` is the contribution of `b`
+#[cfg(feature = "overlay-text")]
+fn mix(a: Color, b: Color, amount: f32) -> Color {
+    let (a, b) = (<[u8; 4]>::from(a), <[u8; 4]>::from(b));
+    let amount = amount.clamp(0.0, 1.0);
+    let channel = |i: usize| (a[i] as f32 + (b[i] as f32 - a[i] as f32) * amount).round() as u8;
+    Color::from([channel(0), channel(1), channel(2), channel(3)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_composite() {
+        let red = Color::from((0xFF, 0, 0));
+        let overlay = Overlay::new();
+        assert!(overlay.is_empty());
+        overlay.set_image(Some(OverlayImage::new(
+            2,
+            1,
+            2,
+            2,
+            vec![red, Color::from_rgba(0, 0, 0xFF, 0), red, red.with_alpha(0x80)],
+        )));
+        assert!(!overlay.is_empty());
+
+        // the image is cut off at the edge of the frame and transparent pixels are skipped
+        let background = Color::from((0, 0xFF, 0));
+        let mut frame = vec![background; 3 * 3];
+        overlay.composite(&mut frame, 3, 3);
+        assert_eq!(
+            frame,
+            vec![
+                background, background, background, background, background, red, background, background, red
+            ]
+        );
+
+        let mut rgb = vec![0; 4 * 3 * 3];
+        let generation = overlay.generation();
+        overlay.set_text(Some(OverlayImage::new(3, 2, 1, 1, vec![red.with_alpha(0x80)])));
+        assert_ne!(overlay.generation(), generation);
+        overlay.composite_rgb(&mut rgb, 4, 3);
+        assert_eq!(rgb[(2 * 4 + 3) * 3..], [0xC0, 0, 0]);
+        assert_eq!(rgb[(4 + 2) * 3..(4 + 3) * 3], [0xFF, 0, 0]);
+    }
+
+    #[cfg(feature = "overlay-text")]
+    #[test]
+    fn test_render_text() {
+        let font = FontRef::try_from_slice(FONT_HERMIT_REGULAR).unwrap();
+        let white = Color::from((0xFF, 0xFF, 0xFF));
+        let background = Color::from_rgba(0, 0, 0, 0x80);
+        let image = render_text(&font, "pixels", 16.0, white, background);
+        assert!(image.width > 16 && image.height >= 16);
+        assert_eq!(image.colors.len(), image.width * image.height);
+        assert!(image.colors.contains(&white));
+        assert!(image.colors.contains(&background));
+        assert!(render_text(&font, "", 16.0, white, background).colors.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "image data does not fit the image size")]
+    fn test_image_size_mismatch() {
+        OverlayImage::new(0, 0, 2, 2, vec![Color::default(); 3]);
+    }
+}
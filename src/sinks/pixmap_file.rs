@@ -1,6 +1,6 @@
 //! A sink for periodically snapshotting the canvas into a pixmap file
 
-use crate::pixmap::{Pixmap, SharedPixmap};
+use crate::pixmap::{Color, DirtyCursor, Pixmap, SharedPixmap, FILE_MAGIC};
 use crate::DaemonResult;
 use anyhow::anyhow;
 use itertools::Itertools;
@@ -11,7 +11,6 @@ use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::task::{AbortHandle, JoinSet};
 use tokio::time::Interval;
 
-const FILE_MAGIC: &[u8] = b"PIXELFLUT";
 const HEADER_SIZE: usize = size_of::<u64>() * 2; // enough space for width and height
 
 const SEEK_MAGIC: SeekFrom = SeekFrom::Start(0);
@@ -46,7 +45,7 @@ impl FileSink {
     /// Open the target file and start the background tasks for periodic snapshotting
     pub async fn start(self, join_set: &mut JoinSet<DaemonResult>) -> anyhow::Result<AbortHandle> {
         let mut file = self.open_file().await?;
-        self.write_header(&mut file).await?;
+        self.write_header(&mut file, self.pixmap.get_size()).await?;
         let handle = join_set
             .build_task()
             .name("file_sink")
@@ -59,14 +58,14 @@ impl FileSink {
         Ok(File::options()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&self.options.path)
             .await?)
     }
 
     /// Write appropriate header information into the file so that later operations only have to write data
-    async fn write_header(&self, file: &mut File) -> anyhow::Result<()> {
+    async fn write_header(&self, file: &mut File, (width, height): (usize, usize)) -> anyhow::Result<()> {
         // set file length to exact content size
-        let (width, height) = self.pixmap.get_size();
         file.set_len((FILE_MAGIC.len() + HEADER_SIZE + width * height * 3) as u64)
             .await?;
 
@@ -86,15 +85,9 @@ impl FileSink {
     }
 
     /// Write pixmap data into the data section of the file
-    async fn write_data(&self, file: &mut File) -> anyhow::Result<()> {
+    async fn write_data(&self, file: &mut File, data: &[u8]) -> anyhow::Result<()> {
         file.seek(SEEK_DATA).await?;
-
-        let data = unsafe { self.pixmap.get_color_data() };
-        let data = data
-            .iter()
-            .flat_map(|c| Into::<[u8; 3]>::into(*c))
-            .collect::<Vec<_>>();
-        file.write_all(&data).await?;
+        file.write_all(data).await?;
 
         file.flush().await?;
         file.sync_all().await?;
@@ -103,9 +96,27 @@ impl FileSink {
     }
 
     /// Execute the main loop which periodically snapshots data into the file
+    ///
+    /// Snapshots are skipped while nothing changes.
     async fn run(mut self, mut file: File) -> anyhow::Result<!> {
+        let mut size = self.pixmap.watch_size();
+        let mut header_size = self.pixmap.get_size();
+        let mut cursor = DirtyCursor::default();
+        let mut data = Vec::new();
         loop {
-            self.write_data(&mut file).await?;
+            // the header describes the size of the data so it needs to be rewritten when the pixmap is resized
+            let current_size = *size.borrow_and_update();
+            if current_size != header_size {
+                header_size = current_size;
+                self.write_header(&mut file, header_size).await?;
+                cursor = DirtyCursor::default();
+            }
+
+            // a resize may not have been announced yet, in which case the data does not fit the header
+            let changed = self.pixmap.update_rgb_data(&mut cursor, &mut data);
+            if changed && data.len() == header_size.0 * header_size.1 * 3 {
+                self.write_data(&mut file, &data).await?;
+            }
             self.options.interval.tick().await;
         }
     }
@@ -137,11 +148,8 @@ pub async fn load_pixmap_file(path: &Path) -> anyhow::Result<Pixmap> {
     file.read_exact(&mut buf).await?;
 
     // construct a pixmap with the loaded data
-    let pixmap = Pixmap::new(width, height)?;
-    let pixmap_data = unsafe { pixmap.get_color_data() };
-    for (i, i_color) in buf.into_iter().tuples::<(_, _, _)>().enumerate() {
-        pixmap_data[i] = i_color.into()
-    }
+    let data = buf.into_iter().tuples::<(_, _, _)>().map(Color::from).collect();
+    let pixmap = Pixmap::from_color_data(width, height, data)?;
 
     Ok(pixmap)
 }
@@ -179,16 +187,32 @@ mod test {
                 original_pixmap.clone(),
             );
             let mut file = sink.open_file().await.unwrap();
-            sink.write_header(&mut file).await.unwrap();
-            sink.write_data(&mut file).await.unwrap();
+            sink.write_header(&mut file, original_pixmap.get_size())
+                .await
+                .unwrap();
+            sink.write_data(&mut file, &original_pixmap.rgb_data())
+                .await
+                .unwrap();
         }
 
         // restore data from the file
         let restored_pixmap = load_pixmap_file(&file_path).await.unwrap();
 
         // compare data
-        let original_data = unsafe { original_pixmap.get_color_data() };
-        let restored_data = unsafe { restored_pixmap.get_color_data() };
-        assert_eq!(original_data, restored_data);
+        assert_eq!(original_pixmap.color_data(), restored_pixmap.color_data());
+    }
+
+    #[tokio::test]
+    async fn test_load_mapped_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("test.pixmap");
+        let mapped_pixmap = Pixmap::map_file(&file_path, 5, 3).unwrap();
+        mapped_pixmap
+            .set_pixel(4, 2, Color::from((0xab, 0xab, 0xab)))
+            .unwrap();
+
+        // the file can be read while it is mapped
+        let restored_pixmap = load_pixmap_file(&file_path).await.unwrap();
+        assert_eq!(mapped_pixmap.color_data(), restored_pixmap.color_data());
     }
 }
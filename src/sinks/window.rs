@@ -1,10 +1,10 @@
 //! A sink for drawing on an X or Wayland window
 
-use crate::pixmap::SharedPixmap;
+use crate::pixmap::{Color, DirtyCursor, ScaleFilter, Scaler, SharedPixmap};
+use crate::sinks::overlay::SharedOverlay;
 use crate::DaemonResult;
 use anyhow::anyhow;
-use minifb::{Window, WindowOptions};
-use std::mem;
+use minifb::{ScaleMode, Window, WindowOptions};
 use std::time::Duration;
 use tokio::task::{AbortHandle, JoinSet};
 use tokio::time::MissedTickBehavior;
@@ -13,23 +13,49 @@ use tokio::time::MissedTickBehavior;
 ///
 /// Note that handles to X/Wayland windows are not Send so the background task must always be scheduled on the same thread.
 /// This is achieved by passing an existing `LocalSet` in which the background task will execute.
-pub fn start(join_set: &mut JoinSet<DaemonResult>, pixmap: SharedPixmap) -> anyhow::Result<AbortHandle> {
+/// If an overlay is given, it is drawn over the canvas.
+pub fn start(
+    join_set: &mut JoinSet<DaemonResult>,
+    pixmap: SharedPixmap,
+    overlay: Option<SharedOverlay>,
+) -> anyhow::Result<AbortHandle> {
     let (width, height) = pixmap.get_size();
-    let mut window = Window::new("pixelflut", width, height, WindowOptions::default())?;
-
-    window.set_title("Pixelflut Server");
-
+    let window = open_window(width, height)?;
     let handle = join_set
         .build_task()
         .name("window_renderer")
-        .spawn_local(async move { render(pixmap, window).await })?;
+        .spawn_local(async move { render(pixmap, overlay, window).await })?;
     Ok(handle)
 }
 
-async fn render(pixmap: SharedPixmap, mut window: Window) -> anyhow::Result<!> {
-    let (width, height) = pixmap.get_size();
+/// Open a resizable window which initially fits a pixmap of the given size
+fn open_window(width: usize, height: usize) -> anyhow::Result<Window> {
+    let options = WindowOptions {
+        resize: true,
+        scale_mode: ScaleMode::UpperLeft,
+        ..WindowOptions::default()
+    };
+    let mut window = Window::new("pixelflut", width, height, options)?;
+    window.set_title("Pixelflut Server");
+    Ok(window)
+}
+
+async fn render(
+    pixmap: SharedPixmap,
+    overlay: Option<SharedOverlay>,
+    mut window: Window,
+) -> anyhow::Result<!> {
+    let mut size = pixmap.watch_size();
+    let (mut width, mut height) = *size.borrow_and_update();
+    let mut window_size = window.get_size();
+    let mut scaler = Scaler::new(width, height, window_size.0, window_size.1, ScaleFilter::Nearest);
     let mut interval = tokio::time::interval(Duration::from_millis(1000 / 60));
     interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut cursor = DirtyCursor::default();
+    let mut pixels = Vec::<Color>::new();
+    let mut overlay_generation = None;
+    let mut frame = Vec::new();
+    let mut buffer = Vec::<u32>::new();
     loop {
         if !window.is_open() {
             return Err(anyhow!(
@@ -37,10 +63,34 @@ async fn render(pixmap: SharedPixmap, mut window: Window) -> anyhow::Result<!> {
             ));
         }
 
-        let buffer = unsafe { mem::transmute::<_, &[u32]>(pixmap.get_color_data()) };
-        window
-            .update_with_buffer(buffer, width, height)
-            .expect("Could not update window data");
+        // the content is scaled to fit the window whenever either of them changes its size
+        if size.has_changed().unwrap_or(false) || window.get_size() != window_size {
+            (width, height) = *size.borrow_and_update();
+            window_size = window.get_size();
+            scaler = Scaler::new(width, height, window_size.0, window_size.1, ScaleFilter::Nearest);
+            cursor = DirtyCursor::default();
+        }
+
+        let mut changed = pixmap.update_color_data(&mut cursor, &mut pixels);
+        let generation = overlay.as_ref().map(|overlay| overlay.generation());
+        changed |= generation != overlay_generation;
+
+        // a resize may not have been announced yet, in which case the data does not fit the scaler
+        // and minimized windows have no size to draw into
+        if changed && pixels.len() == width * height && window_size.0 * window_size.1 > 0 {
+            overlay_generation = generation;
+            let data = match &overlay {
+                Some(overlay) => overlay.composited(&pixels, &mut frame, width, height),
+                None => &pixels,
+            };
+            scaler.scale(data, &mut buffer);
+            window
+                .update_with_buffer(&buffer, window_size.0, window_size.1)
+                .expect("Could not update window data");
+        } else {
+            // events still need to be processed while the content stays the same
+            window.update();
+        }
 
         interval.tick().await;
     }
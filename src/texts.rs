@@ -5,28 +5,124 @@ Available subcommands are:\n\
 HELP\t- This help message\n\
 SIZE\t- Get the current canvas size\n\
 PX\t- Get or set one specific pixels color\n\
+PB\t- Set one specific pixels color using a compact binary encoding\n\
+RECT\t- Fill a rectangle with one color\n\
+GETRECT\t- Get the colors of all pixels in a rectangle\n\
+STATE\t- Get the content of the whole canvas at once\n\
+OFFSET\t- Translate the coordinates of all following PX commands\n\
+FEATURES\t- Get the capabilities of this server\n\
+WHO\t- Get who last wrote a pixel and when\n\
 \n\
 More detailed descriptions about these subcommands is available by sending 'HELP <subcommand>'\n\
 \n\
-All commands end with a newline character (\\n) and need to be sent as ASCII encoded strings.\n\
-Responses are also always newline terminated.\n";
+All commands except PB end with a newline character (\\n) and need to be sent as ASCII encoded strings.\n\
+Responses are also always newline terminated.\n\
+\n\
+Requests that cannot be handled are answered with 'ERR <code> <message>' where <code> is one of\n\
+UNKNOWN_COMMAND, INVALID_COMMAND, OUT_OF_BOUNDS, LINE_TOO_LONG or PROTECTED.\n\
+Writes to regions which the operator reserved are rejected with PROTECTED and change nothing.\n";
 
 pub static HELP_SIZE: &str = "HELP SIZE\n\
 Syntax:\t\tSIZE\n\
 Response:\tSIZE <width> <height>\n\
 \n\
 Returns the current canvas size.\n\
-This server does not support changing the canvas size at runtime so the result can safely be cached\n";
+The canvas may be resized at runtime, keeping the pixels of the region that lies inside both sizes.\n\
+Clients which cache the size should query it again when they receive an OUT_OF_BOUNDS error.\n";
 
 pub static HELP_PX: &str = "HELP PX\n\
-Syntax:\t\tPX <x> <y> [<rgb>]\n\
+Syntax:\t\tPX <x> <y> [<rgb>|<rgba>]\n\
 Response:\t[PX <x> <y> <rgb>]\n\
 \n\
 Gets or sets the pixel color addressed by the coordinates <x> and <y>.\n\
 The mode of operation is determined by the third argument (<rgb>) being present or not.\n\
 If it is present, the pixel will be set to that color and no response will be sent.\n\
 It it is not present, the current color will be returned.\n\
+If the color includes an alpha channel, it is blended over the current pixel color instead.\n\
 \n\
 <x>\t- X position on the canvas counted from the left side\n\
 <y>\t- Y position on the canvas counted from the top\n\
-<rgb>\t- HEX encoded rgb color (000000 - FFFFFF)\n";
+<rgb>\t- HEX encoded rgb color (000000 - FFFFFF), 3 digit short form (000 - FFF) or 2 digit grayscale (00 - FF)\n\
+<rgba>\t- HEX encoded rgb color with alpha channel (00000000 - FFFFFFFF)\n\
+\n\
+Pixels can also be set using the binary PB command which is exactly 10 bytes long and not newline terminated.\n\
+It consists of the ASCII characters 'PB' followed by <x> and <y> as little-endian u16 and 4 bytes of <rgba>.\n";
+
+pub static HELP_OFFSET: &str = "HELP OFFSET\n\
+Syntax:\t\tOFFSET <x> <y>\n\
+Response:\tNone\n\
+\n\
+Sets an offset that is added to the coordinates of all following PX commands on the same connection.\n\
+This allows drawing the same content at different positions without re-encoding all coordinates.\n\
+The offset is reset when the connection is closed and can be removed again by sending 'OFFSET 0 0'.\n\
+For UDP, the offset is only valid for the remainder of the datagram in which it is sent.\n\
+\n\
+<x>\t- Offset that is added to the x position of following commands\n\
+<y>\t- Offset that is added to the y position of following commands\n";
+
+pub static HELP_RECT: &str = "HELP RECT\n\
+Syntax:\t\tRECT <x> <y> <width> <height> <rgb>\n\
+Response:\tNone\n\
+\n\
+Fills the rectangle which starts at the coordinates <x> and <y> with one color.\n\
+Depending on the server configuration, a rectangle that does not lie completely inside the canvas is either\n\
+rejected, cut off at the edges or wrapped around to the opposite side.\n\
+Like with PX, the color may include an alpha channel in which case it is blended over the current pixel colors.\n\
+\n\
+<x>\t- X position of the top left corner counted from the left side\n\
+<y>\t- Y position of the top left corner counted from the top\n\
+<width>\t- Width of the rectangle\n\
+<height>\t- Height of the rectangle\n\
+<rgb>\t- HEX encoded rgb or rgba color in any notation that PX supports\n";
+
+pub static HELP_STATE: &str = "HELP STATE\n\
+Syntax:\t\tSTATE [<encoding>]\n\
+Response:\tSTATE <encoding> <data>\n\
+\n\
+Returns the color of all pixels on the canvas, row by row starting at the top left.\n\
+Each pixel is represented by 3 bytes of red, green and blue data.\n\
+This command is not available over UDP because the response does not fit into a datagram.\n\
+\n\
+<encoding>\t- How the data is encoded. Defaults to rgb if not given.\n\
+\t\t  rgb:   The response line is 'STATE rgb <length>' which is followed by <length> bytes of binary data\n\
+\t\t  rgb64: The data is base64 encoded and sent on the same line as 'STATE rgb64 <base64>'\n";
+
+pub static HELP_GETRECT: &str = "HELP GETRECT\n\
+Syntax:\t\tGETRECT <x> <y> <width> <height>\n\
+Response:\tRECT <x> <y> <width> <height> <data>\n\
+\n\
+Returns the colors of all pixels in the rectangle which starts at the coordinates <x> and <y>.\n\
+The rectangle must lie completely inside the canvas unless the server is configured to wrap coordinates around.\n\
+Servers do not answer this command on connectionless transports like UDP because the response is much larger than the request.\n\
+\n\
+<x>\t- X position of the top left corner counted from the left side\n\
+<y>\t- Y position of the top left corner counted from the top\n\
+<width>\t- Width of the rectangle\n\
+<height>\t- Height of the rectangle\n\
+<data>\t- HEX encoded rgb colors of all pixels, row by row, with exactly 6 digits per pixel\n";
+
+pub static HELP_FEATURES: &str = "HELP FEATURES\n\
+Syntax:\t\tFEATURES\n\
+Response:\tFEATURES commands=<list> colors=<list> [maxline=<n>] [ratelimit=<n>] transports=<list>\n\
+\n\
+Returns the capabilities of this server so that clients can choose the most efficient way of communicating.\n\
+CAPS is accepted as an alias of this command.\n\
+Lists are separated by commas and clients should ignore any keys and values they do not know.\n\
+\n\
+commands\t- Optional commands that are supported in addition to HELP, SIZE and PX\n\
+colors\t\t- Color notations that are accepted by PX and RECT\n\
+maxline\t\t- Maximum length of a single request line in bytes\n\
+ratelimit\t- Maximum number of requests per second that are accepted from one client\n\
+transports\t- Transports over which this server can be reached\n";
+
+pub static HELP_WHO: &str = "HELP WHO\n\
+Syntax:\t\tWHO <x> <y>\n\
+Response:\tWHO <x> <y> <identity> <time> | WHO <x> <y> -\n\
+\n\
+Returns who last wrote the pixel addressed by the coordinates <x> and <y> and when that happened.\n\
+Like PX, the coordinates are translated by the offset of the connection.\n\
+If the pixel was not written since the server started tracking writers, '-' is returned instead.\n\
+Servers only answer this command if they track writers and only on listeners which are reserved for operators.\n\
+\n\
+<identity>\t- Who wrote the pixel, usually the IP address of the client or 'unknown'\n\
+<time>\t\t- When the pixel was written in seconds since the unix epoch\n";